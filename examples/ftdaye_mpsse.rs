@@ -14,18 +14,19 @@ use std::time::Duration;
 fn main() {
     pretty_env_logger::init();
 
-    let device_info = nusb::list_devices()
+    let probe = ftdaye::list_probes()
         .unwrap()
-        .find(|dev| dev.vendor_id() == 0x0403 && dev.product_id() == 0x6010)
+        .into_iter()
+        .find(|probe| probe.interfaces.contains(&Interface::B))
         .expect("device not connected");
 
-    debug!("device_info {:?}", device_info);
+    debug!("probe {:?}", probe);
 
     let device = ftdaye::ftdaye::Builder::new()
         .with_interface(Interface::B)
         .with_read_timeout(Duration::from_secs(5))
         .with_write_timeout(Duration::from_secs(5))
-        .usb_open(device_info)
+        .usb_open(&probe)
         .unwrap();
 
     let mut ft = FtdiMpsse::new(device, 1000);
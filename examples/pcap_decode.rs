@@ -0,0 +1,82 @@
+//! Decodes the MPSSE commands in usbmon captures of FTDI sessions, with the JTAG state
+//! transitions they cause.
+//!
+//! Capture with Wireshark or `tcpdump -i usbmon1 -w openocd.pcapng` while running e.g.
+//! `openocd -f openocd_idcode.cfg`, then
+//! `cargo run --example pcap_decode -- openocd.pcapng [ftdaye.pcapng] [--interface B]
+//! [--device BUS:DEV]`. Given two captures, their listings are printed side by side.
+
+use std::process::exit;
+
+use ftdaye::ftdaye::{
+    capture::{jtag_trace, open_capture, CaptureFilter},
+    Interface,
+};
+
+fn usage() -> ! {
+    eprintln!("usage: pcap_decode <capture> [<capture>] [--interface A|B|C|D] [--device BUS:DEV]");
+    exit(2);
+}
+
+fn main() {
+    pretty_env_logger::init();
+
+    let mut paths = vec![];
+    let mut filter = CaptureFilter::new(Interface::A);
+    let mut device = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interface" => {
+                let interface = match args.next().as_deref() {
+                    Some("A") => Interface::A,
+                    Some("B") => Interface::B,
+                    Some("C") => Interface::C,
+                    Some("D") => Interface::D,
+                    _ => usage(),
+                };
+                filter = CaptureFilter::new(interface);
+            }
+            "--device" => {
+                let arg = args.next().unwrap_or_else(|| usage());
+                let (bus, dev) = arg.split_once(':').unwrap_or_else(|| usage());
+                device = Some((
+                    bus.parse().unwrap_or_else(|_| usage()),
+                    dev.parse().unwrap_or_else(|_| usage()),
+                ));
+            }
+            path if !path.starts_with('-') && paths.len() < 2 => paths.push(arg),
+            _ => usage(),
+        }
+    }
+    if paths.is_empty() {
+        usage();
+    }
+    if let Some((bus, dev)) = device {
+        filter = filter.with_device(bus, dev);
+    }
+
+    let listings: Vec<Vec<String>> = paths
+        .iter()
+        .map(|path| {
+            let events = open_capture(path, filter).unwrap_or_else(|e| {
+                eprintln!("{path}: {e}");
+                exit(1);
+            });
+            jtag_trace(&events).iter().map(|l| l.to_string()).collect()
+        })
+        .collect();
+
+    match &listings[..] {
+        [listing] => listing.iter().for_each(|line| println!("{line}")),
+        [left, right] => {
+            let width = left.iter().map(|l| l.len()).max().unwrap_or(0);
+            for i in 0..left.len().max(right.len()) {
+                let l = left.get(i).map_or("", |l| l.as_str());
+                let r = right.get(i).map_or("", |r| r.as_str());
+                println!("{l:<width$} | {r}");
+            }
+        }
+        _ => unreachable!(),
+    }
+}
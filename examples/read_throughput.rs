@@ -0,0 +1,59 @@
+//! Measures how queued bulk IN transfers affect the throughput of large reads.
+//!
+//! Runs against the MPSSE emulator with a fixed latency per bulk IN transfer, so no hardware
+//! is needed: `cargo run --release --example read_throughput`
+
+use ftdaye::{
+    emulator::{EmulatedTransport, Target},
+    ftdaye::{mpsse::cmd_read_imm, BitMode, Builder},
+};
+
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+const READ_SIZE: usize = 1 << 20;
+
+/// Presents a pseudo-random bit stream on TDO.
+struct Lfsr(u16);
+
+impl Target for Lfsr {
+    fn clock(&mut self, _tms: bool, _tdi: bool) -> bool {
+        let bit = (self.0 ^ (self.0 >> 2) ^ (self.0 >> 3) ^ (self.0 >> 5)) & 1;
+        self.0 = (self.0 >> 1) | (bit << 15);
+        bit != 0
+    }
+}
+
+fn main() {
+    pretty_env_logger::init();
+
+    let mut reference = None;
+    for depth in [1, 2, 4, 8, 16] {
+        let transport =
+            EmulatedTransport::new(Lfsr(0xace1)).with_transfer_latency(Duration::from_micros(250));
+        let mut device = Builder::new()
+            .with_read_queue_depth(depth)
+            .transport_open(transport)
+            .unwrap();
+        device.set_bitmode(0, BitMode::Mpsse).unwrap();
+
+        for _ in 0..READ_SIZE / 65536 {
+            device.write_all(&cmd_read_imm(65536).unwrap()).unwrap();
+        }
+
+        let mut data = vec![0; READ_SIZE];
+        let start = Instant::now();
+        device.read_exact(&mut data).unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(
+            reference.get_or_insert_with(|| data.clone()),
+            &data,
+            "data differs between queue depths"
+        );
+        println!(
+            "depth {depth:2}: {:7.2} MiB/s",
+            READ_SIZE as f64 / (1 << 20) as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
@@ -207,6 +207,11 @@ impl Command {
         }
     }
 
+    /// Returns `true` if this command outputs no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Appends the command to the given buffer.
     pub fn encode(&self, out: &mut Vec<u8>) {
         match self {
@@ -0,0 +1,246 @@
+use super::Target;
+
+// SCL is TCK/SK, SDA is wired to both TDI/DO and TDO/DI.
+const SCL_PIN: u8 = 1 << 0;
+const SDA_PIN: u8 = 1 << 1;
+
+/// What the next received byte is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Address,
+    /// The low byte of a 10-bit address.
+    AddressLow,
+    /// The register pointer of a write.
+    Pointer,
+    Data,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Not addressed, waiting for a start condition.
+    Idle,
+    /// Shifting in a byte, MSB first.
+    Receiving { bits: u8, byte: u8 },
+    /// Pulling SDA low for the acknowledge clock of a received byte.
+    Acking,
+    /// Shifting out a byte, the next bit is on SDA.
+    Sending { bits: u8, byte: u8 },
+    /// Waiting for the master to acknowledge a sent byte.
+    AwaitingAck,
+}
+
+/// A simulated I2C slave with a register file, like an EEPROM or a sensor.
+///
+/// A write sets the register pointer with its first byte and stores the following ones, a read
+/// returns the registers from the pointer on. Both 7-bit and 10-bit addressing are supported.
+/// Writes past the end of the registers are not acknowledged.
+///
+/// The slave samples SDA on the clocks of MPSSE data commands; SCL edges from setting the GPIO
+/// pins only serve to detect start and stop conditions.
+#[derive(Clone, Debug)]
+pub struct I2cSlave {
+    address: u16,
+    ten_bit: bool,
+    registers: Vec<u8>,
+    pointer: usize,
+
+    state: State,
+    phase: Phase,
+    /// Whether the master addressed the slave for reading.
+    read: bool,
+    /// Whether the first two bytes of a 10-bit write address matched, so that a repeated start
+    /// with just the first byte can turn it into a read.
+    ten_bit_selected: bool,
+    sda_low: bool,
+    scl: bool,
+    sda: bool,
+    host_sda_low: bool,
+    starts: usize,
+    stops: usize,
+}
+
+impl I2cSlave {
+    /// Creates a slave at 7-bit `address` with 256 registers, all zero.
+    pub fn new(address: u8) -> Self {
+        assert!(address < 0x80, "7-bit address {address:#x} out of range");
+        Self::with_address(address as u16, false)
+    }
+
+    /// Creates a slave at 10-bit `address` with 256 registers, all zero.
+    pub fn new_ten_bit(address: u16) -> Self {
+        assert!(address < 0x400, "10-bit address {address:#x} out of range");
+        Self::with_address(address, true)
+    }
+
+    fn with_address(address: u16, ten_bit: bool) -> Self {
+        Self {
+            address,
+            ten_bit,
+            registers: vec![0; 256],
+            pointer: 0,
+            state: State::Idle,
+            phase: Phase::Address,
+            read: false,
+            ten_bit_selected: false,
+            sda_low: false,
+            scl: true,
+            sda: true,
+            host_sda_low: false,
+            starts: 0,
+            stops: 0,
+        }
+    }
+
+    /// Replaces the registers, which also sets how many there are.
+    pub fn with_registers(mut self, registers: Vec<u8>) -> Self {
+        self.registers = registers;
+        self
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Returns whether the slave is pulling SDA low.
+    pub fn holds_sda(&self) -> bool {
+        self.sda_low
+    }
+
+    /// Returns the number of start (including repeated start) and stop conditions seen.
+    pub fn conditions(&self) -> (usize, usize) {
+        (self.starts, self.stops)
+    }
+
+    fn sda_line(&self) -> bool {
+        !(self.host_sda_low || self.sda_low)
+    }
+
+    /// Handles a received byte, returning whether to acknowledge it.
+    fn receive(&mut self, byte: u8) -> bool {
+        let read = byte & 1 != 0;
+        match self.phase {
+            Phase::Address if self.ten_bit => {
+                // 11110 followed by the two high address bits.
+                if byte >> 3 != 0b11110 || (byte >> 1 & 0b11) as u16 != self.address >> 8 {
+                    self.ten_bit_selected = false;
+                    return false;
+                }
+                if read {
+                    self.phase = Phase::Data;
+                    self.read = true;
+                    self.ten_bit_selected
+                } else {
+                    self.phase = Phase::AddressLow;
+                    true
+                }
+            }
+            Phase::Address => {
+                if (byte >> 1) as u16 != self.address {
+                    return false;
+                }
+                self.phase = if read { Phase::Data } else { Phase::Pointer };
+                self.read = read;
+                true
+            }
+            Phase::AddressLow => {
+                self.ten_bit_selected = byte == self.address as u8;
+                self.phase = Phase::Pointer;
+                self.ten_bit_selected
+            }
+            Phase::Pointer => {
+                self.pointer = byte as usize;
+                self.phase = Phase::Data;
+                true
+            }
+            Phase::Data => match self.registers.get_mut(self.pointer) {
+                Some(register) => {
+                    *register = byte;
+                    self.pointer += 1;
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Starts sending the register at the pointer, 0xFF past the end.
+    fn send_next(&mut self) {
+        let byte = self.registers.get(self.pointer).copied().unwrap_or(0xff);
+        self.pointer += 1;
+        self.sda_low = byte & 0x80 == 0;
+        self.state = State::Sending { bits: 1, byte };
+    }
+}
+
+impl Target for I2cSlave {
+    fn clock(&mut self, _tms: bool, _tdi: bool) -> bool {
+        // Rising edge: sample SDA.
+        let sda = self.sda_line();
+
+        // Falling edge: update what the slave drives.
+        self.state = match self.state {
+            State::Idle => State::Idle,
+            State::Receiving { bits, byte } => {
+                let byte = byte << 1 | sda as u8;
+                match bits + 1 {
+                    8 if self.receive(byte) => {
+                        self.sda_low = true;
+                        State::Acking
+                    }
+                    8 => State::Idle,
+                    bits => State::Receiving { bits, byte },
+                }
+            }
+            State::Acking => {
+                self.sda_low = false;
+                if self.read {
+                    self.send_next();
+                    self.state
+                } else {
+                    State::Receiving { bits: 0, byte: 0 }
+                }
+            }
+            State::Sending { bits: 8, .. } => {
+                self.sda_low = false;
+                State::AwaitingAck
+            }
+            State::Sending { bits, byte } => {
+                self.sda_low = byte & (0x80 >> bits) == 0;
+                State::Sending {
+                    bits: bits + 1,
+                    byte,
+                }
+            }
+            // A NACK ends the read.
+            State::AwaitingAck if sda => State::Idle,
+            State::AwaitingAck => {
+                self.send_next();
+                self.state
+            }
+        };
+
+        sda
+    }
+
+    fn set_pins(&mut self, drive_low: u8, _drive_high: u8) {
+        self.host_sda_low = drive_low & SDA_PIN != 0;
+        let scl = drive_low & SCL_PIN == 0;
+        let sda = self.sda_line();
+
+        if self.scl && scl && self.sda != sda {
+            if sda {
+                self.stops += 1;
+                self.state = State::Idle;
+                self.ten_bit_selected = false;
+            } else {
+                self.starts += 1;
+                self.state = State::Receiving { bits: 0, byte: 0 };
+                self.read = false;
+            }
+            self.phase = Phase::Address;
+            self.sda_low = false;
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+}
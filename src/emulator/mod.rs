@@ -0,0 +1,665 @@
+//! A software model of the FTDI MPSSE engine.
+//!
+//! [`EmulatedTransport`] plugs into [`Builder::transport_open`](crate::ftdaye::Builder) in place
+//! of a real USB device. It decodes the byte stream `Device::write` sends as MPSSE opcodes, as
+//! described in AN108, clocks the TCK/TMS/TDI activity into a [`Target`], and queues the bytes
+//! the chip would return. With a [`TapChain`] as the target, the whole JTAG stack can be tested
+//! deterministically without hardware, and with an [`I2cSlave`] the I2C master.
+
+mod i2c;
+mod tap;
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use log::{debug, trace, warn};
+
+use crate::ftdaye::transport::{
+    fill_packets, DeviceDescriptor, TransferSink, Transport, TransportFuture,
+};
+use crate::ftdaye::BitMode;
+
+pub use i2c::I2cSlave;
+pub use tap::{Tap, TapChain};
+
+/// Something connected to the JTAG pins of the emulated MPSSE engine.
+pub trait Target: Send {
+    /// Clocks one TCK cycle with the given TMS and TDI levels.
+    ///
+    /// Returns the TDO level presented during the cycle, which is what the MPSSE engine samples.
+    fn clock(&mut self, tms: bool, tdi: bool) -> bool;
+
+    /// Called whenever the low byte pins change, with the pins the chip drives low and those it
+    /// drives high. Other pins are released: inputs, and outputs set high in drive-zero mode.
+    ///
+    /// JTAG targets only care about the clocked levels, so the default ignores it.
+    fn set_pins(&mut self, drive_low: u8, drive_high: u8) {
+        let _ = (drive_low, drive_high);
+    }
+}
+
+// Bits of the low GPIO byte with a fixed function in MPSSE mode.
+const TDI_PIN: u8 = 1 << 1;
+const TMS_PIN: u8 = 1 << 3;
+
+/// The state of the emulated MPSSE engine.
+#[derive(Debug)]
+pub struct MpsseEmulator<T> {
+    target: T,
+    mode: BitMode,
+
+    /// Bytes received from the host which don't form a complete command yet.
+    pending: Vec<u8>,
+    /// Bytes to be returned to the host.
+    output: VecDeque<u8>,
+
+    gpio_level: u16,
+    gpio_direction: u16,
+    /// Outputs that are tristated instead of driven high.
+    drive_zero: u16,
+    loopback: bool,
+    divide_by_5: bool,
+    clock_divisor: u16,
+
+    cycles: u64,
+}
+
+impl<T: Target> MpsseEmulator<T> {
+    fn new(target: T) -> Self {
+        Self {
+            target,
+            mode: BitMode::Reset,
+            pending: vec![],
+            output: VecDeque::new(),
+            gpio_level: 0,
+            gpio_direction: 0,
+            drive_zero: 0,
+            loopback: false,
+            divide_by_5: true,
+            clock_divisor: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Returns the device connected to the JTAG pins.
+    pub fn target(&self) -> &T {
+        &self.target
+    }
+
+    /// Returns the device connected to the JTAG pins.
+    pub fn target_mut(&mut self) -> &mut T {
+        &mut self.target
+    }
+
+    /// Returns the number of TCK cycles clocked so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Returns the current GPIO output levels and directions, high byte first.
+    pub fn gpio(&self) -> (u16, u16) {
+        (self.gpio_level, self.gpio_direction)
+    }
+
+    /// Returns the TCK frequency the host configured, in kHz.
+    pub fn tck_khz(&self) -> u32 {
+        let base = if self.divide_by_5 { 6_000 } else { 30_000 };
+        base / (self.clock_divisor as u32 + 1)
+    }
+
+    fn control(&mut self, request: u8, value: u16) {
+        const SIO_RESET_REQUEST: u8 = 0;
+        const SIO_SET_BITMODE_REQUEST: u8 = 0x0B;
+
+        match (request, value) {
+            // Reset
+            (SIO_RESET_REQUEST, 0) => {
+                self.pending.clear();
+                self.output.clear();
+            }
+            // Purge RX: the host side buffer, i.e. our output
+            (SIO_RESET_REQUEST, 1) => self.output.clear(),
+            // Purge TX: commands not executed yet
+            (SIO_RESET_REQUEST, 2) => self.pending.clear(),
+            (SIO_SET_BITMODE_REQUEST, _) => {
+                let [_mask, mode] = value.to_le_bytes();
+                self.mode = match mode {
+                    0 => BitMode::Reset,
+                    2 => BitMode::Mpsse,
+                    other => {
+                        warn!("emulator: unsupported bit mode {:#04x}", other);
+                        BitMode::Reset
+                    }
+                };
+                if self.mode == BitMode::Mpsse {
+                    self.drive_zero = 0;
+                    self.loopback = false;
+                    self.divide_by_5 = true;
+                }
+            }
+            _ => trace!("emulator: ignoring request {:02X}/{:04X}", request, value),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        if self.mode != BitMode::Mpsse {
+            trace!("emulator: dropping {} bytes outside MPSSE mode", data.len());
+            return;
+        }
+
+        self.pending.extend_from_slice(data);
+
+        let mut consumed = 0;
+        while consumed < self.pending.len() {
+            let Some(len) = command_len(&self.pending[consumed..]) else {
+                // Wait for the rest of the command.
+                break;
+            };
+            let command = self.pending[consumed..consumed + len].to_vec();
+            self.execute(&command);
+            consumed += len;
+        }
+        self.pending.drain(..consumed);
+    }
+
+    fn tms(&self) -> bool {
+        self.gpio_level as u8 & TMS_PIN != 0
+    }
+
+    fn tdi(&self) -> bool {
+        self.gpio_level as u8 & TDI_PIN != 0
+    }
+
+    fn set_pin(&mut self, pin: u8, level: bool) {
+        if level {
+            self.gpio_level |= pin as u16;
+        } else {
+            self.gpio_level &= !(pin as u16);
+        }
+    }
+
+    /// Tells the target which pins are driven.
+    fn update_pins(&mut self) {
+        let driven = self.gpio_direction;
+        let drive_low = driven & !self.gpio_level;
+        let drive_high = driven & self.gpio_level & !self.drive_zero;
+        self.target.set_pins(drive_low as u8, drive_high as u8);
+    }
+
+    fn clock(&mut self, tms: bool, tdi: bool) -> bool {
+        self.set_pin(TMS_PIN, tms);
+        self.set_pin(TDI_PIN, tdi);
+        self.update_pins();
+        self.cycles += 1;
+
+        let tdo = self.target.clock(tms, tdi);
+        if self.loopback {
+            tdi
+        } else {
+            tdo
+        }
+    }
+
+    /// Clocks `count` bits of `data` out on TDI (or all-current-TDI if `data` is `None`).
+    ///
+    /// Returns the sampled TDO bits, in the order they were clocked.
+    fn shift_bits(&mut self, data: Option<u8>, count: usize, lsb_first: bool) -> Vec<bool> {
+        let tms = self.tms();
+        (0..count)
+            .map(|i| {
+                let tdi = match data {
+                    Some(byte) if lsb_first => byte & (1 << i) != 0,
+                    Some(byte) => byte & (0x80 >> i) != 0,
+                    None => self.tdi(),
+                };
+                self.clock(tms, tdi)
+            })
+            .collect()
+    }
+
+    fn execute(&mut self, command: &[u8]) {
+        let opcode = command[0];
+        trace!("emulator: executing {:02X?}", command);
+
+        if is_shift_opcode(opcode) {
+            self.execute_shift(command);
+            return;
+        }
+
+        match opcode {
+            0x80 => {
+                self.gpio_level = (self.gpio_level & 0xff00) | command[1] as u16;
+                self.gpio_direction = (self.gpio_direction & 0xff00) | command[2] as u16;
+                self.update_pins();
+            }
+            0x82 => {
+                self.gpio_level = (self.gpio_level & 0x00ff) | (command[1] as u16) << 8;
+                self.gpio_direction = (self.gpio_direction & 0x00ff) | (command[2] as u16) << 8;
+            }
+            // Input pins read back as pulled up.
+            0x81 => self
+                .output
+                .push_back((self.gpio_level | !self.gpio_direction) as u8),
+            0x83 => self
+                .output
+                .push_back(((self.gpio_level | !self.gpio_direction) >> 8) as u8),
+            0x84 => self.loopback = true,
+            0x85 => self.loopback = false,
+            0x86 => self.clock_divisor = u16::from_le_bytes([command[1], command[2]]),
+            0x8A => self.divide_by_5 = false,
+            0x8B => self.divide_by_5 = true,
+            0x8E => {
+                self.shift_bits(None, command[1] as usize + 1, true);
+            }
+            0x8F => {
+                let bytes = u16::from_le_bytes([command[1], command[2]]) as usize + 1;
+                self.shift_bits(None, bytes * 8, true);
+            }
+            // GPIOL1 is not modelled, so waiting on it completes immediately.
+            0x88 | 0x89 | 0x94 | 0x95 | 0x9C | 0x9D => {}
+            0x9E => {
+                self.drive_zero = u16::from_le_bytes([command[1], command[2]]);
+                self.update_pins();
+            }
+            // Send immediate, three-phase and adaptive clocking don't change what the target
+            // sees.
+            0x87 | 0x8C | 0x8D | 0x96 | 0x97 => {}
+            bad => {
+                debug!("emulator: bad command {:#04x}", bad);
+                self.output.extend([0xFA, bad]);
+            }
+        }
+    }
+
+    fn execute_shift(&mut self, command: &[u8]) {
+        let opcode = command[0];
+        let bit_mode = opcode & 0x02 != 0;
+        let lsb_first = opcode & 0x08 != 0;
+        let write_tdi = opcode & 0x10 != 0;
+        let read_tdo = opcode & 0x20 != 0;
+        let write_tms = opcode & 0x40 != 0;
+
+        if write_tms {
+            let count = command[1] as usize + 1;
+            let byte = command[2];
+            let tdi = byte & 0x80 != 0;
+
+            let mut captured = 0u8;
+            for i in 0..count {
+                let tdo = self.clock(byte & (1 << i) != 0, tdi);
+                captured = (captured >> 1) | (tdo as u8) << 7;
+            }
+            if read_tdo {
+                self.output.push_back(captured);
+            }
+            return;
+        }
+
+        if bit_mode {
+            let count = command[1] as usize + 1;
+            let data = write_tdi.then(|| command[2]);
+            let bits = self.shift_bits(data, count, lsb_first);
+
+            if read_tdo {
+                // The bits enter the shift register from the end opposite to the one they leave.
+                let captured = bits.into_iter().fold(0u8, |acc, bit| {
+                    if lsb_first {
+                        (acc >> 1) | (bit as u8) << 7
+                    } else {
+                        (acc << 1) | bit as u8
+                    }
+                });
+                self.output.push_back(captured);
+            }
+            return;
+        }
+
+        let count = u16::from_le_bytes([command[1], command[2]]) as usize + 1;
+        for i in 0..count {
+            let data = write_tdi.then(|| command[3 + i]);
+            let bits = self.shift_bits(data, 8, lsb_first);
+
+            if read_tdo {
+                let captured = bits.into_iter().enumerate().fold(0u8, |acc, (i, bit)| {
+                    let shift = if lsb_first { i } else { 7 - i };
+                    acc | (bit as u8) << shift
+                });
+                self.output.push_back(captured);
+            }
+        }
+    }
+}
+
+/// Returns `true` for the data shifting opcodes listed in AN108 section 3.
+fn is_shift_opcode(opcode: u8) -> bool {
+    let neg_write = opcode & 0x01 != 0;
+    let neg_read = opcode & 0x04 != 0;
+
+    match opcode & 0xF0 {
+        // Write only: the read edge bit must be clear
+        0x10 => !neg_read,
+        // Read only: the write edge bit must be clear
+        0x20 => !neg_write,
+        // Read and write on opposite edges
+        0x30 => neg_write != neg_read,
+        // TMS, with or without read
+        0x40 | 0x60 => matches!(opcode, 0x4A | 0x4B | 0x6A | 0x6B | 0x6E | 0x6F),
+        _ => false,
+    }
+}
+
+/// Returns the length of the command at the start of `data`, or `None` if it is incomplete.
+fn command_len(data: &[u8]) -> Option<usize> {
+    let opcode = *data.first()?;
+
+    let len = if is_shift_opcode(opcode) {
+        let bit_mode = opcode & 0x02 != 0;
+        let write = opcode & 0x50 != 0;
+
+        match (bit_mode, write) {
+            (true, true) => 3,
+            (true, false) => 2,
+            (false, false) => 3,
+            (false, true) => {
+                let count = u16::from_le_bytes([*data.get(1)?, *data.get(2)?]) as usize + 1;
+                3 + count
+            }
+        }
+    } else {
+        match opcode {
+            0x80 | 0x82 | 0x86 | 0x8F | 0x9C | 0x9D | 0x9E => 3,
+            0x8E => 2,
+            _ => 1,
+        }
+    };
+
+    (data.len() >= len).then_some(len)
+}
+
+/// A [`Transport`] backed by an [`MpsseEmulator`] instead of a USB device.
+///
+/// Like [`MemoryTransport`](crate::ftdaye::transport::MemoryTransport), this is a cheap handle:
+/// keep a clone to inspect the emulator after moving the transport into a device.
+///
+/// Bulk IN transfers complete instantly unless a [transfer
+/// latency](EmulatedTransport::with_transfer_latency) is set, which models the round trip to
+/// the host controller and makes the effect of queued reads measurable.
+pub struct EmulatedTransport<T> {
+    descriptor: DeviceDescriptor,
+    max_packet_size: usize,
+    transfer_latency: Duration,
+    emulator: Arc<Mutex<MpsseEmulator<T>>>,
+}
+
+impl<T> Clone for EmulatedTransport<T> {
+    fn clone(&self) -> Self {
+        Self {
+            descriptor: self.descriptor.clone(),
+            max_packet_size: self.max_packet_size,
+            transfer_latency: self.transfer_latency,
+            emulator: self.emulator.clone(),
+        }
+    }
+}
+
+impl<T: Target> EmulatedTransport<T> {
+    /// Creates an emulated FT2232H with `target` connected to its JTAG pins.
+    pub fn new(target: T) -> Self {
+        Self {
+            descriptor: DeviceDescriptor {
+                vendor_id: 0x0403,
+                product_id: 0x6010,
+                device_version: 0x700,
+                serial_number: None,
+                product_string: None,
+            },
+            max_packet_size: 512,
+            transfer_latency: Duration::ZERO,
+            emulator: Arc::new(Mutex::new(MpsseEmulator::new(target))),
+        }
+    }
+
+    /// Overrides the USB descriptor the emulated device presents.
+    pub fn with_descriptor(mut self, descriptor: DeviceDescriptor) -> Self {
+        self.descriptor = descriptor;
+        self
+    }
+
+    /// Delays the completion of bulk IN transfers by `latency`.
+    ///
+    /// Transfers queued with [`Transport::read_bulk_queued`] are serviced back to back, so each
+    /// batch of up to `depth` transfers only pays the latency once.
+    pub fn with_transfer_latency(mut self, latency: Duration) -> Self {
+        self.transfer_latency = latency;
+        self
+    }
+
+    /// Gives access to the emulator state.
+    pub fn emulator(&self) -> MutexGuard<'_, MpsseEmulator<T>> {
+        self.emulator.lock().unwrap()
+    }
+}
+
+impl<T: Target> Transport for EmulatedTransport<T> {
+    fn descriptor(&self) -> &DeviceDescriptor {
+        &self.descriptor
+    }
+
+    fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    fn control_out(
+        &mut self,
+        request: u8,
+        value: u16,
+        _index: u16,
+        _timeout: Duration,
+    ) -> TransportFuture<'_, ()> {
+        self.emulator().control(request, value);
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn control_in<'a>(
+        &'a mut self,
+        request: u8,
+        _value: u16,
+        _index: u16,
+        _buf: &'a mut [u8],
+        _timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        Box::pin(std::future::ready(Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("control request {request:#04x} is not emulated"),
+        ))))
+    }
+
+    fn read_bulk<'a>(
+        &'a mut self,
+        _endpoint: u8,
+        buf: &'a mut [u8],
+        _timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        Box::pin(async move {
+            self.wait_latency().await;
+            Ok(self.fill_transfer(buf))
+        })
+    }
+
+    fn write_bulk<'a>(
+        &'a mut self,
+        _endpoint: u8,
+        buf: &'a [u8],
+        _timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        self.emulator().write(buf);
+        Box::pin(std::future::ready(Ok(buf.len())))
+    }
+
+    fn read_bulk_queued<'a>(
+        &'a mut self,
+        _endpoint: u8,
+        transfer_size: usize,
+        depth: usize,
+        _timeout: Duration,
+        sink: &'a mut TransferSink<'_>,
+    ) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            let mut buf = vec![0; transfer_size];
+            loop {
+                self.wait_latency().await;
+                for _ in 0..depth.max(1) {
+                    let read = self.fill_transfer(&mut buf);
+                    if !sink(&buf[..read]) {
+                        return Ok(());
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl<T: Target> EmulatedTransport<T> {
+    async fn wait_latency(&self) {
+        if !self.transfer_latency.is_zero() {
+            async_io::Timer::after(self.transfer_latency).await;
+        }
+    }
+
+    fn fill_transfer(&self, buf: &mut [u8]) -> usize {
+        fill_packets(
+            buf,
+            self.max_packet_size,
+            [0x32, 0x60],
+            &mut self.emulator().output,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::jtag::{FtdiMpsse, TapState};
+    use crate::ftdaye::Builder;
+    use crate::xilinx7::{IR_IDCODE, IR_USER3};
+    use crate::{JtagAdapter, FTDI_COMPAT_DEVICES};
+    use bitvec::prelude::*;
+    use std::io::{Read, Write};
+
+    fn artix7() -> Tap {
+        Tap::new(6)
+            .with_idcode(IR_IDCODE as u64, 0x0362d093)
+            .with_register(
+                IR_USER3 as u64,
+                0xdeadbeef_u32
+                    .view_bits::<Lsb0>()
+                    .iter()
+                    .by_vals()
+                    .collect(),
+            )
+    }
+
+    fn shift(adapter: &mut JtagAdapter, tms: &[bool], tdi: &[bool], capture: bool) {
+        for (tms, tdi) in tms.iter().zip(tdi) {
+            adapter.shift_bit(*tms, *tdi, capture).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_mpsse_read_idcode() {
+        let transport = EmulatedTransport::new(artix7());
+        let device = Builder::new().transport_open(transport.clone()).unwrap();
+        let mut ft = FtdiMpsse::new(device, 1000);
+
+        ft.reset_and_to_rti();
+        let mut data = [0; 4];
+        ft.read_register(IR_IDCODE, &mut data);
+
+        assert_eq!(u32::from_le_bytes(data), 0x0362d093);
+        assert_eq!(transport.emulator().target().state(), TapState::RunTestIdle);
+        assert_eq!(transport.emulator().tck_khz(), 1000);
+    }
+
+    #[test]
+    fn test_mpsse_async_read_idcode() {
+        fn assert_send<T: Send>(t: T) -> T {
+            t
+        }
+
+        let transport = EmulatedTransport::new(artix7());
+        let device = Builder::new().transport_open(transport.clone()).unwrap();
+
+        let idcode = async_io::block_on(assert_send(async move {
+            let mut ft = FtdiMpsse::new_async(device, 1000).await?;
+            ft.reset_and_to_rti_async().await?;
+
+            let mut data = [0; 4];
+            ft.read_register_async(IR_IDCODE, &mut data).await?;
+            Ok::<_, crate::FtdiError>(u32::from_le_bytes(data))
+        }))
+        .unwrap();
+
+        assert_eq!(idcode, 0x0362d093);
+    }
+
+    #[test]
+    fn test_mpsse_read_write_register() {
+        let transport = EmulatedTransport::new(artix7());
+        let device = Builder::new().transport_open(transport.clone()).unwrap();
+        let mut ft = FtdiMpsse::new(device, 1000);
+
+        ft.reset_and_to_rti();
+        let mut data = [1, 2, 3, 4];
+        ft.read_write_register(IR_USER3, &mut data);
+
+        assert_eq!(u32::from_le_bytes(data), 0xdeadbeef);
+        assert_eq!(transport.emulator().target().instruction(), IR_USER3 as u64);
+    }
+
+    #[test]
+    fn test_jtag_adapter_dr_scan() {
+        let chain = TapChain::new(vec![artix7(), Tap::new(4)]);
+        let transport = EmulatedTransport::new(chain);
+        let device = Builder::new().transport_open(transport.clone()).unwrap();
+        let mut adapter = JtagAdapter::new(FTDI_COMPAT_DEVICES[0], device).unwrap();
+        adapter.attach().unwrap();
+
+        // Reset, then Run-Test/Idle -> Shift-DR
+        shift(&mut adapter, &[true; 5], &[false; 5], false);
+        shift(
+            &mut adapter,
+            &[false, true, false, false],
+            &[false; 4],
+            false,
+        );
+
+        // The second TAP is in BYPASS, the IDCODE follows its single bit.
+        let mut tms = [false; 33];
+        tms[32] = true;
+        shift(&mut adapter, &tms, &[false; 33], true);
+        let bits = adapter.read_captured_bits().unwrap();
+
+        assert_eq!(bits.len(), 33);
+        assert!(!bits[0]);
+        assert_eq!(bits[1..].load_le::<u32>(), 0x0362d093);
+        assert_eq!(
+            transport.emulator().target().taps()[0].state(),
+            TapState::Exit1Dr
+        );
+    }
+
+    #[test]
+    fn test_bad_command_echo() {
+        let transport = EmulatedTransport::new(Tap::new(4));
+        let mut device = Builder::new().transport_open(transport.clone()).unwrap();
+        device.set_bitmode(0, BitMode::Mpsse).unwrap();
+
+        device.write_all(&[0xAB, 0x87]).unwrap();
+        let mut reply = [0; 2];
+        device.read_exact(&mut reply).unwrap();
+
+        assert_eq!(reply, [0xFA, 0xAB]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+
+use bitvec::prelude::*;
+
+use super::Target;
+use crate::ftdaye::jtag::TapState;
+
+/// A data register selected by an instruction.
+#[derive(Clone, Debug)]
+struct Register {
+    instruction: u64,
+    value: BitVec<u8, Lsb0>,
+    writable: bool,
+}
+
+/// A simulated JTAG TAP with a configurable instruction register and data registers.
+///
+/// Instructions without a data register select the 1-bit BYPASS register.
+#[derive(Clone, Debug)]
+pub struct Tap {
+    ir_len: usize,
+    reset_instruction: u64,
+    registers: Vec<Register>,
+
+    state: TapState,
+    ir: u64,
+    ir_shift: u64,
+    dr_shift: VecDeque<bool>,
+}
+
+impl Tap {
+    /// Creates a TAP with an `ir_len` bit instruction register and no data registers.
+    pub fn new(ir_len: usize) -> Self {
+        assert!(
+            (2..=64).contains(&ir_len),
+            "IR length {ir_len} must be in range 2..=64"
+        );
+
+        let bypass = u64::MAX >> (64 - ir_len);
+        Self {
+            ir_len,
+            reset_instruction: bypass,
+            registers: vec![],
+            state: TapState::TestLogicReset,
+            ir: bypass,
+            ir_shift: 0,
+            dr_shift: VecDeque::new(),
+        }
+    }
+
+    /// Adds a read-only 32-bit IDCODE register, which is also selected after reset.
+    pub fn with_idcode(mut self, instruction: u64, idcode: u32) -> Self {
+        self.registers.push(Register {
+            instruction,
+            value: idcode.view_bits::<Lsb0>().iter().by_vals().collect(),
+            writable: false,
+        });
+        self.reset_instruction = instruction;
+        self.ir = instruction;
+        self
+    }
+
+    /// Adds a data register with the given initial value, updated by DR scans.
+    pub fn with_register(mut self, instruction: u64, value: BitVec<u8, Lsb0>) -> Self {
+        self.registers.push(Register {
+            instruction,
+            value,
+            writable: true,
+        });
+        self
+    }
+
+    /// Returns the current value of the data register selected by `instruction`.
+    pub fn register(&self, instruction: u64) -> Option<&BitSlice<u8, Lsb0>> {
+        self.registers
+            .iter()
+            .find(|r| r.instruction == instruction)
+            .map(|r| r.value.as_bitslice())
+    }
+
+    /// Returns the state of the TAP controller.
+    pub fn state(&self) -> TapState {
+        self.state
+    }
+
+    /// Returns the active instruction.
+    pub fn instruction(&self) -> u64 {
+        self.ir
+    }
+
+    fn selected(&mut self) -> Option<&mut Register> {
+        let ir = self.ir;
+        self.registers.iter_mut().find(|r| r.instruction == ir)
+    }
+}
+
+impl Target for Tap {
+    fn clock(&mut self, tms: bool, tdi: bool) -> bool {
+        let tdo = match self.state {
+            TapState::ShiftIr => self.ir_shift & 1 != 0,
+            TapState::ShiftDr => self.dr_shift.front().copied().unwrap_or(false),
+            _ => false,
+        };
+
+        match self.state {
+            TapState::TestLogicReset => self.ir = self.reset_instruction,
+            TapState::CaptureIr => self.ir_shift = 0b01,
+            TapState::ShiftIr => {
+                self.ir_shift = (self.ir_shift >> 1) | (tdi as u64) << (self.ir_len - 1);
+            }
+            TapState::UpdateIr => self.ir = self.ir_shift,
+            TapState::CaptureDr => {
+                self.dr_shift = match self.selected() {
+                    Some(register) => register.value.iter().by_vals().collect(),
+                    // BYPASS captures a zero.
+                    None => VecDeque::from([false]),
+                };
+            }
+            TapState::ShiftDr => {
+                self.dr_shift.pop_front();
+                self.dr_shift.push_back(tdi);
+            }
+            TapState::UpdateDr => {
+                let shifted: BitVec<u8, Lsb0> = self.dr_shift.iter().copied().collect();
+                if let Some(register) = self.selected().filter(|r| r.writable) {
+                    register.value = shifted;
+                }
+            }
+            _ => {}
+        }
+
+        self.state = self.state.next(tms);
+
+        tdo
+    }
+}
+
+/// A JTAG scan chain of [`Tap`]s, listed starting from the one connected to TDI.
+#[derive(Clone, Debug, Default)]
+pub struct TapChain {
+    taps: Vec<Tap>,
+}
+
+impl TapChain {
+    pub fn new(taps: Vec<Tap>) -> Self {
+        Self { taps }
+    }
+
+    pub fn taps(&self) -> &[Tap] {
+        &self.taps
+    }
+}
+
+impl Target for TapChain {
+    fn clock(&mut self, tms: bool, tdi: bool) -> bool {
+        // Every TAP presents its output before shifting in the output of its predecessor.
+        self.taps
+            .iter_mut()
+            .fold(tdi, |bit, tap| tap.clock(tms, bit))
+    }
+}
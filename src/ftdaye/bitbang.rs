@@ -0,0 +1,187 @@
+//! Driving and sampling GPIO pins in bitbang mode.
+
+use async_io::block_on;
+
+use crate::ftdaye::{error::FtdiError, BitMode, Device, Result};
+
+/// Receive buffer size of the smallest chip with synchronous bitbang mode, the FT232R.
+///
+/// Writing more than this before reading the samples back would make the chip stall.
+const SYNC_CHUNK_SIZE: usize = 256;
+
+/// Drives the 8 pins of a channel directly from the bytes written to it.
+///
+/// In [`BitMode::Bitbang`] the pins are updated at the rate set with
+/// [`Bitbang::set_clock_rate`]. In [`BitMode::SyncBb`] the pins are sampled right before each
+/// byte is applied, and every written byte produces one sampled byte to read back.
+#[derive(Debug)]
+pub struct Bitbang {
+    device: Device,
+    mode: BitMode,
+    direction: u8,
+}
+
+impl Bitbang {
+    /// Switches `device` to bitbang `mode`, with the pins set in `direction` as outputs.
+    pub fn new(device: Device, mode: BitMode, direction: u8) -> Result<Self> {
+        block_on(Self::new_async(device, mode, direction))
+    }
+
+    pub async fn new_async(mut device: Device, mode: BitMode, direction: u8) -> Result<Self> {
+        if !matches!(mode, BitMode::Bitbang | BitMode::SyncBb) {
+            Err(FtdiError::Other(format!("{mode:?} is not a bitbang mode")))?
+        }
+
+        device.set_bitmode_async(direction, mode).await?;
+        device.usb_purge_buffers_async().await?;
+
+        Ok(Self {
+            device,
+            mode,
+            direction,
+        })
+    }
+
+    pub fn mode(&self) -> BitMode {
+        self.mode
+    }
+
+    pub fn direction(&self) -> u8 {
+        self.direction
+    }
+
+    /// Changes which pins are outputs.
+    pub fn set_direction(&mut self, direction: u8) -> Result<()> {
+        block_on(self.set_direction_async(direction))
+    }
+
+    pub async fn set_direction_async(&mut self, direction: u8) -> Result<()> {
+        self.device.set_bitmode_async(direction, self.mode).await?;
+        self.direction = direction;
+
+        Ok(())
+    }
+
+    /// Sets the rate at which written bytes are applied to the pins and returns the actual rate.
+    pub fn set_clock_rate(&mut self, rate: u32) -> Result<u32> {
+        block_on(self.set_clock_rate_async(rate))
+    }
+
+    pub async fn set_clock_rate_async(&mut self, rate: u32) -> Result<u32> {
+        self.device.set_baud_rate_async(rate).await
+    }
+
+    /// Applies each byte of `patterns` to the pins in turn.
+    ///
+    /// In synchronous mode the samples are read back and dropped, use [`Self::transfer`] to
+    /// keep them.
+    pub fn write(&mut self, patterns: &[u8]) -> Result<()> {
+        block_on(self.write_async(patterns))
+    }
+
+    pub async fn write_async(&mut self, patterns: &[u8]) -> Result<()> {
+        match self.mode {
+            BitMode::SyncBb => {
+                self.transfer_async(patterns).await?;
+            }
+            _ => self.device.write_all_async(patterns).await?,
+        }
+
+        Ok(())
+    }
+
+    /// Applies each byte of `patterns` to the pins in turn, returning the pins sampled for each
+    /// byte. Only available in synchronous mode.
+    pub fn transfer(&mut self, patterns: &[u8]) -> Result<Vec<u8>> {
+        block_on(self.transfer_async(patterns))
+    }
+
+    pub async fn transfer_async(&mut self, patterns: &[u8]) -> Result<Vec<u8>> {
+        if self.mode != BitMode::SyncBb {
+            Err(FtdiError::Other(
+                "sampling requires synchronous bitbang mode".to_string(),
+            ))?
+        }
+
+        let mut samples = vec![0; patterns.len()];
+        for (chunk, samples) in patterns
+            .chunks(SYNC_CHUNK_SIZE)
+            .zip(samples.chunks_mut(SYNC_CHUNK_SIZE))
+        {
+            self.device.write_all_async(chunk).await?;
+            self.device.read_exact_async(samples).await?;
+        }
+
+        Ok(samples)
+    }
+
+    /// Reads the current state of all pins, outputs included.
+    pub fn read_pins(&mut self) -> Result<u8> {
+        block_on(self.read_pins_async())
+    }
+
+    pub async fn read_pins_async(&mut self) -> Result<u8> {
+        self.device.read_pins_async().await
+    }
+
+    /// Leaves bitbang mode and returns the device.
+    pub fn into_inner(self) -> Result<Device> {
+        block_on(self.into_inner_async())
+    }
+
+    pub async fn into_inner_async(mut self) -> Result<Device> {
+        self.device.set_bitmode_async(0, BitMode::Reset).await?;
+
+        Ok(self.device)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::transport::MemoryTransport;
+
+    #[test]
+    fn test_bitbang() {
+        let transport = MemoryTransport::default().with_max_packet_size(64);
+        let device = transport.open();
+
+        let mut bitbang = Bitbang::new(device, BitMode::SyncBb, 0x0f).unwrap();
+        assert_eq!(bitbang.set_clock_rate(9600).unwrap(), 9600);
+        let requests = transport.take_control_requests();
+        assert_eq!((requests[0].request, requests[0].value), (0x0B, 0x040f));
+        // After purging both buffers, the baud rate is set to 4 times the requested rate.
+        assert_eq!(
+            (requests[3].request, requests[3].value, requests[3].index),
+            (0x03, 0x4138, 0x0201)
+        );
+
+        // Written in chunks, each followed by reading back its samples.
+        let patterns: Vec<u8> = (0..=255).cycle().take(300).collect();
+        let samples: Vec<u8> = patterns.iter().map(|p| p ^ 0xf0).collect();
+        transport.push_response(&samples);
+        assert_eq!(bitbang.transfer(&patterns).unwrap(), samples);
+        assert_eq!(transport.take_written(), patterns);
+        assert_eq!(transport.pending_response(), 0);
+
+        transport.set_pin_state(0xa5);
+        assert_eq!(bitbang.read_pins().unwrap(), 0xa5);
+
+        bitbang.set_direction(0xff).unwrap();
+        let device = bitbang.into_inner().unwrap();
+        assert_eq!(
+            transport
+                .take_control_requests()
+                .iter()
+                .map(|r| (r.request, r.value))
+                .collect::<Vec<_>>(),
+            [(0x0B, 0x04ff), (0x0B, 0)]
+        );
+
+        let mut bitbang = Bitbang::new(device, BitMode::Bitbang, 0xff).unwrap();
+        bitbang.write(&[1, 2, 3]).unwrap();
+        assert_eq!(transport.take_written(), [1, 2, 3]);
+        assert!(bitbang.transfer(&[1]).is_err());
+        assert!(Bitbang::new(bitbang.into_inner().unwrap(), BitMode::Mpsse, 0).is_err());
+    }
+}
@@ -0,0 +1,729 @@
+//! Reading usbmon captures of FTDI sessions, as saved by Wireshark or `tcpdump -i usbmon1` in
+//! pcap or pcapng files.
+//!
+//! The traffic of one interface is extracted as recording [`Event`]s, with the status bytes
+//! stripped from bulk IN data unless a packet reports more than an idle chip, so that e.g. the
+//! commands OpenOCD sends can be compared with ours.
+//! [`jtag_trace`] decodes the MPSSE commands in it along with the TAP state transitions they cause.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use super::disasm::{self, DecodeError};
+use super::jtag::TapState;
+use super::mpsse::MpsseCommand;
+use super::recording::Event;
+use super::Interface;
+
+/// `LINKTYPE_USB_LINUX`, with the 48 byte usbmon header.
+const LINKTYPE_USB_LINUX: u32 = 189;
+/// `LINKTYPE_USB_LINUX_MMAPPED`, with the 64 byte usbmon header.
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+const XFER_CONTROL: u8 = 2;
+const XFER_BULK: u8 = 3;
+
+/// Selects the traffic to extract from a capture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptureFilter {
+    interface: Interface,
+    device: Option<(u16, u8)>,
+    max_packet_size: usize,
+}
+
+impl CaptureFilter {
+    /// Extracts `interface` of the first device in the capture that uses it, assuming
+    /// 512 byte (high speed) packets.
+    pub const fn new(interface: Interface) -> Self {
+        Self {
+            interface,
+            device: None,
+            max_packet_size: 512,
+        }
+    }
+
+    /// Only extracts the device with USB address `device` on `bus`.
+    pub const fn with_device(mut self, bus: u16, device: u8) -> Self {
+        self.device = Some((bus, device));
+        self
+    }
+
+    /// Overrides the bulk packet size (64 bytes for full speed chips).
+    pub const fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+}
+
+/// Reads a capture file, see [`read_capture`].
+pub fn open_capture(
+    path: impl AsRef<Path>,
+    filter: CaptureFilter,
+) -> io::Result<Vec<(Duration, Event)>> {
+    read_capture(BufReader::new(File::open(path)?), filter)
+}
+
+/// Reads a pcap or pcapng capture and returns the traffic of the interface selected by `filter`,
+/// timestamped relative to the first packet of the capture.
+///
+/// Vendor requests addressed to the interface, bulk OUT submissions and bulk IN completions are
+/// extracted. Everything else, including other link types, is skipped.
+pub fn read_capture(
+    mut reader: impl Read,
+    filter: CaptureFilter,
+) -> io::Result<Vec<(Duration, Event)>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+
+    let mut extractor = Extractor {
+        filter,
+        start: None,
+        pending_control_in: vec![],
+        events: vec![],
+    };
+    match data.get(..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => read_pcapng(&data, &mut extractor)?,
+        Some(_) => read_pcap(&data, &mut extractor)?,
+        None => return Err(invalid("not a pcap or pcapng file")),
+    }
+
+    Ok(extractor.events)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads integers in the byte order of the file.
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        match self.big {
+            true => u16::from_be_bytes(b),
+            false => u16::from_le_bytes(b),
+        }
+    }
+
+    fn u32(self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        match self.big {
+            true => u32::from_be_bytes(b),
+            false => u32::from_le_bytes(b),
+        }
+    }
+
+    fn u64(self, b: &[u8]) -> u64 {
+        let b = b[..8].try_into().unwrap();
+        match self.big {
+            true => u64::from_be_bytes(b),
+            false => u64::from_le_bytes(b),
+        }
+    }
+}
+
+fn read_pcap(data: &[u8], extractor: &mut Extractor) -> io::Result<()> {
+    let header = data
+        .get(..24)
+        .ok_or_else(|| invalid("truncated pcap header"))?;
+    let (endian, nanos) = match header[..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (Endian { big: false }, false),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (Endian { big: true }, false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (Endian { big: false }, true),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (Endian { big: true }, true),
+        _ => return Err(invalid("not a pcap or pcapng file")),
+    };
+    let linktype = endian.u32(&header[20..]) & 0xffff;
+
+    let mut rest = &data[24..];
+    while !rest.is_empty() {
+        let record = rest
+            .get(..16)
+            .ok_or_else(|| invalid("truncated pcap record"))?;
+        let seconds = endian.u32(record) as u64;
+        let fraction = endian.u32(&record[4..]) as u64;
+        let captured = endian.u32(&record[8..]) as usize;
+        let packet = rest
+            .get(16..16 + captured)
+            .ok_or_else(|| invalid("truncated pcap record"))?;
+        rest = &rest[16 + captured..];
+
+        let time = match nanos {
+            true => Duration::from_nanos(seconds * 1_000_000_000 + fraction),
+            false => Duration::from_micros(seconds * 1_000_000 + fraction),
+        };
+        extractor.packet(linktype, endian, time, packet);
+    }
+
+    Ok(())
+}
+
+fn read_pcapng(data: &[u8], extractor: &mut Extractor) -> io::Result<()> {
+    const SECTION_HEADER: u32 = 0x0a0d0d0a;
+    const INTERFACE_DESCRIPTION: u32 = 1;
+    const SIMPLE_PACKET: u32 = 3;
+    const ENHANCED_PACKET: u32 = 6;
+    const IF_TSRESOL: u16 = 9;
+
+    let mut endian = Endian { big: false };
+    // Link type and timestamp units per second of each interface of the current section.
+    let mut interfaces: Vec<(u32, u64)> = vec![];
+
+    let mut rest = data;
+    while !rest.is_empty() {
+        let header = rest
+            .get(..12)
+            .ok_or_else(|| invalid("truncated pcapng block"))?;
+        // The section header type reads the same in either byte order.
+        if header[..4] == SECTION_HEADER.to_le_bytes() {
+            endian = match header[8..12] {
+                [0x4d, 0x3c, 0x2b, 0x1a] => Endian { big: false },
+                [0x1a, 0x2b, 0x3c, 0x4d] => Endian { big: true },
+                _ => return Err(invalid("bad pcapng byte order magic")),
+            };
+            interfaces.clear();
+        }
+        let block_type = endian.u32(header);
+        let length = endian.u32(&header[4..]) as usize;
+        let block = rest
+            .get(..length)
+            .filter(|_| length >= 12)
+            .ok_or_else(|| invalid("truncated pcapng block"))?;
+        let body = &block[8..length - 4];
+        rest = &rest[length..];
+
+        match block_type {
+            INTERFACE_DESCRIPTION if body.len() >= 8 => {
+                let linktype = endian.u16(body) as u32;
+                let mut resolution = 1_000_000;
+                let mut options = &body[8..];
+                while options.len() >= 4 {
+                    let code = endian.u16(options);
+                    let len = endian.u16(&options[2..]) as usize;
+                    let Some(value) = options.get(4..4 + len) else {
+                        break;
+                    };
+                    if code == IF_TSRESOL && len == 1 {
+                        let exponent = (value[0] & 0x7f) as u32;
+                        resolution = match value[0] & 0x80 != 0 {
+                            true => 2u64.checked_pow(exponent),
+                            false => 10u64.checked_pow(exponent),
+                        }
+                        .ok_or_else(|| invalid("bad pcapng timestamp resolution"))?;
+                    }
+                    options = options.get((4 + len).next_multiple_of(4)..).unwrap_or(&[]);
+                }
+                interfaces.push((linktype, resolution));
+            }
+            ENHANCED_PACKET if body.len() >= 20 => {
+                let interface = endian.u32(body) as usize;
+                let timestamp =
+                    (endian.u32(&body[4..]) as u64) << 32 | endian.u32(&body[8..]) as u64;
+                let captured = endian.u32(&body[12..]) as usize;
+                let packet = body
+                    .get(20..20 + captured)
+                    .ok_or_else(|| invalid("truncated pcapng packet"))?;
+                let &(linktype, resolution) = interfaces
+                    .get(interface)
+                    .ok_or_else(|| invalid("pcapng packet on undescribed interface"))?;
+                let time = Duration::from_secs(timestamp / resolution)
+                    + Duration::from_nanos(
+                        ((timestamp % resolution) as u128 * 1_000_000_000 / resolution as u128)
+                            as u64,
+                    );
+                extractor.packet(linktype, endian, time, packet);
+            }
+            SIMPLE_PACKET if body.len() >= 4 => {
+                // Simple packets have no timestamp.
+                let &(linktype, _) = interfaces
+                    .first()
+                    .ok_or_else(|| invalid("pcapng packet on undescribed interface"))?;
+                extractor.packet(linktype, endian, Duration::ZERO, &body[4..]);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Picks the events of the selected interface out of the captured URBs.
+struct Extractor {
+    filter: CaptureFilter,
+    start: Option<Duration>,
+    /// URB IDs of vendor requests reading data, waiting for their completion.
+    pending_control_in: Vec<(u64, u8, u16, u16)>,
+    events: Vec<(Duration, Event)>,
+}
+
+impl Extractor {
+    fn packet(&mut self, linktype: u32, endian: Endian, time: Duration, packet: &[u8]) {
+        let header_len = match linktype {
+            LINKTYPE_USB_LINUX => 48,
+            LINKTYPE_USB_LINUX_MMAPPED => 64,
+            _ => return,
+        };
+        let Some(header) = packet.get(..header_len) else {
+            return;
+        };
+        let data = &packet[header_len..];
+
+        let id = endian.u64(header);
+        let submit = header[8] == b'S';
+        let complete = header[8] == b'C';
+        let xfer_type = header[9];
+        let endpoint = header[10];
+        let address = (endian.u16(&header[12..]), header[11]);
+        let has_setup = header[14] == 0;
+        let setup = &header[40..48];
+
+        let interface = self.filter.interface;
+        // Setup packets are little endian, whatever the capturing host.
+        let le = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]);
+        let relevant = match xfer_type {
+            XFER_BULK => endpoint == interface.write_ep() || endpoint == interface.read_ep(),
+            // Vendor requests to the interface, the low byte of the index selects it.
+            XFER_CONTROL if submit => {
+                has_setup && setup[0] & 0x60 == 0x40 && le(&setup[4..]) & 0xff == interface.index()
+            }
+            // Matched up with their submission below.
+            XFER_CONTROL => complete,
+            _ => false,
+        };
+        if !relevant {
+            return;
+        }
+        match self.filter.device {
+            Some(device) if device != address => return,
+            Some(_) => {}
+            None if xfer_type == XFER_CONTROL && complete => {}
+            None => self.filter.device = Some(address),
+        }
+
+        let start = *self.start.get_or_insert(time);
+        let time = time.saturating_sub(start);
+        let event = match (xfer_type, submit, complete) {
+            (XFER_BULK, true, _) if endpoint == interface.write_ep() => {
+                Event::BulkOut(data.to_vec())
+            }
+            (XFER_BULK, _, true) if endpoint == interface.read_ep() => {
+                Event::bulk_in(data, self.filter.max_packet_size)
+            }
+            (XFER_CONTROL, true, _) if setup[0] & 0x80 == 0 => Event::ControlOut {
+                request: setup[1],
+                value: le(&setup[2..]),
+                index: le(&setup[4..]),
+            },
+            (XFER_CONTROL, true, _) => {
+                self.pending_control_in
+                    .push((id, setup[1], le(&setup[2..]), le(&setup[4..])));
+                return;
+            }
+            (XFER_CONTROL, _, true) => {
+                let Some(i) = self.pending_control_in.iter().position(|p| p.0 == id) else {
+                    return;
+                };
+                let (_, request, value, index) = self.pending_control_in.remove(i);
+                Event::ControlIn {
+                    request,
+                    value,
+                    index,
+                    data: data.to_vec(),
+                }
+            }
+            _ => return,
+        };
+        self.events.push((time, event));
+    }
+}
+
+/// A line of a [`jtag_trace`]: a command sent in MPSSE mode and the TAP state before and after.
+///
+/// States are `None` where they can't be known, e.g. at the start of a capture until TMS was
+/// clocked enough to determine the state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceLine {
+    /// When the bulk OUT transfer completing the command was submitted.
+    pub time: Duration,
+    pub command: Result<MpsseCommand, DecodeError>,
+    pub from: Option<TapState>,
+    pub to: Option<TapState>,
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let command = match &self.command {
+            Ok(command) => command.to_string(),
+            Err(e) => format!("!! {e}"),
+        };
+        let state = |s: Option<TapState>| s.map_or("?".to_string(), |s| format!("{s:?}"));
+        write!(f, "{:>12.6}  {command:<40}", self.time.as_secs_f64())?;
+        if self.from != self.to {
+            write!(f, "  {} -> {}", state(self.from), state(self.to))?;
+        } else {
+            write!(f, "  {}", state(self.to))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes the MPSSE commands sent in `events`, e.g. from [`read_capture`], tracking the JTAG
+/// TAP state they move through.
+///
+/// TMS is assumed to be on ADBUS3 as usual for JTAG. Outside MPSSE mode, as set by bitmode
+/// requests, data is skipped; a capture without any bitmode request is assumed to be in MPSSE
+/// mode throughout. Commands split over several transfers are reassembled.
+pub fn jtag_trace(events: &[(Duration, Event)]) -> Vec<TraceLine> {
+    const SIO_SET_BITMODE_REQUEST: u8 = 0x0B;
+    const BITMODE_MPSSE: u16 = 0x0200;
+
+    let mut mpsse = !events.iter().any(|(_, e)| {
+        matches!(e, Event::ControlOut { request, .. } if *request == SIO_SET_BITMODE_REQUEST)
+    });
+    let mut tracker = TapTracker {
+        candidates: TapTracker::UNKNOWN,
+        tms: None,
+    };
+    let mut pending = vec![];
+    let mut lines = vec![];
+
+    for (time, event) in events {
+        match event {
+            Event::ControlOut { request, value, .. } if *request == SIO_SET_BITMODE_REQUEST => {
+                mpsse = value & 0xff00 == BITMODE_MPSSE;
+                pending.clear();
+            }
+            Event::BulkOut(data) if mpsse => {
+                pending.extend_from_slice(data);
+                let mut decoded = 0;
+                while decoded < pending.len() {
+                    let command = match disasm::decode(&pending[decoded..]) {
+                        Ok((command, len)) => {
+                            decoded += len;
+                            Ok(command)
+                        }
+                        Err(DecodeError::Truncated { .. }) => break,
+                        Err(e) => {
+                            decoded += 1;
+                            Err(e)
+                        }
+                    };
+                    let from = tracker.state();
+                    if let Ok(command) = &command {
+                        tracker.execute(command);
+                    }
+                    lines.push(TraceLine {
+                        time: *time,
+                        command,
+                        from,
+                        to: tracker.state(),
+                    });
+                }
+                pending.drain(..decoded);
+            }
+            _ => {}
+        }
+    }
+
+    lines
+}
+
+const TAP_STATES: [TapState; 16] = {
+    use TapState::*;
+    [
+        TestLogicReset,
+        RunTestIdle,
+        SelectDrScan,
+        CaptureDr,
+        ShiftDr,
+        Exit1Dr,
+        PauseDr,
+        Exit2Dr,
+        UpdateDr,
+        SelectIrScan,
+        CaptureIr,
+        ShiftIr,
+        Exit1Ir,
+        PauseIr,
+        Exit2Ir,
+        UpdateIr,
+    ]
+};
+
+/// Follows the TAP state through the TCK cycles of MPSSE commands.
+struct TapTracker {
+    /// The states the TAP may be in, a bit per entry of [`TAP_STATES`].
+    candidates: u16,
+    /// The level of the TMS pin, it keeps the last bit clocked out on it.
+    tms: Option<bool>,
+}
+
+impl TapTracker {
+    const UNKNOWN: u16 = u16::MAX;
+
+    fn state(&self) -> Option<TapState> {
+        (self.candidates.count_ones() == 1)
+            .then(|| TAP_STATES[self.candidates.trailing_zeros() as usize])
+    }
+
+    fn execute(&mut self, command: &MpsseCommand) {
+        const TMS_PIN: u8 = 1 << 3;
+
+        let clocks = match command {
+            MpsseCommand::WriteTms { count, data, .. } => {
+                for i in 0..*count {
+                    let tms = data & (1 << i) != 0;
+                    self.tms = Some(tms);
+                    self.clock(Some(tms));
+                }
+                return;
+            }
+            MpsseCommand::SetLowBits { value, direction } if direction & TMS_PIN != 0 => {
+                self.tms = Some(value & TMS_PIN != 0);
+                return;
+            }
+            MpsseCommand::WriteBits { count, .. }
+            | MpsseCommand::ReadBits { count, .. }
+            | MpsseCommand::TransferBits { count, .. }
+            | MpsseCommand::ClockBits(count) => *count as usize,
+            MpsseCommand::WriteBytes { data, .. } | MpsseCommand::TransferBytes { data, .. } => {
+                data.len() * 8
+            }
+            MpsseCommand::ReadBytes { len, .. } | MpsseCommand::ClockBytes(len) => len * 8,
+            // The number of cycles depends on GPIOL1.
+            MpsseCommand::ClockUntilIoHigh
+            | MpsseCommand::ClockUntilIoLow
+            | MpsseCommand::ClockBytesUntilIoHigh(_)
+            | MpsseCommand::ClockBytesUntilIoLow(_) => {
+                self.candidates = Self::UNKNOWN;
+                return;
+            }
+            _ => return,
+        };
+
+        // With TMS held, every state settles within 5 cycles.
+        for _ in 0..clocks.min(8) {
+            self.clock(self.tms);
+        }
+    }
+
+    /// Moves every candidate state on, with either TMS level if it's unknown.
+    fn clock(&mut self, tms: Option<bool>) {
+        let levels: &[bool] = match tms {
+            Some(tms) => &[tms][..],
+            None => &[false, true],
+        };
+        let mut next = 0;
+        for (i, state) in TAP_STATES.iter().enumerate() {
+            if self.candidates & 1 << i == 0 {
+                continue;
+            }
+            for &tms in levels {
+                let next_state = state.next(tms);
+                next |= 1 << TAP_STATES.iter().position(|s| *s == next_state).unwrap();
+            }
+        }
+        self.candidates = next;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::mpsse::{BitOrder, ClockEdge, MpsseBuffer};
+
+    /// A usbmon packet with the 48 byte header, from bus 1 device 5.
+    fn usbmon(
+        kind: u8,
+        xfer_type: u8,
+        endpoint: u8,
+        setup: Option<[u8; 8]>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut packet = vec![0; 48];
+        packet[..8].copy_from_slice(&(endpoint as u64).to_le_bytes());
+        packet[8] = kind;
+        packet[9] = xfer_type;
+        packet[10] = endpoint;
+        packet[11] = 5;
+        packet[12..14].copy_from_slice(&1u16.to_le_bytes());
+        packet[14] = if setup.is_some() { 0 } else { b'-' };
+        packet[36..40].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        if let Some(setup) = setup {
+            packet[40..48].copy_from_slice(&setup);
+        }
+        packet.extend(data);
+        packet
+    }
+
+    fn session() -> Vec<Vec<u8>> {
+        let commands = MpsseBuffer::new()
+            .with(&MpsseCommand::SetLowBits {
+                value: 0x08,
+                direction: 0x0b,
+            })
+            .unwrap()
+            .with(&MpsseCommand::WriteTms {
+                edge: ClockEdge::Falling,
+                read_edge: None,
+                count: 5,
+                tdi: false,
+                data: 0b11111,
+            })
+            .unwrap()
+            .with(&MpsseCommand::WriteTms {
+                edge: ClockEdge::Falling,
+                read_edge: None,
+                count: 4,
+                tdi: false,
+                data: 0b0010,
+            })
+            .unwrap()
+            .with(&MpsseCommand::TransferBytes {
+                write_edge: ClockEdge::Falling,
+                read_edge: ClockEdge::Rising,
+                order: BitOrder::LsbFirst,
+                data: vec![0x12, 0x34],
+            })
+            .unwrap()
+            .with(&MpsseCommand::SendImmediate)
+            .unwrap()
+            .into_bytes();
+        let (first, second) = commands.split_at(commands.len() - 3);
+
+        let mut other_device = usbmon(b'S', XFER_BULK, 0x02, None, &[0xaa]);
+        other_device[11] = 6;
+        vec![
+            // Set bitmode to MPSSE on interface A, and its completion.
+            usbmon(
+                b'S',
+                XFER_CONTROL,
+                0x00,
+                Some([0x40, 0x0b, 0x0b, 0x02, 0x01, 0x00, 0, 0]),
+                &[],
+            ),
+            usbmon(b'C', XFER_CONTROL, 0x00, None, &[]),
+            other_device,
+            // A command split over two transfers.
+            usbmon(b'S', XFER_BULK, 0x02, None, first),
+            usbmon(b'S', XFER_BULK, 0x02, None, second),
+            // Interface B.
+            usbmon(b'S', XFER_BULK, 0x04, None, &[0x87]),
+            usbmon(b'C', XFER_BULK, 0x81, None, &[0x32, 0x60, 0x56, 0x78]),
+            // Poll modem status.
+            usbmon(
+                b'S',
+                XFER_CONTROL,
+                0x80,
+                Some([0xc0, 0x05, 0x00, 0x00, 0x01, 0x00, 2, 0]),
+                &[],
+            ),
+            usbmon(b'C', XFER_CONTROL, 0x80, None, &[0x32, 0x60]),
+        ]
+    }
+
+    fn pcap(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        file.extend([0; 8]);
+        file.extend(65535u32.to_le_bytes());
+        file.extend(LINKTYPE_USB_LINUX.to_le_bytes());
+        for (i, packet) in packets.iter().enumerate() {
+            file.extend(100u32.to_le_bytes());
+            file.extend((i as u32 * 1000).to_le_bytes());
+            file.extend((packet.len() as u32).to_le_bytes());
+            file.extend((packet.len() as u32).to_le_bytes());
+            file.extend(packet);
+        }
+        file
+    }
+
+    fn pcapng(packets: &[Vec<u8>]) -> Vec<u8> {
+        fn block(file: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+            let padded = body.len().next_multiple_of(4);
+            let length = (12 + padded) as u32;
+            file.extend(block_type.to_le_bytes());
+            file.extend(length.to_le_bytes());
+            file.extend(body);
+            file.extend(vec![0; padded - body.len()]);
+            file.extend(length.to_le_bytes());
+        }
+
+        let mut file = vec![];
+        block(
+            &mut file,
+            0x0a0d0d0a,
+            &[
+                0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            ],
+        );
+        // Nanosecond timestamps.
+        let mut interface = (LINKTYPE_USB_LINUX_MMAPPED as u16).to_le_bytes().to_vec();
+        interface.extend([0, 0, 0, 0, 0, 0]);
+        interface.extend([9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+        block(&mut file, 1, &interface);
+        for (i, packet) in packets.iter().enumerate() {
+            // The mmapped header has 16 more bytes.
+            let mut packet = packet.clone();
+            packet.splice(48..48, [0; 16]);
+            let timestamp = 100_000_000_000 + i as u64 * 1_000_000;
+            let mut body = vec![0; 4];
+            body.extend(((timestamp >> 32) as u32).to_le_bytes());
+            body.extend((timestamp as u32).to_le_bytes());
+            body.extend((packet.len() as u32).to_le_bytes());
+            body.extend((packet.len() as u32).to_le_bytes());
+            body.extend(&packet);
+            block(&mut file, 6, &body);
+        }
+        file
+    }
+
+    #[test]
+    fn test_read_capture() {
+        let packets = session();
+        for file in [pcap(&packets), pcapng(&packets)] {
+            let events = read_capture(&file[..], CaptureFilter::new(Interface::A)).unwrap();
+            assert_eq!(
+                events
+                    .iter()
+                    .map(|(_, e)| e.to_string())
+                    .collect::<Vec<_>>(),
+                [
+                    "ctrl_out 0b 020b 0001",
+                    "out 80080b4b041f4b0302390100",
+                    "out 123487",
+                    "in 5678",
+                    "ctrl_in 05 0000 0001 3260",
+                ]
+            );
+            assert_eq!(events[1].0, Duration::from_millis(3));
+
+            let trace = jtag_trace(&events);
+            assert_eq!(
+                trace.iter().map(|l| l.to_string()).collect::<Vec<_>>(),
+                [
+                    "    0.003000  SET LOW value=0x08 dir=0x0b               ?",
+                    "    0.003000  TMS 5 bits 0b11111 TDI=0                  ? -> TestLogicReset",
+                    "    0.003000  TMS 4 bits 0b0010 TDI=0                   TestLogicReset -> ShiftDr",
+                    "    0.004000  DATA OUT/IN 2 bytes LSB -ve/+ve           ShiftDr",
+                    "    0.004000  SEND IMMEDIATE                            ShiftDr",
+                ]
+            );
+        }
+
+        let events = read_capture(
+            &pcap(&session())[..],
+            CaptureFilter::new(Interface::A).with_device(1, 6),
+        )
+        .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].1, Event::BulkOut(vec![0xaa]));
+    }
+}
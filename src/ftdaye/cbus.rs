@@ -0,0 +1,226 @@
+//! GPIO on the CBUS pins of the FT232R, FT232H and FT-X series, in CBUS bitbang mode.
+
+use async_io::block_on;
+
+use crate::ftdaye::eeprom::CbusFunction;
+use crate::ftdaye::{error::FtdiError, BitMode, ChipType, Device, Result};
+
+/// Drives up to 4 CBUS pins as GPIOs.
+///
+/// Bits 0 to 3 of every mask map to CBUS0..CBUS3 on the FT232R and FT-X series, and to
+/// ACBUS5, ACBUS6, ACBUS8 and ACBUS9 on the FT232H. A pin can only be used if the EEPROM sets
+/// its function to [`CbusFunction::IoMode`].
+#[derive(Debug)]
+pub struct CbusGpio {
+    device: Device,
+    /// The CBUS pin behind each bit, and its function in the EEPROM.
+    pins: [(u8, CbusFunction); 4],
+    direction: u8,
+    level: u8,
+}
+
+impl CbusGpio {
+    /// Reads the EEPROM of `device` and switches it to CBUS bitbang mode, all pins inputs.
+    pub fn new(device: Device) -> Result<Self> {
+        block_on(Self::new_async(device))
+    }
+
+    pub async fn new_async(mut device: Device) -> Result<Self> {
+        let cbus_pins: [u8; 4] = match device.chip_type() {
+            Some(ChipType::R | ChipType::FT230X) => [0, 1, 2, 3],
+            Some(ChipType::FT232H) => [5, 6, 8, 9],
+            Some(chip_type) => Err(FtdiError::UnsupportedChipType(chip_type))?,
+            None => Err(FtdiError::Other("unknown chip type".to_string()))?,
+        };
+
+        let eeprom = device.read_eeprom_async().await?;
+        if eeprom.is_blank() {
+            Err(FtdiError::Other(
+                "the EEPROM is blank, no CBUS pin is configured as GPIO".to_string(),
+            ))?
+        }
+        let functions = eeprom.decode()?.cbus;
+
+        let mut gpio = Self {
+            device,
+            pins: cbus_pins.map(|pin| (pin, functions[pin as usize])),
+            direction: 0,
+            level: 0,
+        };
+        gpio.apply().await?;
+
+        Ok(gpio)
+    }
+
+    /// Returns a mask of the bits whose pins are configured as GPIO.
+    pub fn available(&self) -> u8 {
+        self.pins
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, function))| *function == CbusFunction::IoMode)
+            .fold(0, |mask, (bit, _)| mask | 1 << bit)
+    }
+
+    pub fn direction(&self) -> u8 {
+        self.direction
+    }
+
+    /// Makes the pins set in `direction` outputs, and the others inputs.
+    pub fn set_direction(&mut self, direction: u8) -> Result<()> {
+        block_on(self.set_direction_async(direction))
+    }
+
+    pub async fn set_direction_async(&mut self, direction: u8) -> Result<()> {
+        self.check(direction)?;
+        self.direction = direction & 0x0f;
+        self.apply().await
+    }
+
+    /// Drives the output pins to the levels in `level`.
+    pub fn write(&mut self, level: u8) -> Result<()> {
+        block_on(self.write_async(level))
+    }
+
+    pub async fn write_async(&mut self, level: u8) -> Result<()> {
+        self.check(level & self.direction)?;
+        self.level = level & 0x0f;
+        self.apply().await
+    }
+
+    /// Drives a single output pin, leaving the others unchanged.
+    pub fn set_pin(&mut self, bit: u8, high: bool) -> Result<()> {
+        block_on(self.set_pin_async(bit, high))
+    }
+
+    pub async fn set_pin_async(&mut self, bit: u8, high: bool) -> Result<()> {
+        if bit >= 4 {
+            Err(FtdiError::Other(format!(
+                "CBUS bitbang has 4 pins, got bit {bit}"
+            )))?
+        }
+        let level = match high {
+            true => self.level | 1 << bit,
+            false => self.level & !(1 << bit),
+        };
+        self.write_async(level).await
+    }
+
+    /// Reads the level of all 4 pins.
+    pub fn read(&mut self) -> Result<u8> {
+        block_on(self.read_async())
+    }
+
+    pub async fn read_async(&mut self) -> Result<u8> {
+        Ok(self.device.read_pins_async().await? & 0x0f)
+    }
+
+    /// Leaves CBUS bitbang mode and returns the device.
+    pub fn into_inner(self) -> Result<Device> {
+        block_on(self.into_inner_async())
+    }
+
+    pub async fn into_inner_async(mut self) -> Result<Device> {
+        self.device.set_bitmode_async(0, BitMode::Reset).await?;
+
+        Ok(self.device)
+    }
+
+    /// Fails if a bit in `mask` belongs to a pin that is not a GPIO.
+    fn check(&self, mask: u8) -> Result<()> {
+        if mask & 0xf0 != 0 {
+            Err(FtdiError::Other(format!(
+                "CBUS bitbang has 4 pins, got mask {mask:#04x}"
+            )))?
+        }
+
+        match self
+            .pins
+            .iter()
+            .enumerate()
+            .find(|(bit, (_, function))| mask & 1 << bit != 0 && *function != CbusFunction::IoMode)
+        {
+            Some((_, &(pin, function))) => Err(FtdiError::CbusPinNotGpio { pin, function }),
+            None => Ok(()),
+        }
+    }
+
+    async fn apply(&mut self) -> Result<()> {
+        // The high nibble selects the outputs, the low nibble their levels.
+        let mask = self.direction << 4 | self.level & self.direction;
+        self.device.set_bitmode_async(mask, BitMode::Cbus).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::eeprom::{ft2232h_config, ChannelConfig, ChannelType, Eeprom, EepromConfig};
+    use crate::ftdaye::transport::{descriptor, MemoryTransport};
+
+    #[test]
+    fn test_cbus_gpio() {
+        let mut cbus = vec![CbusFunction::Tristate; 10];
+        cbus[5] = CbusFunction::IoMode;
+        cbus[8] = CbusFunction::IoMode;
+        cbus[9] = CbusFunction::TxLed;
+        let config = EepromConfig {
+            product_id: 0x6014,
+            release: 0x0900,
+            channels: vec![ChannelConfig {
+                channel_type: ChannelType::Fifo,
+                vcp_driver: false,
+                rs485: false,
+            }],
+            drive: ft2232h_config().drive[..2].to_vec(),
+            cbus,
+            ..ft2232h_config()
+        };
+        let mut eeprom = Eeprom::blank(ChipType::FT232H, 256).unwrap();
+        eeprom.encode(&config).unwrap();
+
+        let transport =
+            MemoryTransport::new(descriptor(0x6014, 0x900)).with_eeprom(eeprom.as_bytes());
+        let device = transport.open();
+
+        let mut gpio = CbusGpio::new(device).unwrap();
+        // ACBUS5 and ACBUS8 are bits 0 and 2.
+        assert_eq!(gpio.available(), 0b0101);
+        gpio.set_direction(0b0101).unwrap();
+        gpio.set_pin(2, true).unwrap();
+        gpio.write(0b0001).unwrap();
+        assert_eq!(
+            transport
+                .take_control_requests()
+                .iter()
+                .map(|r| (r.request, r.value))
+                .collect::<Vec<_>>(),
+            [
+                (0x0B, 0x2000),
+                (0x0B, 0x2050),
+                (0x0B, 0x2054),
+                (0x0B, 0x2051)
+            ]
+        );
+
+        transport.set_pin_state(0xf6);
+        assert_eq!(gpio.read().unwrap(), 0x06);
+
+        assert!(matches!(
+            gpio.set_direction(0b1000),
+            Err(FtdiError::CbusPinNotGpio {
+                pin: 9,
+                function: CbusFunction::TxLed
+            })
+        ));
+        assert!(gpio.set_direction(0b0010).is_err());
+        assert!(gpio.set_pin(8, true).is_err());
+        assert_eq!(gpio.direction(), 0b0101);
+
+        // The FT2232H has no CBUS bitbang mode.
+        let device = MemoryTransport::default().open();
+        assert!(matches!(
+            CbusGpio::new(device),
+            Err(FtdiError::UnsupportedChipType(ChipType::FT2232H))
+        ));
+    }
+}
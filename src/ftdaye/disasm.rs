@@ -0,0 +1,561 @@
+//! Decoding of MPSSE command streams into [`MpsseCommand`]s, for logging and tests.
+//!
+//! [`Listing`] renders a stream one command per line:
+//!
+//! ```text
+//! 0000  SET LOW value=0x08 dir=0x0b
+//! 0003  TMS 3 bits 0b011 TDI=1
+//! 0006  DATA OUT 4096 bytes LSB -ve
+//! 1009  SEND IMMEDIATE
+//! ```
+
+use std::fmt;
+
+use crate::ftdaye::mcu::McuAddress;
+use crate::ftdaye::mpsse::{BitOrder, ClockEdge, MpsseCommand};
+
+/// A byte stream that is not a valid sequence of MPSSE commands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeError {
+    #[error("bad opcode {0:#04x}")]
+    /// The MPSSE answers an unknown opcode with `0xFA` and the opcode, and carries on.
+    BadOpcode(u8),
+
+    #[error("command {opcode:#04x} has a bit count of {count}, more than the maximum of {max}")]
+    /// A bit count that doesn't fit the command, e.g. 8 TMS bits.
+    BadBitCount { opcode: u8, count: u8, max: u8 },
+
+    #[error("command {opcode:#04x} needs {needed} bytes, only {available} left")]
+    /// The stream ends in the middle of a command.
+    Truncated {
+        opcode: u8,
+        needed: usize,
+        available: usize,
+    },
+}
+
+/// Decodes the command at the start of `bytes`, returning it along with its encoded length.
+pub fn decode(bytes: &[u8]) -> Result<(MpsseCommand, usize), DecodeError> {
+    let Some(&opcode) = bytes.first() else {
+        return Err(DecodeError::Truncated {
+            opcode: 0,
+            needed: 1,
+            available: 0,
+        });
+    };
+    let take = |needed: usize| {
+        bytes.get(..needed).ok_or(DecodeError::Truncated {
+            opcode,
+            needed,
+            available: bytes.len(),
+        })
+    };
+    let byte_len = |b: &[u8]| u16::from_le_bytes([b[1], b[2]]) as usize + 1;
+
+    if opcode & 0x80 == 0 {
+        return decode_shift(opcode, take);
+    }
+
+    let command = match opcode {
+        0x80 | 0x82 | 0x9E => {
+            let b = take(3)?;
+            match opcode {
+                0x80 => MpsseCommand::SetLowBits {
+                    value: b[1],
+                    direction: b[2],
+                },
+                0x82 => MpsseCommand::SetHighBits {
+                    value: b[1],
+                    direction: b[2],
+                },
+                _ => MpsseCommand::DriveZeroOnly {
+                    low: b[1],
+                    high: b[2],
+                },
+            }
+        }
+        0x81 => MpsseCommand::ReadLowBits,
+        0x83 => MpsseCommand::ReadHighBits,
+        0x84 | 0x85 => MpsseCommand::Loopback(opcode == 0x84),
+        0x86 => {
+            let b = take(3)?;
+            MpsseCommand::SetClockDivisor(u16::from_le_bytes([b[1], b[2]]))
+        }
+        0x87 => MpsseCommand::SendImmediate,
+        0x88 => MpsseCommand::WaitOnIoHigh,
+        0x89 => MpsseCommand::WaitOnIoLow,
+        0x8A | 0x8B => MpsseCommand::DivideBy5(opcode == 0x8B),
+        0x8C | 0x8D => MpsseCommand::ThreePhaseClocking(opcode == 0x8C),
+        0x8E => MpsseCommand::ClockBits(bit_count(opcode, take(2)?[1], 8)?),
+        0x8F => MpsseCommand::ClockBytes(byte_len(take(3)?)),
+        0x90 => MpsseCommand::McuRead(McuAddress::Short(take(2)?[1])),
+        0x91 => {
+            let b = take(3)?;
+            MpsseCommand::McuRead(McuAddress::Extended(u16::from_be_bytes([b[1], b[2]])))
+        }
+        0x92 => {
+            let b = take(3)?;
+            MpsseCommand::McuWrite(McuAddress::Short(b[1]), b[2])
+        }
+        0x93 => {
+            let b = take(4)?;
+            MpsseCommand::McuWrite(McuAddress::Extended(u16::from_be_bytes([b[1], b[2]])), b[3])
+        }
+        0x94 => MpsseCommand::ClockUntilIoHigh,
+        0x95 => MpsseCommand::ClockUntilIoLow,
+        0x96 | 0x97 => MpsseCommand::AdaptiveClocking(opcode == 0x96),
+        0x9C => MpsseCommand::ClockBytesUntilIoHigh(byte_len(take(3)?)),
+        0x9D => MpsseCommand::ClockBytesUntilIoLow(byte_len(take(3)?)),
+        _ => return Err(DecodeError::BadOpcode(opcode)),
+    };
+    let len = encoded_len(&command);
+
+    Ok((command, len))
+}
+
+/// Decodes a data shifting command, see AN108 section 3.2 for the opcode bits.
+fn decode_shift<'a>(
+    opcode: u8,
+    take: impl Fn(usize) -> Result<&'a [u8], DecodeError>,
+) -> Result<(MpsseCommand, usize), DecodeError> {
+    let edge = |falling| match falling {
+        true => ClockEdge::Falling,
+        false => ClockEdge::Rising,
+    };
+    let write_edge = edge(opcode & 0x01 != 0);
+    let bit_mode = opcode & 0x02 != 0;
+    let read_edge = edge(opcode & 0x04 != 0);
+    let order = match opcode & 0x08 != 0 {
+        true => BitOrder::LsbFirst,
+        false => BitOrder::MsbFirst,
+    };
+    let write_tdi = opcode & 0x10 != 0;
+    let read_tdo = opcode & 0x20 != 0;
+    let write_tms = opcode & 0x40 != 0;
+
+    // Only the opcodes listed in AN108 are accepted, so that every command has one encoding.
+    let valid = match (write_tms, write_tdi, read_tdo) {
+        (true, false, read) => {
+            bit_mode && order == BitOrder::LsbFirst && (read || opcode & 0x04 == 0)
+        }
+        (true, true, _) | (false, false, false) => false,
+        (false, true, read) => read || opcode & 0x04 == 0,
+        (false, false, true) => opcode & 0x01 == 0,
+    };
+    if !valid {
+        return Err(DecodeError::BadOpcode(opcode));
+    }
+
+    let command = if write_tms {
+        let b = take(3)?;
+        MpsseCommand::WriteTms {
+            edge: write_edge,
+            read_edge: read_tdo.then_some(read_edge),
+            count: bit_count(opcode, b[1], 7)?,
+            tdi: b[2] & 0x80 != 0,
+            data: b[2] & 0x7f,
+        }
+    } else if bit_mode {
+        let header = take(2)?;
+        let count = bit_count(opcode, header[1], 8)?;
+        match (write_tdi, read_tdo) {
+            (true, true) => MpsseCommand::TransferBits {
+                write_edge,
+                read_edge,
+                order,
+                count,
+                data: take(3)?[2],
+            },
+            (true, false) => MpsseCommand::WriteBits {
+                edge: write_edge,
+                order,
+                count,
+                data: take(3)?[2],
+            },
+            _ => MpsseCommand::ReadBits {
+                edge: read_edge,
+                order,
+                count,
+            },
+        }
+    } else {
+        let header = take(3)?;
+        let len = u16::from_le_bytes([header[1], header[2]]) as usize + 1;
+        match (write_tdi, read_tdo) {
+            (true, true) => MpsseCommand::TransferBytes {
+                write_edge,
+                read_edge,
+                order,
+                data: take(3 + len)?[3..].to_vec(),
+            },
+            (true, false) => MpsseCommand::WriteBytes {
+                edge: write_edge,
+                order,
+                data: take(3 + len)?[3..].to_vec(),
+            },
+            _ => MpsseCommand::ReadBytes {
+                edge: read_edge,
+                order,
+                len,
+            },
+        }
+    };
+    let len = encoded_len(&command);
+
+    Ok((command, len))
+}
+
+/// Decodes a bit count, encoded offset by one.
+fn bit_count(opcode: u8, encoded: u8, max: u8) -> Result<u8, DecodeError> {
+    match encoded.checked_add(1) {
+        Some(count) if count <= max => Ok(count),
+        _ => Err(DecodeError::BadBitCount {
+            opcode,
+            count: encoded.saturating_add(1),
+            max,
+        }),
+    }
+}
+
+fn encoded_len(command: &MpsseCommand) -> usize {
+    let mut buf = vec![];
+    // Decoded counts are always in range.
+    let _ = command.encode(&mut buf);
+    buf.len()
+}
+
+/// Iterates over the commands in a byte stream, along with their offsets.
+///
+/// A bad opcode is reported and skipped, like the MPSSE does. A malformed or truncated command
+/// ends the iteration.
+#[derive(Clone, Debug)]
+pub struct Disassembler<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+/// Decodes `bytes` command by command.
+pub fn disassemble(bytes: &[u8]) -> Disassembler<'_> {
+    Disassembler { bytes, offset: 0 }
+}
+
+impl Iterator for Disassembler<'_> {
+    type Item = (usize, Result<MpsseCommand, DecodeError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        let rest = self.bytes.get(offset..).filter(|rest| !rest.is_empty())?;
+        match decode(rest) {
+            Ok((command, len)) => {
+                self.offset += len;
+                Some((offset, Ok(command)))
+            }
+            Err(e @ DecodeError::BadOpcode(_)) => {
+                self.offset += 1;
+                Some((offset, Err(e)))
+            }
+            Err(e) => {
+                self.offset = self.bytes.len();
+                Some((offset, Err(e)))
+            }
+        }
+    }
+}
+
+/// Returns how many bytes the MPSSE sends back for the commands in `bytes`, counting the two
+/// bytes it answers a bad opcode with.
+///
+/// Fails if the stream ends in the middle of a command.
+pub fn response_len(bytes: &[u8]) -> Result<usize, DecodeError> {
+    disassemble(bytes).try_fold(0, |len, (_, command)| match command {
+        Ok(command) => Ok(len + command.response_len()),
+        Err(DecodeError::BadOpcode(_)) => Ok(len + 2),
+        Err(e) => Err(e),
+    })
+}
+
+/// Displays a byte stream as a listing of commands, one per line.
+#[derive(Clone, Copy, Debug)]
+pub struct Listing<'a>(pub &'a [u8]);
+
+impl fmt::Display for Listing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (offset, command)) in disassemble(self.0).enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match command {
+                Ok(command) => write!(f, "{offset:04x}  {command}")?,
+                Err(e) => write!(f, "{offset:04x}  !! {e}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Edge(ClockEdge);
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.0 {
+            ClockEdge::Rising => "+ve",
+            ClockEdge::Falling => "-ve",
+        })
+    }
+}
+
+struct Order(BitOrder);
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.0 {
+            BitOrder::MsbFirst => "MSB",
+            BitOrder::LsbFirst => "LSB",
+        })
+    }
+}
+
+/// The `count` bits of `data` that are shifted out in binary, the low ones LSB first and the high
+/// ones MSB first.
+struct Bits(u8, u8, BitOrder);
+
+impl fmt::Display for Bits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self(count, data, order) = *self;
+        let count = count.min(8) as usize;
+        let bits = match order {
+            BitOrder::LsbFirst => data as u16 & ((1 << count) - 1),
+            BitOrder::MsbFirst => data as u16 >> (8 - count),
+        };
+        write!(f, "0b{bits:0count$b}")
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    match on {
+        true => "ON",
+        false => "OFF",
+    }
+}
+
+fn address(address: McuAddress) -> String {
+    match address {
+        McuAddress::Short(a) => format!("{a:#04x}"),
+        McuAddress::Extended(a) => format!("{a:#06x}"),
+    }
+}
+
+impl fmt::Display for MpsseCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WriteBytes { edge, order, data } => write!(
+                f,
+                "DATA OUT {} bytes {} {}",
+                data.len(),
+                Order(*order),
+                Edge(*edge)
+            ),
+            Self::WriteBits {
+                edge,
+                order,
+                count,
+                data,
+            } => write!(
+                f,
+                "DATA OUT {count} bits {} {} {}",
+                Bits(*count, *data, *order),
+                Order(*order),
+                Edge(*edge)
+            ),
+            Self::ReadBytes { edge, order, len } => {
+                write!(f, "DATA IN {len} bytes {} {}", Order(*order), Edge(*edge))
+            }
+            Self::ReadBits { edge, order, count } => {
+                write!(f, "DATA IN {count} bits {} {}", Order(*order), Edge(*edge))
+            }
+            Self::TransferBytes {
+                write_edge,
+                read_edge,
+                order,
+                data,
+            } => write!(
+                f,
+                "DATA OUT/IN {} bytes {} {}/{}",
+                data.len(),
+                Order(*order),
+                Edge(*write_edge),
+                Edge(*read_edge)
+            ),
+            Self::TransferBits {
+                write_edge,
+                read_edge,
+                order,
+                count,
+                data,
+            } => write!(
+                f,
+                "DATA OUT/IN {count} bits {} {} {}/{}",
+                Bits(*count, *data, *order),
+                Order(*order),
+                Edge(*write_edge),
+                Edge(*read_edge)
+            ),
+            Self::WriteTms {
+                edge,
+                read_edge,
+                count,
+                tdi,
+                data,
+            } => {
+                write!(
+                    f,
+                    "TMS {count} bits {} TDI={}",
+                    Bits(*count, *data, BitOrder::LsbFirst),
+                    *tdi as u8
+                )?;
+                if *edge == ClockEdge::Rising {
+                    write!(f, " {}", Edge(*edge))?;
+                }
+                if let Some(read_edge) = read_edge {
+                    write!(f, " IN {}", Edge(*read_edge))?;
+                }
+                Ok(())
+            }
+            Self::SetLowBits { value, direction } => {
+                write!(f, "SET LOW value={value:#04x} dir={direction:#04x}")
+            }
+            Self::SetHighBits { value, direction } => {
+                write!(f, "SET HIGH value={value:#04x} dir={direction:#04x}")
+            }
+            Self::ReadLowBits => f.write_str("GET LOW"),
+            Self::ReadHighBits => f.write_str("GET HIGH"),
+            Self::Loopback(on) => write!(f, "LOOPBACK {}", on_off(*on)),
+            Self::SetClockDivisor(divisor) => write!(f, "CLOCK DIVISOR {divisor}"),
+            Self::SendImmediate => f.write_str("SEND IMMEDIATE"),
+            Self::WaitOnIoHigh => f.write_str("WAIT IO HIGH"),
+            Self::WaitOnIoLow => f.write_str("WAIT IO LOW"),
+            Self::McuRead(a) => write!(f, "MCU READ {}", address(*a)),
+            Self::McuWrite(a, data) => write!(f, "MCU WRITE {} = {data:#04x}", address(*a)),
+            Self::DivideBy5(on) => write!(f, "DIVIDE BY 5 {}", on_off(*on)),
+            Self::ThreePhaseClocking(on) => write!(f, "3-PHASE CLOCK {}", on_off(*on)),
+            Self::ClockBits(count) => write!(f, "CLOCK {count} bits"),
+            Self::ClockBytes(len) => write!(f, "CLOCK {len} bytes"),
+            Self::ClockUntilIoHigh => f.write_str("CLOCK UNTIL IO HIGH"),
+            Self::ClockUntilIoLow => f.write_str("CLOCK UNTIL IO LOW"),
+            Self::AdaptiveClocking(on) => write!(f, "ADAPTIVE CLOCK {}", on_off(*on)),
+            Self::ClockBytesUntilIoHigh(len) => write!(f, "CLOCK {len} bytes UNTIL IO HIGH"),
+            Self::ClockBytesUntilIoLow(len) => write!(f, "CLOCK {len} bytes UNTIL IO LOW"),
+            Self::DriveZeroOnly { low, high } => {
+                write!(f, "DRIVE ZERO low={low:#04x} high={high:#04x}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::mpsse::MpsseBuffer;
+
+    #[test]
+    fn test_listing() {
+        let bytes = MpsseBuffer::new()
+            .with(&MpsseCommand::SetLowBits {
+                value: 0x08,
+                direction: 0x0b,
+            })
+            .unwrap()
+            .with(&MpsseCommand::WriteTms {
+                edge: ClockEdge::Falling,
+                read_edge: None,
+                count: 3,
+                tdi: true,
+                data: 0b011,
+            })
+            .unwrap()
+            .with(&MpsseCommand::WriteBytes {
+                edge: ClockEdge::Falling,
+                order: BitOrder::LsbFirst,
+                data: vec![0; 4096],
+            })
+            .unwrap()
+            .with(&MpsseCommand::ReadBits {
+                edge: ClockEdge::Rising,
+                order: BitOrder::LsbFirst,
+                count: 7,
+            })
+            .unwrap()
+            // The NACK of an I2C master, the high bit goes out first.
+            .with(&MpsseCommand::WriteBits {
+                edge: ClockEdge::Falling,
+                order: BitOrder::MsbFirst,
+                count: 1,
+                data: 0x80,
+            })
+            .unwrap()
+            .with(&MpsseCommand::SendImmediate)
+            .unwrap()
+            .into_bytes();
+
+        assert_eq!(
+            Listing(&bytes).to_string(),
+            "0000  SET LOW value=0x08 dir=0x0b\n\
+             0003  TMS 3 bits 0b011 TDI=1\n\
+             0006  DATA OUT 4096 bytes LSB -ve\n\
+             1009  DATA IN 7 bits LSB +ve\n\
+             100b  DATA OUT 1 bits 0b1 MSB -ve\n\
+             100e  SEND IMMEDIATE"
+        );
+        assert_eq!(response_len(&bytes), Ok(1));
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        // Every valid opcode decodes to a command that encodes to the same bytes.
+        for opcode in 0..=0xff {
+            let bytes = [opcode, 1, 0, 0xa5, 0x5a, 0x12];
+            let Ok((command, len)) = decode(&bytes) else {
+                continue;
+            };
+            let mut encoded = vec![];
+            command.encode(&mut encoded).unwrap();
+            assert_eq!(encoded, bytes[..len], "{command}");
+        }
+        // The shift commands of JTAG adapters.
+        for opcode in [0x19, 0x1b, 0x28, 0x2a, 0x39, 0x3b, 0x4b, 0x6b] {
+            assert!(decode(&[opcode, 0, 0, 0]).is_ok(), "{opcode:#04x}");
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(
+            decode(&[0x19, 3, 0, 1, 2]),
+            Err(DecodeError::Truncated {
+                opcode: 0x19,
+                needed: 7,
+                available: 5
+            })
+        );
+        assert_eq!(decode(&[0x42, 0, 0]), Err(DecodeError::BadOpcode(0x42)));
+        assert_eq!(
+            decode(&[0x4b, 7, 0]),
+            Err(DecodeError::BadBitCount {
+                opcode: 0x4b,
+                count: 8,
+                max: 7
+            })
+        );
+
+        let bytes = [0xab, 0x87, 0x80, 0];
+        assert_eq!(
+            Listing(&bytes).to_string(),
+            "0000  !! bad opcode 0xab\n\
+             0001  SEND IMMEDIATE\n\
+             0002  !! command 0x80 needs 3 bytes, only 2 left"
+        );
+        assert!(response_len(&bytes).is_err());
+        assert_eq!(response_len(&bytes[..2]), Ok(2));
+    }
+}
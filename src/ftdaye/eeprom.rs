@@ -0,0 +1,842 @@
+//! The configuration EEPROM of FTDI chips.
+//!
+//! [`Eeprom`] holds a raw image as read from the chip and knows its per-chip layout, which
+//! follows libftdi's `ftdi_eeprom_decode`/`ftdi_eeprom_build`. Decoding it gives an
+//! [`EepromConfig`], which can be modified and encoded back into the image. Encoding only
+//! touches the fields it knows about, so vendor specific bits survive a round trip.
+//!
+//! The FT2232H, FT4232H, FT232H, FT232R and FT-X series are supported.
+
+use super::{error::FtdiError, ChipType};
+
+/// A raw EEPROM image, tied to the chip type that defines its layout.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Eeprom {
+    chip_type: ChipType,
+    data: Vec<u8>,
+}
+
+impl std::fmt::Debug for Eeprom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Eeprom")
+            .field("chip_type", &self.chip_type)
+            .field("size", &self.data.len())
+            .field("checksum_valid", &self.is_checksum_valid())
+            .finish()
+    }
+}
+
+/// The decoded contents of an [`Eeprom`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EepromConfig {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// The `bcdDevice` the chip reports, which is also used to detect the chip type.
+    pub release: u16,
+    pub self_powered: bool,
+    pub remote_wakeup: bool,
+    /// Maximum bus current, in mA.
+    pub max_power_ma: u16,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    /// Whether the serial number string is reported to the host.
+    pub use_serial: bool,
+    /// Whether pull-downs are enabled on the IO pins during USB suspend.
+    pub suspend_pull_downs: bool,
+    /// One entry per interface.
+    pub channels: Vec<ChannelConfig>,
+    /// One entry per IO pin group, in the order the datasheet lists them.
+    pub drive: Vec<PinGroupConfig>,
+    /// One entry per CBUS pin.
+    pub cbus: Vec<CbusFunction>,
+    /// Free space the chip does not interpret, without trailing zeros.
+    pub user_area: Vec<u8>,
+}
+
+/// The configuration of one interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelConfig {
+    pub channel_type: ChannelType,
+    /// Whether the host should load the virtual COM port driver instead of D2XX.
+    pub vcp_driver: bool,
+    /// Whether the TXDEN pin is driven for an RS485 transceiver (FT4232H only).
+    pub rs485: bool,
+}
+
+/// The function an interface comes up in after reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelType {
+    Uart,
+    /// FT245 style asynchronous FIFO.
+    Fifo,
+    /// Fast opto-isolated serial.
+    Opto,
+    /// FT245 style FIFO with CPU bus timing.
+    CpuFifo,
+    Ft1284,
+}
+
+impl ChannelType {
+    fn from_bits(bits: u8) -> Result<Self, FtdiError> {
+        Ok(match bits {
+            0 => ChannelType::Uart,
+            1 => ChannelType::Fifo,
+            2 => ChannelType::Opto,
+            4 => ChannelType::CpuFifo,
+            8 => ChannelType::Ft1284,
+            _ => Err(FtdiError::Other(format!("unknown channel type {bits:#x}")))?,
+        })
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            ChannelType::Uart => 0,
+            ChannelType::Fifo => 1,
+            ChannelType::Opto => 2,
+            ChannelType::CpuFifo => 4,
+            ChannelType::Ft1284 => 8,
+        }
+    }
+}
+
+/// Output driver settings of a group of IO pins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PinGroupConfig {
+    /// Drive strength in mA, one of 4, 8, 12 or 16.
+    pub drive_ma: u8,
+    pub slow_slew: bool,
+    pub schmitt: bool,
+}
+
+impl PinGroupConfig {
+    fn from_nibble(nibble: u8) -> Self {
+        Self {
+            drive_ma: 4 * ((nibble & 0x3) + 1),
+            slow_slew: nibble & 0x4 != 0,
+            schmitt: nibble & 0x8 != 0,
+        }
+    }
+
+    fn nibble(self) -> Result<u8, FtdiError> {
+        let drive = match self.drive_ma {
+            4 => 0,
+            8 => 1,
+            12 => 2,
+            16 => 3,
+            ma => Err(FtdiError::Other(format!(
+                "unsupported drive strength {ma} mA"
+            )))?,
+        };
+        Ok(drive | (self.slow_slew as u8) << 2 | (self.schmitt as u8) << 3)
+    }
+}
+
+/// The function of a CBUS pin.
+///
+/// Not every function is available on every chip, and the same function has a different
+/// encoding on each chip family.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CbusFunction {
+    Tristate,
+    TxLed,
+    RxLed,
+    TxRxLed,
+    PwrEn,
+    Sleep,
+    Drive0,
+    Drive1,
+    /// The pin is a GPIO, controlled in CBUS bitbang mode.
+    IoMode,
+    TxDen,
+    Clk48,
+    Clk30,
+    Clk24,
+    Clk15,
+    Clk12,
+    Clk7_5,
+    Clk6,
+    BitbangWr,
+    BitbangRd,
+    BcdCharger,
+    BcdChargerN,
+    I2cTxe,
+    I2cRxf,
+    VbusSense,
+    TimeStamp,
+    KeepAwake,
+}
+
+impl CbusFunction {
+    /// The CBUS functions of `chip_type`, indexed by their encoding.
+    fn table(chip_type: ChipType) -> &'static [CbusFunction] {
+        use CbusFunction::*;
+        match chip_type {
+            ChipType::R => &[
+                TxDen, PwrEn, RxLed, TxLed, TxRxLed, Sleep, Clk48, Clk24, Clk12, Clk6, IoMode,
+                BitbangWr, BitbangRd,
+            ],
+            ChipType::FT232H => &[
+                Tristate, TxLed, RxLed, TxRxLed, PwrEn, Sleep, Drive0, Drive1, IoMode, TxDen,
+                Clk30, Clk15, Clk7_5,
+            ],
+            ChipType::FT230X => &[
+                Tristate,
+                TxLed,
+                RxLed,
+                TxRxLed,
+                PwrEn,
+                Sleep,
+                Drive0,
+                Drive1,
+                IoMode,
+                TxDen,
+                Clk24,
+                Clk12,
+                Clk6,
+                BcdCharger,
+                BcdChargerN,
+                I2cTxe,
+                I2cRxf,
+                VbusSense,
+                BitbangWr,
+                BitbangRd,
+                TimeStamp,
+                KeepAwake,
+            ],
+            _ => &[],
+        }
+    }
+
+    fn from_bits(chip_type: ChipType, bits: u8) -> Result<Self, FtdiError> {
+        Self::table(chip_type)
+            .get(bits as usize)
+            .copied()
+            .ok_or_else(|| FtdiError::Other(format!("unknown CBUS function {bits:#x}")))
+    }
+
+    fn bits(self, chip_type: ChipType) -> Result<u8, FtdiError> {
+        Self::table(chip_type)
+            .iter()
+            .position(|&f| f == self)
+            .map(|i| i as u8)
+            .ok_or_else(|| {
+                FtdiError::Other(format!(
+                    "CBUS function {self:?} not available on {chip_type:?}"
+                ))
+            })
+    }
+}
+
+// Byte offsets shared by all supported chips.
+const VENDOR_ID: usize = 0x02;
+const PRODUCT_ID: usize = 0x04;
+const RELEASE: usize = 0x06;
+const ATTRIBUTES: usize = 0x08;
+const MAX_POWER: usize = 0x09;
+const CHIP_CONFIG: usize = 0x0a;
+const DRIVE: usize = 0x0c;
+/// Offset/length pairs of the manufacturer, product and serial number strings.
+const STRINGS: [usize; 3] = [0x0e, 0x10, 0x12];
+
+const SELF_POWERED: u8 = 0x40;
+const REMOTE_WAKEUP: u8 = 0x20;
+const SUSPEND_PULL_DOWNS: u8 = 0x04;
+const USE_SERIAL: u8 = 0x08;
+const DRIVER_VCP: u8 = 0x08;
+const DRIVER_VCP_232H: u8 = 0x10;
+const RS485_4232H: usize = 0x0b;
+
+/// The FT-X user area lives in the MTP memory and is not covered by the checksum.
+const USER_AREA_X: std::ops::Range<usize> = 0x24..0x80;
+
+impl Eeprom {
+    /// Wraps a raw image of the EEPROM of a `chip_type` chip.
+    ///
+    /// The image must be 128 or 256 bytes, the sizes of the 93C46 and 93C56 EEPROMs.
+    pub fn new(chip_type: ChipType, data: Vec<u8>) -> Result<Self, FtdiError> {
+        let min_size = match chip_type {
+            ChipType::R | ChipType::FT2232H | ChipType::FT4232H | ChipType::FT232H => 128,
+            ChipType::FT230X => 256,
+            _ => Err(FtdiError::UnsupportedChipType(chip_type))?,
+        };
+
+        if data.len() < min_size || !matches!(data.len(), 128 | 256) {
+            Err(FtdiError::Other(format!(
+                "invalid EEPROM size {} for {:?}",
+                data.len(),
+                chip_type
+            )))?
+        }
+
+        Ok(Self { chip_type, data })
+    }
+
+    /// Creates an erased image of `size` bytes.
+    pub fn blank(chip_type: ChipType, size: usize) -> Result<Self, FtdiError> {
+        Self::new(chip_type, vec![0xff; size])
+    }
+
+    pub fn chip_type(&self) -> ChipType {
+        self.chip_type
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the 16-bit word at word address `addr`.
+    pub fn word(&self, addr: usize) -> u16 {
+        u16::from_le_bytes([self.data[2 * addr], self.data[2 * addr + 1]])
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.data.iter().all(|&b| b == 0xff)
+    }
+
+    /// Computes the checksum over the image, which the chip expects in the last word.
+    pub fn checksum(&self) -> u16 {
+        let words = self.data.len() / 2 - 1;
+        (0..words)
+            .filter(|&i| !(self.chip_type == ChipType::FT230X && (0x12..0x40).contains(&i)))
+            .fold(0xaaaa, |checksum: u16, i| {
+                (checksum ^ self.word(i)).rotate_left(1)
+            })
+    }
+
+    pub fn stored_checksum(&self) -> u16 {
+        self.word(self.data.len() / 2 - 1)
+    }
+
+    pub fn is_checksum_valid(&self) -> bool {
+        self.checksum() == self.stored_checksum()
+    }
+
+    /// Recomputes the checksum after the image has been modified.
+    pub fn update_checksum(&mut self) {
+        let checksum = self.checksum();
+        let len = self.data.len();
+        self.data[len - 2..].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    /// Decodes the image. The checksum is not checked, so broken images can be repaired.
+    pub fn decode(&self) -> Result<EepromConfig, FtdiError> {
+        if self.is_blank() {
+            Err(FtdiError::Other("EEPROM is blank".to_string()))?
+        }
+
+        let d = &self.data;
+        let word = |offset: usize| u16::from_le_bytes([d[offset], d[offset + 1]]);
+
+        let [manufacturer, product, serial_number] = STRINGS.map(|at| self.decode_string(at));
+
+        Ok(EepromConfig {
+            vendor_id: word(VENDOR_ID),
+            product_id: word(PRODUCT_ID),
+            release: word(RELEASE),
+            self_powered: d[ATTRIBUTES] & SELF_POWERED != 0,
+            remote_wakeup: d[ATTRIBUTES] & REMOTE_WAKEUP != 0,
+            max_power_ma: d[MAX_POWER] as u16 * 2,
+            manufacturer: manufacturer?,
+            product: product?,
+            serial_number: serial_number?,
+            use_serial: d[CHIP_CONFIG] & USE_SERIAL != 0,
+            suspend_pull_downs: d[CHIP_CONFIG] & SUSPEND_PULL_DOWNS != 0,
+            channels: self.decode_channels()?,
+            drive: (0..self.drive_groups())
+                .map(|i| PinGroupConfig::from_nibble(self.nibble(DRIVE, i)))
+                .collect(),
+            cbus: self.decode_cbus()?,
+            user_area: {
+                // Trailing zeros can't be told apart from free space.
+                let user_area = &self.data[self.user_area()?];
+                let len = user_area.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+                user_area[..len].to_vec()
+            },
+        })
+    }
+
+    /// Writes `config` into the image and updates the checksum.
+    ///
+    /// Fails without modifying the image if `config` can't be represented on this chip.
+    pub fn encode(&mut self, config: &EepromConfig) -> Result<(), FtdiError> {
+        let mut new = self.clone();
+        new.encode_into(config)?;
+        *self = new;
+        Ok(())
+    }
+
+    fn encode_into(&mut self, config: &EepromConfig) -> Result<(), FtdiError> {
+        if self.is_blank() {
+            // Start from a clean slate rather than with every flag set.
+            self.data.fill(0);
+        }
+
+        let d = &mut self.data;
+        d[VENDOR_ID..][..2].copy_from_slice(&config.vendor_id.to_le_bytes());
+        d[PRODUCT_ID..][..2].copy_from_slice(&config.product_id.to_le_bytes());
+        d[RELEASE..][..2].copy_from_slice(&config.release.to_le_bytes());
+
+        // Bit 7 is reserved and must be set.
+        d[ATTRIBUTES] = 0x80
+            | if config.self_powered { SELF_POWERED } else { 0 }
+            | if config.remote_wakeup {
+                REMOTE_WAKEUP
+            } else {
+                0
+            };
+        d[MAX_POWER] = (config.max_power_ma / 2).min(0xff) as u8;
+        set_bits(&mut d[CHIP_CONFIG], USE_SERIAL, config.use_serial);
+        set_bits(
+            &mut d[CHIP_CONFIG],
+            SUSPEND_PULL_DOWNS,
+            config.suspend_pull_downs,
+        );
+
+        self.encode_channels(&config.channels)?;
+
+        if config.drive.len() != self.drive_groups() {
+            Err(FtdiError::Other(format!(
+                "{:?} has {} IO pin groups",
+                self.chip_type,
+                self.drive_groups()
+            )))?
+        }
+        for (i, group) in config.drive.iter().enumerate() {
+            self.set_nibble(DRIVE, i, group.nibble()?);
+        }
+
+        self.encode_cbus(&config.cbus)?;
+        self.encode_strings(config)?;
+        self.update_checksum();
+
+        Ok(())
+    }
+
+    /// Returns where the string descriptors start.
+    fn strings_start(&self) -> usize {
+        let start = match self.chip_type {
+            ChipType::R => 0x98,
+            ChipType::FT2232H | ChipType::FT4232H => 0x9a,
+            _ => 0xa0,
+        };
+        start & (self.data.len() - 1)
+    }
+
+    fn decode_string(&self, at: usize) -> Result<Option<String>, FtdiError> {
+        let offset = self.data[at] as usize & (self.data.len() - 1);
+        let len = self.data[at + 1] as usize;
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let descriptor = self
+            .data
+            .get(offset..offset + len)
+            .filter(|descriptor| len >= 2 && descriptor[1] == 0x03)
+            .ok_or_else(|| FtdiError::Other(format!("invalid string descriptor at {offset:#x}")))?;
+
+        let utf16: Vec<u16> = descriptor[2..]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16(&utf16)
+            .map(Some)
+            .map_err(|e| FtdiError::Other(format!("invalid string descriptor: {e}")))
+    }
+
+    fn encode_strings(&mut self, config: &EepromConfig) -> Result<(), FtdiError> {
+        let checksum_at = self.data.len() - 2;
+        let strings_end = match self.chip_type {
+            ChipType::FT230X => checksum_at,
+            // The user area follows the strings.
+            _ => checksum_at.saturating_sub(config.user_area.len()),
+        };
+
+        let mut offset = self.strings_start();
+        let strings = [&config.manufacturer, &config.product, &config.serial_number];
+        for (at, string) in STRINGS.into_iter().zip(strings) {
+            let Some(string) = string else {
+                self.data[at..at + 2].fill(0);
+                continue;
+            };
+
+            let mut descriptor = vec![0, 0x03];
+            descriptor.extend(string.encode_utf16().flat_map(u16::to_le_bytes));
+            descriptor[0] = descriptor.len() as u8;
+            if descriptor.len() > 0xff || offset + descriptor.len() > strings_end {
+                Err(FtdiError::Other(
+                    "strings and user area do not fit in the EEPROM".to_string(),
+                ))?
+            }
+
+            self.data[at] = offset as u8 | 0x80;
+            self.data[at + 1] = descriptor.len() as u8;
+            self.data[offset..][..descriptor.len()].copy_from_slice(&descriptor);
+            offset += descriptor.len();
+        }
+        self.data[offset..strings_end].fill(0);
+
+        let user_area = self.user_area()?;
+        if config.user_area.len() > user_area.len() {
+            Err(FtdiError::Other(format!(
+                "user area is limited to {} bytes",
+                user_area.len()
+            )))?
+        }
+        self.data[user_area.clone()].fill(0);
+        self.data[user_area.start..][..config.user_area.len()].copy_from_slice(&config.user_area);
+
+        Ok(())
+    }
+
+    fn user_area(&self) -> Result<std::ops::Range<usize>, FtdiError> {
+        if self.chip_type == ChipType::FT230X {
+            return Ok(USER_AREA_X);
+        }
+
+        // Everything between the last string and the checksum.
+        let mut start = self.strings_start();
+        for at in STRINGS {
+            let offset = self.data[at] as usize & (self.data.len() - 1);
+            let len = self.data[at + 1] as usize;
+            if len > 0 {
+                start = start.max(offset + len);
+            }
+        }
+
+        let end = self.data.len() - 2;
+        if start > end {
+            Err(FtdiError::Other(
+                "string descriptors overlap the checksum".to_string(),
+            ))?
+        }
+        Ok(start..end)
+    }
+
+    fn decode_channels(&self) -> Result<Vec<ChannelConfig>, FtdiError> {
+        let d = &self.data;
+        let channel = |channel_type, vcp_driver| ChannelConfig {
+            channel_type,
+            vcp_driver,
+            rs485: false,
+        };
+
+        Ok(match self.chip_type {
+            ChipType::FT2232H => vec![
+                channel(ChannelType::from_bits(d[0] & 0x7)?, d[0] & DRIVER_VCP != 0),
+                channel(ChannelType::from_bits(d[1] & 0x7)?, d[1] & DRIVER_VCP != 0),
+            ],
+            ChipType::FT4232H => (0..4)
+                .map(|i| ChannelConfig {
+                    channel_type: ChannelType::Uart,
+                    // Channels C and D use the high nibbles.
+                    vcp_driver: d[i % 2] & (DRIVER_VCP << (4 * (i / 2))) != 0,
+                    rs485: d[RS485_4232H] & (0x10 << i) != 0,
+                })
+                .collect(),
+            ChipType::FT232H => vec![channel(
+                ChannelType::from_bits(d[0] & 0xf)?,
+                d[0] & DRIVER_VCP_232H != 0,
+            )],
+            _ => vec![channel(ChannelType::Uart, d[0] & DRIVER_VCP != 0)],
+        })
+    }
+
+    fn encode_channels(&mut self, channels: &[ChannelConfig]) -> Result<(), FtdiError> {
+        let count = match self.chip_type {
+            ChipType::FT2232H => 2,
+            ChipType::FT4232H => 4,
+            _ => 1,
+        };
+        if channels.len() != count {
+            Err(FtdiError::Other(format!(
+                "{:?} has {} channels",
+                self.chip_type, count
+            )))?
+        }
+
+        let only_uart = matches!(
+            self.chip_type,
+            ChipType::FT4232H | ChipType::R | ChipType::FT230X
+        );
+        for channel in channels {
+            if only_uart && channel.channel_type != ChannelType::Uart
+                || self.chip_type != ChipType::FT232H && channel.channel_type == ChannelType::Ft1284
+            {
+                Err(FtdiError::Other(format!(
+                    "channel type {:?} not available on {:?}",
+                    channel.channel_type, self.chip_type
+                )))?
+            }
+            if channel.rs485 && self.chip_type != ChipType::FT4232H {
+                Err(FtdiError::Other(format!(
+                    "RS485 mode not available on {:?}",
+                    self.chip_type
+                )))?
+            }
+        }
+
+        let d = &mut self.data;
+        match self.chip_type {
+            ChipType::FT2232H => {
+                for (byte, channel) in d.iter_mut().zip(channels) {
+                    *byte = (*byte & !0x0f) | channel.channel_type.bits();
+                    set_bits(byte, DRIVER_VCP, channel.vcp_driver);
+                }
+            }
+            ChipType::FT4232H => {
+                for (i, channel) in channels.iter().enumerate() {
+                    set_bits(
+                        &mut d[i % 2],
+                        DRIVER_VCP << (4 * (i / 2)),
+                        channel.vcp_driver,
+                    );
+                    set_bits(&mut d[RS485_4232H], 0x10 << i, channel.rs485);
+                }
+            }
+            ChipType::FT232H => {
+                d[0] = (d[0] & !0x0f) | channels[0].channel_type.bits();
+                set_bits(&mut d[0], DRIVER_VCP_232H, channels[0].vcp_driver);
+            }
+            _ => set_bits(&mut d[0], DRIVER_VCP, channels[0].vcp_driver),
+        }
+
+        Ok(())
+    }
+
+    fn drive_groups(&self) -> usize {
+        match self.chip_type {
+            ChipType::FT2232H | ChipType::FT4232H => 4,
+            ChipType::FT232H | ChipType::FT230X => 2,
+            _ => 0,
+        }
+    }
+
+    /// Returns where the CBUS functions start and how many pins there are.
+    fn cbus_layout(&self) -> (usize, usize) {
+        match self.chip_type {
+            ChipType::R => (0x14, 5),
+            ChipType::FT232H => (0x18, 10),
+            ChipType::FT230X => (0x1a, 4),
+            _ => (0, 0),
+        }
+    }
+
+    fn decode_cbus(&self) -> Result<Vec<CbusFunction>, FtdiError> {
+        let (start, count) = self.cbus_layout();
+        (0..count)
+            .map(|i| {
+                let bits = match self.chip_type {
+                    // One byte per pin.
+                    ChipType::FT230X => self.data[start + i],
+                    _ => self.nibble(start, i),
+                };
+                CbusFunction::from_bits(self.chip_type, bits)
+            })
+            .collect()
+    }
+
+    fn encode_cbus(&mut self, cbus: &[CbusFunction]) -> Result<(), FtdiError> {
+        let (start, count) = self.cbus_layout();
+        if cbus.len() != count {
+            Err(FtdiError::Other(format!(
+                "{:?} has {} CBUS pins",
+                self.chip_type, count
+            )))?
+        }
+
+        for (i, function) in cbus.iter().enumerate() {
+            let bits = function.bits(self.chip_type)?;
+            match self.chip_type {
+                ChipType::FT230X => self.data[start + i] = bits,
+                _ => self.set_nibble(start, i, bits),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `i`th nibble starting at byte `start`, low nibble first.
+    fn nibble(&self, start: usize, i: usize) -> u8 {
+        (self.data[start + i / 2] >> (4 * (i % 2))) & 0xf
+    }
+
+    fn set_nibble(&mut self, start: usize, i: usize, value: u8) {
+        let shift = 4 * (i % 2);
+        let byte = &mut self.data[start + i / 2];
+        *byte = (*byte & !(0xf << shift)) | (value & 0xf) << shift;
+    }
+}
+
+fn set_bits(byte: &mut u8, mask: u8, set: bool) {
+    if set {
+        *byte |= mask;
+    } else {
+        *byte &= !mask;
+    }
+}
+
+/// Returns the configuration of an FT2232H with a FIFO and a VCP UART channel, for tests.
+#[cfg(test)]
+pub(crate) fn ft2232h_config() -> EepromConfig {
+    EepromConfig {
+        vendor_id: 0x0403,
+        product_id: 0x6010,
+        release: 0x0700,
+        self_powered: false,
+        remote_wakeup: false,
+        max_power_ma: 500,
+        manufacturer: Some("Digilent".to_string()),
+        product: Some("Digilent USB Device".to_string()),
+        serial_number: Some("210319B0C0DE".to_string()),
+        use_serial: true,
+        suspend_pull_downs: false,
+        channels: vec![
+            ChannelConfig {
+                channel_type: ChannelType::Fifo,
+                vcp_driver: false,
+                rs485: false,
+            },
+            ChannelConfig {
+                channel_type: ChannelType::Uart,
+                vcp_driver: true,
+                rs485: false,
+            },
+        ],
+        drive: vec![
+            PinGroupConfig {
+                drive_ma: 4,
+                slow_slew: false,
+                schmitt: false,
+            };
+            4
+        ],
+        cbus: vec![],
+        user_area: vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::transport::{ControlRequest, MemoryTransport};
+
+    #[test]
+    fn test_checksum() {
+        let mut eeprom = Eeprom::new(ChipType::FT2232H, vec![0; 256]).unwrap();
+        // 127 words of zeros only rotate the initial value.
+        assert_eq!(eeprom.checksum(), 0x5555);
+
+        eeprom.update_checksum();
+        assert!(eeprom.is_checksum_valid());
+        assert_eq!(eeprom.as_bytes()[254..], [0x55, 0x55]);
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut eeprom = Eeprom::blank(ChipType::FT2232H, 256).unwrap();
+        let mut config = ft2232h_config();
+        eeprom.encode(&config).unwrap();
+
+        assert!(eeprom.is_checksum_valid());
+        assert_eq!(eeprom.as_bytes()[0x0e..0x10], [0x9a, 0x12]);
+        assert_eq!(eeprom.as_bytes()[0x9a..0x9e], [0x12, 0x03, b'D', 0]);
+        assert_eq!(eeprom.decode().unwrap(), config);
+
+        config.product = Some("Relabelled".to_string());
+        config.user_area = vec![1, 2, 3];
+        eeprom.encode(&config).unwrap();
+        assert_eq!(eeprom.decode().unwrap(), config);
+    }
+
+    #[test]
+    fn test_encode_cbus() {
+        let mut eeprom = Eeprom::blank(ChipType::FT232H, 256).unwrap();
+        let config = EepromConfig {
+            product_id: 0x6014,
+            release: 0x0900,
+            channels: vec![ChannelConfig {
+                channel_type: ChannelType::Ft1284,
+                vcp_driver: true,
+                rs485: false,
+            }],
+            drive: ft2232h_config().drive[..2].to_vec(),
+            cbus: vec![CbusFunction::IoMode; 10],
+            ..ft2232h_config()
+        };
+        eeprom.encode(&config).unwrap();
+        assert_eq!(eeprom.as_bytes()[0x18..0x1d], [0x88; 5]);
+        assert_eq!(eeprom.decode().unwrap(), config);
+
+        let invalid = EepromConfig {
+            cbus: vec![CbusFunction::Clk48; 10],
+            ..config
+        };
+        assert!(eeprom.encode(&invalid).is_err());
+        assert!(eeprom.is_checksum_valid());
+    }
+
+    #[test]
+    fn test_strings_overflow() {
+        let mut eeprom = Eeprom::blank(ChipType::R, 128).unwrap();
+        let config = EepromConfig {
+            product: Some("x".repeat(100)),
+            channels: vec![ChannelConfig {
+                channel_type: ChannelType::Uart,
+                vcp_driver: true,
+                rs485: false,
+            }],
+            drive: vec![],
+            cbus: vec![CbusFunction::TxLed; 5],
+            ..ft2232h_config()
+        };
+        assert!(eeprom.encode(&config).is_err());
+        assert!(eeprom.is_blank());
+    }
+
+    #[test]
+    fn test_device_eeprom() {
+        let transport = MemoryTransport::default();
+        let mut device = transport.open();
+
+        let mut eeprom = device.read_eeprom().unwrap();
+        assert!(eeprom.is_blank());
+        assert_eq!(eeprom.as_bytes().len(), 256);
+
+        let mut config = ft2232h_config();
+        eeprom.encode(&config).unwrap();
+        device.set_latency_timer(2).unwrap();
+        transport.take_control_requests();
+        device.write_eeprom(&eeprom).unwrap();
+        assert_eq!(transport.eeprom(), eeprom.as_bytes());
+        // The latency timer set by the unlock sequence is put back.
+        assert_eq!(
+            transport.take_control_requests().last(),
+            Some(&ControlRequest {
+                request: 0x09,
+                value: 2,
+                index: 1
+            })
+        );
+        assert_eq!(device.read_eeprom().unwrap().decode().unwrap(), config);
+
+        // A stale checksum is refused.
+        config.product = Some("Relabelled".to_string());
+        eeprom.encode(&config).unwrap();
+        device.write_eeprom_word(0x7f, 0).unwrap();
+        assert!(!device.read_eeprom().unwrap().is_checksum_valid());
+        let mut corrupted = eeprom.as_bytes().to_vec();
+        corrupted[0x30] ^= 1;
+        let corrupted = Eeprom::new(ChipType::FT2232H, corrupted).unwrap();
+        assert!(device.write_eeprom(&corrupted).is_err());
+
+        device.erase_eeprom().unwrap();
+        assert!(device.read_eeprom().unwrap().is_blank());
+
+        // A 93C46 mirrors its contents above 128 bytes.
+        let transport = MemoryTransport::default().with_eeprom(&[0x55; 128]);
+        let mut device = transport.open();
+        assert_eq!(device.read_eeprom().unwrap().as_bytes(), [0x55; 128]);
+    }
+}
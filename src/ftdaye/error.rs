@@ -1,6 +1,6 @@
 use nusb::descriptors::ActiveConfigurationError;
 
-use crate::ftdaye::ChipType;
+use crate::ftdaye::{eeprom::CbusFunction, ChipType, Interface};
 #[derive(Debug, thiserror::Error)]
 pub enum FtdiError {
     #[error("A USB transport error occurred.")]
@@ -9,16 +9,83 @@ pub enum FtdiError {
     /// operation. It may indicate that the USB device was unplugged, that another application or an
     /// operating system driver is currently using it, or that the current user does not have
     /// permission to access it.
-    Usb(#[from] nusb::Error),
+    Usb(nusb::Error),
 
     #[error("Unsupported chip type: {0:?}")]
     /// The connected device is not supported by the driver.
     UnsupportedChipType(ChipType),
 
+    #[error("{0:?} has no MPSSE on interface {1:?}")]
+    /// The interface can't be used for JTAG, e.g. C or D of an FT4232H.
+    NoMpsse(ChipType, Interface),
+
+    #[error("Unsupported baud rate: {0}")]
+    /// The chip can't get within 5% of the requested baud rate.
+    UnsupportedBaudRate(u32),
+
+    #[error("CBUS pin {pin} is configured as {function:?} in the EEPROM, not as GPIO")]
+    /// A CBUS pin was used as GPIO while the EEPROM assigns it another function.
+    CbusPinNotGpio { pin: u8, function: CbusFunction },
+
     #[error("Failed to get active configuration")]
     ActiveConfigurationError(#[source] ActiveConfigurationError),
 
+    #[error("MPSSE rejected bad command {0:#04x}")]
+    /// The MPSSE answered with `0xFA` and the opcode, so the commands sent didn't match what it
+    /// expects and the reply is garbage.
+    BadCommand(u8),
+
+    #[error("MPSSE transfer length {0} is out of range 1..=65536")]
+    /// A single MPSSE data command can only clock 1 to 65536 bytes.
+    TransferLength(usize),
+
+    #[error("MPSSE bit count {count} is out of range 1..={max}")]
+    /// A single MPSSE bit command can only clock 1 to 8 bits, or 1 to 7 on TMS.
+    BitCount { count: u8, max: u8 },
+
+    #[error("I2C device {address:#x} didn't acknowledge its address")]
+    /// No I2C device answered to the address.
+    I2cAddressNack { address: u16 },
+
+    #[error("I2C device {address:#x} didn't acknowledge byte {index} written to it")]
+    /// The I2C device refused a byte, `index` counts from the start of the write.
+    I2cDataNack { address: u16, index: usize },
+
+    #[error("I2C bus is stuck, SDA is held low")]
+    /// A device kept SDA low even after the bus was clocked to recover it.
+    I2cBusStuck,
+
+    #[error("Receive buffer overrun")]
+    /// The chip's receive buffer overflowed before the host read it, so data was lost.
+    Overrun,
+
+    #[error("Device was reconnected")]
+    /// The device disappeared and was opened again, losing the state of the chip and anything
+    /// connected to it. Only reported when reconnecting is enabled on the
+    /// [`Builder`](super::Builder).
+    Reconnected,
+
+    #[error("USB transfer failed and the channel was recovered (state lost: {state_lost})")]
+    /// A bulk transfer failed, the channel was recovered but buffered data was dropped. If
+    /// `state_lost` is set, the MPSSE had to be resynchronised and commands in flight may or
+    /// may not have run, so e.g. the JTAG TAP state is unknown. Only reported when recovery is
+    /// enabled on the [`Builder`](super::Builder).
+    Recovered { state_lost: bool },
+
     #[error("{0}")]
     /// An unspecified error occurred.
     Other(String),
 }
+
+impl From<nusb::Error> for FtdiError {
+    /// Unwraps driver errors that had to travel through an [`std::io::Error`], e.g. from
+    /// [`std::io::Read`].
+    fn from(err: nusb::Error) -> Self {
+        if err.get_ref().is_some_and(|e| e.is::<FtdiError>()) {
+            let inner = err.into_inner().unwrap();
+            return *inner.downcast::<FtdiError>().unwrap();
+        }
+
+        FtdiError::Usb(err)
+    }
+}
@@ -0,0 +1,239 @@
+//! Streaming through the FT245 style synchronous FIFO of the FT2232H and FT232H.
+
+use std::time::{Duration, Instant};
+
+use async_io::block_on;
+use log::info;
+
+use crate::ftdaye::{error::FtdiError, BitMode, ChipType, Device, Interface, Result};
+
+/// Counters of a [`SyncFifo`], per stream and in total.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FifoStats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Time spent in read and write streams.
+    pub read_time: Duration,
+    pub write_time: Duration,
+    /// Packets flagged by the chip because its receive buffer overflowed, losing data.
+    pub overruns: u64,
+}
+
+impl FifoStats {
+    /// Returns the sustained read rate, in bytes per second, or 0 if nothing was timed.
+    pub fn read_throughput(&self) -> f64 {
+        throughput(self.bytes_read, self.read_time)
+    }
+
+    /// Returns the sustained write rate, in bytes per second, or 0 if nothing was timed.
+    pub fn write_throughput(&self) -> f64 {
+        throughput(self.bytes_written, self.write_time)
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.bytes_read += other.bytes_read;
+        self.bytes_written += other.bytes_written;
+        self.read_time += other.read_time;
+        self.write_time += other.write_time;
+        self.overruns += other.overruns;
+    }
+}
+
+/// Returns `bytes` per second over `time`, or 0 if no time was measured.
+fn throughput(bytes: u64, time: Duration) -> f64 {
+    if time.is_zero() {
+        return 0.0;
+    }
+    bytes as f64 / time.as_secs_f64()
+}
+
+/// A channel in synchronous 245 FIFO mode.
+///
+/// Only interface A of the FT2232H and the FT232H support it, and the EEPROM has to set the
+/// channel type to FIFO. Streams keep as many bulk transfers in flight as the read queue depth
+/// set on the [`Builder`](super::Builder), and with it the transfer size.
+#[derive(Debug)]
+pub struct SyncFifo {
+    device: Device,
+    stats: FifoStats,
+}
+
+impl SyncFifo {
+    /// Switches `device` to synchronous FIFO mode.
+    pub fn new(device: Device) -> Result<Self> {
+        block_on(Self::new_async(device))
+    }
+
+    pub async fn new_async(mut device: Device) -> Result<Self> {
+        match (device.chip_type(), device.context.interface) {
+            (Some(ChipType::FT2232H | ChipType::FT232H), Interface::A) => {}
+            (Some(chip_type @ (ChipType::FT2232H | ChipType::FT232H)), interface) => {
+                Err(FtdiError::Other(format!(
+                    "{chip_type:?} only has a synchronous FIFO on interface A, not {interface:?}"
+                )))?
+            }
+            (Some(chip_type), _) => Err(FtdiError::UnsupportedChipType(chip_type))?,
+            (None, _) => Err(FtdiError::Other("unknown chip type".to_string()))?,
+        }
+
+        device.set_bitmode_async(0xff, BitMode::Reset).await?;
+        device.set_bitmode_async(0xff, BitMode::SyncFf).await?;
+        device.set_latency_timer_async(2).await?;
+        device.usb_purge_buffers_async().await?;
+
+        Ok(Self {
+            device,
+            stats: FifoStats::default(),
+        })
+    }
+
+    /// Returns the totals over all streams so far.
+    pub fn stats(&self) -> FifoStats {
+        self.stats
+    }
+
+    /// Passes the received data to `sink` until it returns `false`.
+    pub fn read_stream(&mut self, sink: impl FnMut(&[u8]) -> bool + Send) -> Result<FifoStats> {
+        block_on(self.read_stream_async(sink))
+    }
+
+    pub async fn read_stream_async(
+        &mut self,
+        mut sink: impl FnMut(&[u8]) -> bool + Send,
+    ) -> Result<FifoStats> {
+        let mut stats = FifoStats::default();
+        let start = Instant::now();
+        let mut counting_sink = |data: &[u8]| {
+            stats.bytes_read += data.len() as u64;
+            sink(data)
+        };
+
+        let overruns = self.device.context.read_stream(&mut counting_sink).await;
+        stats.read_time = start.elapsed();
+        stats.overruns = overruns?;
+
+        info!(
+            "read {} bytes at {:.1} MB/s, {} overruns",
+            stats.bytes_read,
+            stats.read_throughput() / 1e6,
+            stats.overruns
+        );
+        self.stats.add(&stats);
+
+        Ok(stats)
+    }
+
+    /// Writes the chunks produced by `source`, each as one bulk transfer, until it returns `None`
+    /// or an empty chunk.
+    pub fn write_stream<'a>(
+        &mut self,
+        source: impl FnMut() -> Option<&'a [u8]> + Send,
+    ) -> Result<FifoStats> {
+        block_on(self.write_stream_async(source))
+    }
+
+    pub async fn write_stream_async<'a>(
+        &mut self,
+        mut source: impl FnMut() -> Option<&'a [u8]> + Send,
+    ) -> Result<FifoStats> {
+        let mut stats = FifoStats::default();
+        let start = Instant::now();
+        let mut fill = |buf: &mut Vec<u8>| {
+            if let Some(chunk) = source() {
+                stats.bytes_written += chunk.len() as u64;
+                buf.extend_from_slice(chunk);
+            }
+        };
+
+        let result = self.device.context.write_stream(&mut fill).await;
+        stats.write_time = start.elapsed();
+        result?;
+
+        info!(
+            "wrote {} bytes at {:.1} MB/s",
+            stats.bytes_written,
+            stats.write_throughput() / 1e6
+        );
+        self.stats.add(&stats);
+
+        Ok(stats)
+    }
+
+    /// Leaves FIFO mode and returns the device.
+    pub fn into_inner(self) -> Result<Device> {
+        block_on(self.into_inner_async())
+    }
+
+    pub async fn into_inner_async(mut self) -> Result<Device> {
+        self.device.set_bitmode_async(0, BitMode::Reset).await?;
+
+        Ok(self.device)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::transport::{descriptor, MemoryTransport};
+    use crate::ftdaye::Builder;
+
+    #[test]
+    fn test_sync_fifo() {
+        let transport = MemoryTransport::new(descriptor(0x6014, 0x900));
+        let device = transport.open();
+        let mut fifo = SyncFifo::new(device).unwrap();
+        assert_eq!(
+            transport
+                .take_control_requests()
+                .iter()
+                .map(|r| (r.request, r.value))
+                .collect::<Vec<_>>(),
+            [
+                (0x0B, 0x00ff),
+                (0x0B, 0x40ff),
+                (0x09, 2),
+                (0x00, 2),
+                (0x00, 1)
+            ]
+        );
+
+        let samples: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        transport.push_response(&samples);
+        transport.set_status(0x32, 0x62);
+        let mut received = vec![];
+        let stats = fifo
+            .read_stream(|data| {
+                received.extend_from_slice(data);
+                received.len() < 300
+            })
+            .unwrap();
+        assert_eq!(stats.bytes_read, 510);
+        assert_eq!(stats.overruns, 2);
+
+        // The rest of the transfer is kept for the next stream.
+        fifo.read_stream(|data| {
+            received.extend_from_slice(data);
+            false
+        })
+        .unwrap();
+        assert_eq!(received, samples);
+
+        let chunks = [&samples[..300], &samples[300..]];
+        let mut chunks = chunks.into_iter();
+        let stats = fifo.write_stream(|| chunks.next()).unwrap();
+        assert_eq!(stats.bytes_written, 1000);
+        assert_eq!(transport.take_written(), samples);
+        assert_eq!(fifo.stats().bytes_read, 1000);
+        assert_eq!(fifo.stats().bytes_written, 1000);
+
+        let device = Builder::new()
+            .with_interface(Interface::B)
+            .transport_open(MemoryTransport::default())
+            .unwrap();
+        assert!(SyncFifo::new(device).is_err());
+
+        let stats = FifoStats::default();
+        assert_eq!(stats.read_throughput(), 0.0);
+        assert_eq!(stats.write_throughput(), 0.0);
+    }
+}
@@ -0,0 +1,573 @@
+//! I2C master on an MPSSE channel, see AN108, AN113 and AN255.
+//!
+//! SCL is ADBUS0, and SDA has to be wired to both ADBUS1 (DO) and ADBUS2 (DI), with pull-ups on
+//! both lines. Data is shifted with three-phase clocking, so that SDA is stable on both edges of
+//! SCL. The FT232H drives the lines as open drain outputs; other chips release SDA by switching
+//! it to an input, and drive SCL push-pull, which rules out clock stretching.
+
+use async_io::block_on;
+use log::{debug, info};
+
+use crate::ftdaye::mpsse::{BitOrder, ClockEdge, MpsseCommand};
+use crate::ftdaye::{error::FtdiError, BitMode, Device, Result};
+
+const SCL: u8 = 1 << 0;
+const SDA: u8 = 1 << 1;
+
+/// How many times each bus state of a start or stop condition is set, as in AN255, to meet the
+/// setup and hold times.
+const HOLD_REPEATS: usize = 4;
+
+/// The address of an I2C device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I2cAddress {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+impl From<u8> for I2cAddress {
+    fn from(address: u8) -> Self {
+        Self::SevenBit(address)
+    }
+}
+
+impl I2cAddress {
+    pub fn value(self) -> u16 {
+        match self {
+            Self::SevenBit(address) => address as u16,
+            Self::TenBit(address) => address,
+        }
+    }
+}
+
+/// A step of an I2C transaction.
+#[derive(Debug)]
+pub enum I2cOperation<'a> {
+    Write(&'a [u8]),
+    Read(&'a mut [u8]),
+}
+
+/// What a byte read back during a transaction is.
+#[derive(Clone, Copy, Debug)]
+enum Reply {
+    AddressAck,
+    /// The acknowledge of byte `index` of a write, counting across joined operations.
+    DataAck {
+        index: usize,
+    },
+    /// Byte `index` of the read `operation`.
+    Data {
+        operation: usize,
+        index: usize,
+    },
+}
+
+/// An I2C master on a channel with an MPSSE.
+#[derive(Debug)]
+pub struct I2c {
+    device: Device,
+    open_drain: bool,
+    three_phase: bool,
+    clock_hz: u32,
+}
+
+impl I2c {
+    /// Switches `device` to MPSSE mode and releases the bus, with SCL running at up to
+    /// `clock_hz`.
+    pub fn new(device: Device, clock_hz: u32) -> Result<Self> {
+        block_on(Self::new_async(device, clock_hz))
+    }
+
+    pub async fn new_async(mut device: Device, clock_hz: u32) -> Result<Self> {
+        let interface = device.interface();
+        let chip_type = match device.chip_type() {
+            Some(chip_type) if chip_type.mpsse_interfaces().contains(&interface) => chip_type,
+            Some(chip_type) => Err(FtdiError::NoMpsse(chip_type, interface))?,
+            None => Err(FtdiError::Other("unknown chip type".to_string()))?,
+        };
+
+        device.set_bitmode_async(0, BitMode::Reset).await?;
+        device.set_bitmode_async(0, BitMode::Mpsse).await?;
+        device.set_latency_timer_async(1).await?;
+        device.usb_purge_buffers_async().await?;
+        device.sync_mpsse_async().await?;
+
+        let drive_zero = MpsseCommand::DriveZeroOnly {
+            low: SCL | SDA,
+            high: 0,
+        };
+        let open_drain = drive_zero.is_supported_by(chip_type);
+        let three_phase = MpsseCommand::ThreePhaseClocking(true).is_supported_by(chip_type);
+        let mut setup = vec![MpsseCommand::Loopback(false)];
+        if open_drain {
+            setup.push(drive_zero);
+        }
+        if three_phase {
+            setup.push(MpsseCommand::ThreePhaseClocking(true));
+        }
+        device.run_commands_async(&setup).await?;
+
+        let mut i2c = Self {
+            device,
+            open_drain,
+            three_phase,
+            clock_hz: 0,
+        };
+        i2c.set_clock_async(clock_hz).await?;
+        let idle = i2c.pins(true, true);
+        i2c.device.run_commands_async(&[idle]).await?;
+
+        Ok(i2c)
+    }
+
+    /// Returns the actual SCL frequency.
+    pub fn clock_hz(&self) -> u32 {
+        self.clock_hz
+    }
+
+    /// Sets SCL to the fastest frequency not above `hz` and returns it.
+    pub fn set_clock(&mut self, hz: u32) -> Result<u32> {
+        block_on(self.set_clock_async(hz))
+    }
+
+    pub async fn set_clock_async(&mut self, hz: u32) -> Result<u32> {
+        if hz == 0 {
+            Err(FtdiError::Other("the I2C clock can't be 0 Hz".to_string()))?
+        }
+
+        let mut base_hz: u32 = if self.device.chip_type().is_some_and(|c| c.is_high_speed()) {
+            self.device.disable_divide_by_5_async().await?;
+            30_000_000
+        } else {
+            6_000_000
+        };
+        // Three-phase clocking stretches each bit by half a period.
+        if self.three_phase {
+            base_hz = base_hz * 2 / 3;
+        }
+        let divisor = base_hz.div_ceil(hz).clamp(1, 0x10000) - 1;
+        self.device
+            .configure_clock_divider_async(divisor as u16)
+            .await?;
+        self.clock_hz = base_hz / (divisor + 1);
+        info!(
+            "Setting I2C clock to {} Hz (divisor: {}, actual: {} Hz)",
+            hz, divisor, self.clock_hz
+        );
+
+        Ok(self.clock_hz)
+    }
+
+    /// Writes `data` to the device at `address`, an `u8` for a 7-bit address.
+    pub fn write(&mut self, address: impl Into<I2cAddress>, data: &[u8]) -> Result<()> {
+        block_on(self.write_async(address, data))
+    }
+
+    pub async fn write_async(&mut self, address: impl Into<I2cAddress>, data: &[u8]) -> Result<()> {
+        self.transaction_async(address, &mut [I2cOperation::Write(data)])
+            .await
+    }
+
+    /// Reads `data` from the device at `address`.
+    pub fn read(&mut self, address: impl Into<I2cAddress>, data: &mut [u8]) -> Result<()> {
+        block_on(self.read_async(address, data))
+    }
+
+    pub async fn read_async(
+        &mut self,
+        address: impl Into<I2cAddress>,
+        data: &mut [u8],
+    ) -> Result<()> {
+        self.transaction_async(address, &mut [I2cOperation::Read(data)])
+            .await
+    }
+
+    /// Writes `data` to the device at `address`, then reads `read` from it after a repeated
+    /// start, e.g. to read registers.
+    pub fn write_read(
+        &mut self,
+        address: impl Into<I2cAddress>,
+        data: &[u8],
+        read: &mut [u8],
+    ) -> Result<()> {
+        block_on(self.write_read_async(address, data, read))
+    }
+
+    pub async fn write_read_async(
+        &mut self,
+        address: impl Into<I2cAddress>,
+        data: &[u8],
+        read: &mut [u8],
+    ) -> Result<()> {
+        self.transaction_async(
+            address,
+            &mut [I2cOperation::Write(data), I2cOperation::Read(read)],
+        )
+        .await
+    }
+
+    /// Runs `operations` on the device at `address` between a start and a stop condition, in a
+    /// single round trip unless they don't fit the chip's buffer.
+    ///
+    /// Consecutive operations in the same direction are joined, a change of direction sends a
+    /// repeated start and the address again. The last byte read before a write or the stop is
+    /// not acknowledged, as the protocol requires.
+    ///
+    /// A missing acknowledge fails with [`FtdiError::I2cAddressNack`] or
+    /// [`FtdiError::I2cDataNack`], after the transaction was ended with a stop condition.
+    pub fn transaction(
+        &mut self,
+        address: impl Into<I2cAddress>,
+        operations: &mut [I2cOperation<'_>],
+    ) -> Result<()> {
+        block_on(self.transaction_async(address, operations))
+    }
+
+    pub async fn transaction_async(
+        &mut self,
+        address: impl Into<I2cAddress>,
+        operations: &mut [I2cOperation<'_>],
+    ) -> Result<()> {
+        let address = address.into();
+        match address {
+            I2cAddress::SevenBit(0x80..) | I2cAddress::TenBit(0x400..) => Err(FtdiError::Other(
+                format!("I2C address {address:x?} out of range"),
+            ))?,
+            _ => {}
+        }
+        if operations
+            .iter()
+            .any(|op| matches!(op, I2cOperation::Read(data) if data.is_empty()))
+        {
+            Err(FtdiError::Other("I2C reads can't be empty".to_string()))?
+        }
+
+        let mut commands = vec![];
+        let mut replies = vec![];
+        let mut reading = None;
+        // Bytes written since the last start, as joined writes are one on the bus.
+        let mut written = 0;
+        for (i, operation) in operations.iter().enumerate() {
+            let read = matches!(operation, I2cOperation::Read(_));
+            if reading != Some(read) {
+                match reading {
+                    Some(_) => self.repeated_start(&mut commands),
+                    None => self.start(&mut commands),
+                }
+                self.address(
+                    &mut commands,
+                    &mut replies,
+                    address,
+                    read,
+                    reading.is_some(),
+                );
+                reading = Some(read);
+                written = 0;
+            }
+
+            match operation {
+                I2cOperation::Write(data) => {
+                    for byte in data.iter() {
+                        self.write_byte(&mut commands, *byte);
+                        replies.push(Reply::DataAck { index: written });
+                        written += 1;
+                    }
+                }
+                I2cOperation::Read(data) => {
+                    let joined = matches!(operations.get(i + 1), Some(I2cOperation::Read(_)));
+                    for index in 0..data.len() {
+                        let last = index == data.len() - 1 && !joined;
+                        self.read_byte(&mut commands, last);
+                        replies.push(Reply::Data {
+                            operation: i,
+                            index,
+                        });
+                    }
+                }
+            }
+        }
+        self.stop(&mut commands);
+        debug!("i2c transaction with {address:x?}: {operations:x?}");
+
+        let response = self.device.run_commands_async(&commands).await?;
+
+        for (reply, byte) in replies.into_iter().zip(response) {
+            let nack = byte & 1 != 0;
+            match reply {
+                Reply::AddressAck if nack => Err(FtdiError::I2cAddressNack {
+                    address: address.value(),
+                })?,
+                Reply::DataAck { index } if nack => Err(FtdiError::I2cDataNack {
+                    address: address.value(),
+                    index,
+                })?,
+                Reply::Data { operation, index } => {
+                    if let I2cOperation::Read(data) = &mut operations[operation] {
+                        data[index] = byte;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Frees the bus from a device that holds SDA low, e.g. after a transfer was interrupted
+    /// while it was sending a 0: SCL is clocked 9 times, enough for it to finish its byte and
+    /// see a missing acknowledge, then a stop condition is sent.
+    ///
+    /// Fails with [`FtdiError::I2cBusStuck`] if SDA is still low.
+    pub fn recover_bus(&mut self) -> Result<()> {
+        block_on(self.recover_bus_async())
+    }
+
+    pub async fn recover_bus_async(&mut self) -> Result<()> {
+        let mut commands = vec![self.pins(false, true); HOLD_REPEATS];
+        commands.extend((0..9).map(|_| MpsseCommand::ReadBits {
+            edge: ClockEdge::Rising,
+            order: BitOrder::MsbFirst,
+            count: 1,
+        }));
+        self.stop(&mut commands);
+
+        let response = self.device.run_commands_async(&commands).await?;
+        debug!("i2c bus recovery sampled SDA {response:x?}");
+        if response.last().is_some_and(|sda| sda & 1 == 0) {
+            Err(FtdiError::I2cBusStuck)?
+        }
+
+        Ok(())
+    }
+
+    /// Leaves MPSSE mode and returns the device.
+    pub fn into_inner(self) -> Result<Device> {
+        block_on(self.into_inner_async())
+    }
+
+    pub async fn into_inner_async(mut self) -> Result<Device> {
+        self.device.set_bitmode_async(0, BitMode::Reset).await?;
+
+        Ok(self.device)
+    }
+
+    /// Returns the command setting SCL and SDA. A high line is released, except for SCL when
+    /// the chip can't drive open drain.
+    fn pins(&self, scl: bool, sda: bool) -> MpsseCommand {
+        let released = if self.open_drain || !sda { 0 } else { SDA };
+        MpsseCommand::SetLowBits {
+            value: scl as u8 | (sda as u8) << 1,
+            direction: (SCL | SDA) & !released,
+        }
+    }
+
+    fn hold(&self, commands: &mut Vec<MpsseCommand>, scl: bool, sda: bool) {
+        commands.extend(std::iter::repeat_n(self.pins(scl, sda), HOLD_REPEATS));
+    }
+
+    fn start(&self, commands: &mut Vec<MpsseCommand>) {
+        self.hold(commands, true, true);
+        self.hold(commands, true, false);
+        self.hold(commands, false, false);
+    }
+
+    fn repeated_start(&self, commands: &mut Vec<MpsseCommand>) {
+        self.hold(commands, false, true);
+        self.start(commands);
+    }
+
+    fn stop(&self, commands: &mut Vec<MpsseCommand>) {
+        self.hold(commands, false, false);
+        self.hold(commands, true, false);
+        self.hold(commands, true, true);
+    }
+
+    /// Sends the address, expecting a reply per byte. A 10-bit read needs the full address
+    /// written first, unless the transaction `continued` from a write to it.
+    fn address(
+        &self,
+        commands: &mut Vec<MpsseCommand>,
+        replies: &mut Vec<Reply>,
+        address: I2cAddress,
+        read: bool,
+        continued: bool,
+    ) {
+        let mut send = |commands: &mut Vec<MpsseCommand>, byte: u8| {
+            self.write_byte(commands, byte);
+            replies.push(Reply::AddressAck);
+        };
+
+        match address {
+            I2cAddress::SevenBit(address) => send(commands, address << 1 | read as u8),
+            I2cAddress::TenBit(address) => {
+                let header = 0b1111_0000 | ((address >> 8) as u8) << 1;
+                if !read || !continued {
+                    send(commands, header);
+                    send(commands, address as u8);
+                }
+                if read {
+                    if !continued {
+                        self.repeated_start(commands);
+                    }
+                    send(commands, header | 1);
+                }
+            }
+        }
+    }
+
+    /// Shifts out `byte` and reads the acknowledge.
+    fn write_byte(&self, commands: &mut Vec<MpsseCommand>, byte: u8) {
+        commands.extend([
+            MpsseCommand::SetLowBits {
+                value: 0,
+                direction: SCL | SDA,
+            },
+            MpsseCommand::WriteBytes {
+                edge: ClockEdge::Falling,
+                order: BitOrder::MsbFirst,
+                data: vec![byte],
+            },
+            self.pins(false, true),
+            MpsseCommand::ReadBits {
+                edge: ClockEdge::Rising,
+                order: BitOrder::MsbFirst,
+                count: 1,
+            },
+        ]);
+    }
+
+    /// Shifts in a byte and acknowledges it, unless it is the `last`.
+    fn read_byte(&self, commands: &mut Vec<MpsseCommand>, last: bool) {
+        commands.extend([
+            self.pins(false, true),
+            MpsseCommand::ReadBits {
+                edge: ClockEdge::Rising,
+                order: BitOrder::MsbFirst,
+                count: 8,
+            },
+            MpsseCommand::SetLowBits {
+                value: 0,
+                direction: SCL | SDA,
+            },
+            MpsseCommand::WriteBits {
+                edge: ClockEdge::Falling,
+                order: BitOrder::MsbFirst,
+                count: 1,
+                data: if last { 0x80 } else { 0 },
+            },
+        ]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::{EmulatedTransport, I2cSlave};
+    use crate::ftdaye::transport::descriptor;
+    use crate::ftdaye::{BitMode, Builder};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_i2c_master() {
+        let slave = I2cSlave::new(0x50).with_registers((0..=255).collect());
+        let transport = EmulatedTransport::new(slave);
+        let device = Builder::new().transport_open(transport.clone()).unwrap();
+        let mut i2c = I2c::new(device, 100_000).unwrap();
+        assert_eq!(i2c.clock_hz(), 100_000);
+
+        i2c.write(0x50, &[0x10, 1, 2, 3]).unwrap();
+        assert_eq!(
+            transport.emulator().target().registers()[0x10..0x14],
+            [1, 2, 3, 0x13]
+        );
+
+        let mut data = [0; 4];
+        i2c.write_read(0x50, &[0x11], &mut data).unwrap();
+        assert_eq!(data, [2, 3, 0x13, 0x14]);
+        // Reading goes on from where the last read stopped.
+        let mut data = [0; 2];
+        i2c.read(0x50, &mut data).unwrap();
+        assert_eq!(data, [0x15, 0x16]);
+        assert_eq!(transport.emulator().target().conditions(), (4, 3));
+
+        assert!(matches!(
+            i2c.write(0x51, &[0]),
+            Err(FtdiError::I2cAddressNack { address: 0x51 })
+        ));
+        assert!(matches!(
+            i2c.read(0x51, &mut data),
+            Err(FtdiError::I2cAddressNack { address: 0x51 })
+        ));
+        assert!(!transport.emulator().target().holds_sda());
+    }
+
+    #[test]
+    fn test_i2c_ten_bit_open_drain() {
+        // An FT232H, which drives the bus open drain.
+        let slave = I2cSlave::new_ten_bit(0x2a5).with_registers(vec![0; 4]);
+        let transport = EmulatedTransport::new(slave).with_descriptor(descriptor(0x6014, 0x900));
+        let device = Builder::new().transport_open(transport.clone()).unwrap();
+        let mut i2c = I2c::new(device, 400_000).unwrap();
+        let address = I2cAddress::TenBit(0x2a5);
+
+        i2c.write(address, &[1, 0xaa, 0xbb]).unwrap();
+        let mut data = [0; 2];
+        i2c.write_read(address, &[1], &mut data).unwrap();
+        assert_eq!(data, [0xaa, 0xbb]);
+        // A read on its own writes the address first.
+        i2c.read(address, &mut data).unwrap();
+        assert_eq!(data, [0, 0xff]);
+
+        // Past the end of the registers, writes aren't acknowledged.
+        assert!(matches!(
+            i2c.write(address, &[2, 1, 2, 3]),
+            Err(FtdiError::I2cDataNack {
+                address: 0x2a5,
+                index: 3
+            })
+        ));
+        // Joined writes count their bytes from the start of the first.
+        assert!(matches!(
+            i2c.transaction(
+                address,
+                &mut [I2cOperation::Write(&[2]), I2cOperation::Write(&[1, 2, 3])]
+            ),
+            Err(FtdiError::I2cDataNack {
+                address: 0x2a5,
+                index: 3
+            })
+        ));
+        assert_eq!(transport.emulator().target().registers(), [0, 0xaa, 1, 2]);
+        for other in [0x1a5, 0x2a4] {
+            assert!(matches!(
+                i2c.write(I2cAddress::TenBit(other), &[0]),
+                Err(FtdiError::I2cAddressNack { address }) if address == other
+            ));
+        }
+    }
+
+    #[test]
+    fn test_i2c_bus_recovery() {
+        let transport = EmulatedTransport::new(I2cSlave::new(0x50));
+        let mut device = Builder::new().transport_open(transport.clone()).unwrap();
+        device.set_bitmode(0, BitMode::Mpsse).unwrap();
+
+        // A host that died after the first 3 bits of a read, with the slave sending zeros.
+        device
+            .write_all(&[
+                0x80, 0x03, 0x03, 0x80, 0x01, 0x03, 0x80, 0x00, 0x03, 0x13, 0x07, 0xa1, 0x80, 0x00,
+                0x01, 0x22, 0x00, 0x22, 0x02, 0x87,
+            ])
+            .unwrap();
+        let mut reply = [0; 2];
+        device.read_exact(&mut reply).unwrap();
+        assert_eq!(reply[0] & 1, 0);
+        assert!(transport.emulator().target().holds_sda());
+
+        let mut i2c = I2c::new(device, 100_000).unwrap();
+        i2c.recover_bus().unwrap();
+        assert!(!transport.emulator().target().holds_sda());
+        i2c.write(0x50, &[0, 0x42]).unwrap();
+        assert_eq!(transport.emulator().target().registers()[0], 0x42);
+    }
+}
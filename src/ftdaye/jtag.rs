@@ -1,19 +1,21 @@
 // jtag helpers for ftdi mpsse
 
 use crate::ftdaye::mpsse::{
-    cmd_read_imm, cmd_read_write_imm, cmd_write_imm, Clock_Data_Bits_Out_on_neg_ve_LSB_first,
-    Clock_Data_to_TMS_on_neg_ve_LSB_first, CmdImm,
+    cmd_read_imm, cmd_read_write_imm, cmd_write_imm, find_bad_command,
+    Clock_Data_Bits_Out_on_neg_ve_LSB_first, Clock_Data_to_TMS_on_neg_ve_LSB_first, CmdImm,
+    MAX_TRANSFER_LEN,
 };
-use crate::ftdaye::{BitMode, Device};
+use crate::ftdaye::{error::FtdiError, BitMode, Device};
 
+use async_io::block_on;
 use log::*;
-use std::io::{Read, Write};
+use std::io::Read;
 
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct FtdiMpsse {
     pub device: Device,
-    buffer_size_bytes: u16,
+    buffer_size_bytes: usize,
     actual_speed_khz: u16,
 }
 
@@ -24,23 +26,80 @@ enum JtagState {
     ShiftDr(u8),
 }
 
+/// The states of the IEEE 1149.1 TAP controller.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TapState {
+    #[default]
+    TestLogicReset,
+    RunTestIdle,
+    SelectDrScan,
+    CaptureDr,
+    ShiftDr,
+    Exit1Dr,
+    PauseDr,
+    Exit2Dr,
+    UpdateDr,
+    SelectIrScan,
+    CaptureIr,
+    ShiftIr,
+    Exit1Ir,
+    PauseIr,
+    Exit2Ir,
+    UpdateIr,
+}
+
+impl TapState {
+    /// Returns the state the TAP controller moves to on a TCK rising edge with the given TMS.
+    pub fn next(self, tms: bool) -> Self {
+        use TapState::*;
+
+        match (self, tms) {
+            (TestLogicReset, false) => RunTestIdle,
+            (TestLogicReset, true) => TestLogicReset,
+            (RunTestIdle, false) => RunTestIdle,
+            (RunTestIdle, true) => SelectDrScan,
+
+            (SelectDrScan, false) => CaptureDr,
+            (SelectDrScan, true) => SelectIrScan,
+            (CaptureDr, false) | (ShiftDr, false) | (Exit2Dr, false) => ShiftDr,
+            (CaptureDr, true) | (ShiftDr, true) => Exit1Dr,
+            (Exit1Dr, false) | (PauseDr, false) => PauseDr,
+            (Exit1Dr, true) | (Exit2Dr, true) => UpdateDr,
+            (PauseDr, true) => Exit2Dr,
+            (UpdateDr, false) | (UpdateIr, false) => RunTestIdle,
+            (UpdateDr, true) | (UpdateIr, true) => SelectDrScan,
+
+            (SelectIrScan, false) => CaptureIr,
+            (SelectIrScan, true) => TestLogicReset,
+            (CaptureIr, false) | (ShiftIr, false) | (Exit2Ir, false) => ShiftIr,
+            (CaptureIr, true) | (ShiftIr, true) => Exit1Ir,
+            (Exit1Ir, false) | (PauseIr, false) => PauseIr,
+            (Exit1Ir, true) | (Exit2Ir, true) => UpdateIr,
+            (PauseIr, true) => Exit2Ir,
+        }
+    }
+}
+
+// The async methods are the primitives, the blocking ones drive them to completion.
 // Todo: what kind of errors do we want here?
-// for now, just unwrap and panic...
+// for now, the blocking API just unwraps and panics...
 impl FtdiMpsse {
-    pub fn new(mut device: Device, speed_khz: u32) -> Self {
-        device.usb_reset().unwrap();
+    pub fn new(device: Device, speed_khz: u32) -> Self {
+        block_on(Self::new_async(device, speed_khz)).unwrap()
+    }
+
+    pub async fn new_async(mut device: Device, speed_khz: u32) -> Result<Self, FtdiError> {
+        device.usb_reset_async().await?;
         // 0x0B configures pins for JTAG
         // does it really?
         //device.set_bitmode(0x0b, BitMode::Mpsse).unwrap();
         // We should reset
-        device.set_bitmode(0x00, BitMode::Reset).unwrap();
+        device.set_bitmode_async(0x00, BitMode::Reset).await?;
         // Then set mode = 2, mask = 0 according to manual
-        device.set_bitmode(0x00, BitMode::Mpsse).unwrap();
-        device.set_latency_timer(1).unwrap();
-        device.usb_purge_buffers().unwrap();
-
-        let mut junk = vec![];
-        let _ = device.read_to_end(&mut junk);
+        device.set_bitmode_async(0x00, BitMode::Mpsse).await?;
+        device.set_latency_timer_async(1).await?;
+        device.usb_purge_buffers_async().await?;
+        device.sync_mpsse_async().await?;
 
         let (output, direction) = (0x0088, 0x008b);
         debug!(
@@ -51,16 +110,16 @@ impl FtdiMpsse {
         );
         //println!("Device chip type: {:?}", device.chip_type);
         debug!("pinmode {:x} {:x}", output, direction);
-        device.set_pins(output, direction).unwrap();
+        device.set_pins_async(output, direction).await?;
 
         // FTDI 2232
         // Disable divide-by-5 mode
-        device.disable_divide_by_5().unwrap();
-        let buffer_size_bytes: u16 = 4096;
+        device.disable_divide_by_5_async().await?;
+        let buffer_size_bytes = device.mpsse_buffer_size();
         let max_clock_khz: u32 = 30_000;
 
         // If `speed_khz` is not a divisor of the maximum supported speed, we need to round up
-        let is_exact = max_clock_khz % speed_khz == 0;
+        let is_exact = max_clock_khz.is_multiple_of(speed_khz);
 
         // If `speed_khz` is 0, use the maximum supported speed
         let divisor =
@@ -73,145 +132,239 @@ impl FtdiMpsse {
             speed_khz, divisor, actual_speed_khz
         );
 
-        device.configure_clock_divider(divisor as u16).unwrap();
+        device.configure_clock_divider_async(divisor as u16).await?;
 
-        device.disable_loopback().unwrap();
-
-        // check bad command
-        let bad_command = [0xAB];
-        device.write_all(&bad_command).unwrap();
-
-        let mut junk = vec![];
-        let r = device.read_to_end(&mut junk);
+        device.disable_loopback_async().await?;
 
-        debug!("r {:?}, buf {:x?}", r, junk);
-        Self {
+        Ok(Self {
             device,
             buffer_size_bytes,
             actual_speed_khz,
-        }
+        })
     }
 
     pub fn read_write_register(&mut self, ir: u8, data: &mut [u8]) {
-        debug!("read write ir #{:#04x}", ir);
-        self.rti_to_shift_ir();
-        self.shift_ir(ir);
+        block_on(self.read_write_register_async(ir, data)).unwrap()
+    }
 
-        self.rti_to_shift_dr();
+    pub async fn read_write_register_async(
+        &mut self,
+        ir: u8,
+        data: &mut [u8],
+    ) -> Result<(), FtdiError> {
+        debug!("read write ir #{:#04x}", ir);
+        self.rti_to_shift_ir_async().await?;
+        self.shift_ir_async(ir).await?;
 
-        self.device.write(&cmd_read_write_imm(data)).unwrap();
+        self.rti_to_shift_dr_async().await?;
 
-        self.device.read_exact(data).unwrap();
+        for chunk in data.chunks_mut(self.batch_len(4)) {
+            self.device
+                .write_all_async(&cmd_read_write_imm(chunk)?)
+                .await?;
+            self.device.read_exact_async(chunk).await?;
+        }
+        self.check_reply_async(data).await?;
 
-        self.dr_to_rti();
+        self.dr_to_rti_async().await
     }
 
     pub fn write_register(&mut self, ir: u8, data: &[u8]) {
+        block_on(self.write_register_async(ir, data)).unwrap()
+    }
+
+    pub async fn write_register_async(&mut self, ir: u8, data: &[u8]) -> Result<(), FtdiError> {
         debug!("write ir #{:#04x}", ir);
-        self.rti_to_shift_ir();
-        self.shift_ir(ir);
+        self.rti_to_shift_ir_async().await?;
+        self.shift_ir_async(ir).await?;
+
+        self.rti_to_shift_dr_async().await?;
+
+        for chunk in data.chunks(self.batch_len(4)) {
+            self.device.write_all_async(&cmd_write_imm(chunk)?).await?;
+        }
+
+        self.dr_to_rti_async().await
+    }
 
-        self.rti_to_shift_dr();
+    /// Returns how many data bytes one batch of commands may clock, given the `overhead` of the
+    /// command bytes around them.
+    ///
+    /// A batch must fit the chip's buffer, both as commands and as reply, and the MPSSE length
+    /// field.
+    fn batch_len(&self, overhead: usize) -> usize {
+        (self.buffer_size_bytes - overhead).min(MAX_TRANSFER_LEN)
+    }
+
+    /// Checks that nothing arrived after `reply`, the reply read so far.
+    ///
+    /// Data received along with the reply is drained, as is the rest if the reply holds what
+    /// looks like the echo of a bad command, and the check fails if there is any, with
+    /// [`FtdiError::BadCommand`] if the MPSSE rejected a command. An echo without anything after
+    /// the reply was data, as a real one pushes the reply back by two bytes. So a good reply only
+    /// costs an extra round trip if it happens to contain such an echo.
+    async fn check_reply_async(&mut self, reply: &[u8]) -> Result<(), FtdiError> {
+        let echo = find_bad_command(reply);
+        if echo.is_none() && self.device.queued_len() == 0 {
+            return Ok(());
+        }
 
-        self.device.write(&cmd_write_imm(data)).unwrap();
+        let mut extra = vec![];
+        self.device.read_to_end_async(&mut extra).await?;
+        if extra.is_empty() {
+            return Ok(());
+        }
 
-        self.dr_to_rti();
+        // The echo may straddle the end of the expected reply.
+        let tail = &reply[reply.len().saturating_sub(1)..];
+        match echo.or_else(|| find_bad_command(&[tail, &extra].concat())) {
+            Some(opcode) => Err(FtdiError::BadCommand(opcode)),
+            None => Err(FtdiError::Other(format!(
+                "received {} bytes more than expected",
+                extra.len()
+            ))),
+        }
     }
 
     pub fn assert_ftdi_buffer_empty(&mut self) {
         let mut junk = vec![];
         let _ = self.device.read_to_end(&mut junk);
-        assert!(junk.len() == 0, "buffer not empty {:?}", junk)
+        assert!(junk.is_empty(), "buffer not empty {:?}", junk)
     }
 
     pub fn read_register(&mut self, ir: u8, data: &mut [u8]) {
-        debug!("read ir #{:#04x}", ir);
-        self.rti_to_shift_ir();
-        self.shift_ir(ir);
+        block_on(self.read_register_async(ir, data)).unwrap()
+    }
 
-        self.rti_to_shift_dr();
+    pub async fn read_register_async(&mut self, ir: u8, data: &mut [u8]) -> Result<(), FtdiError> {
+        debug!("read ir #{:#04x}", ir);
+        self.rti_to_shift_ir_async().await?;
+        self.shift_ir_async(ir).await?;
 
-        self.device.write(&cmd_read_imm(data.len())).unwrap();
+        self.rti_to_shift_dr_async().await?;
 
-        self.device.read_exact(data).unwrap();
+        for chunk in data.chunks_mut(self.batch_len(0)) {
+            self.device
+                .write_all_async(&cmd_read_imm(chunk.len())?)
+                .await?;
+            self.device.read_exact_async(chunk).await?;
+        }
+        self.check_reply_async(data).await?;
 
-        self.dr_to_rti();
+        self.dr_to_rti_async().await
     }
 
     // reset state machine, and go to rti
     pub fn reset_and_to_rti(&mut self) {
+        block_on(self.reset_and_to_rti_async()).unwrap()
+    }
+
+    pub async fn reset_and_to_rti_async(&mut self) -> Result<(), FtdiError> {
         self.device
-            .write(&[Clock_Data_to_TMS_on_neg_ve_LSB_first, 4, 0b1_1111, CmdImm])
-            .unwrap();
+            .write_all_async(&[Clock_Data_to_TMS_on_neg_ve_LSB_first, 4, 0b1_1111, CmdImm])
+            .await?;
         self.device
-            .write(&[Clock_Data_to_TMS_on_neg_ve_LSB_first, 0, 0b0, CmdImm])
-            .unwrap();
+            .write_all_async(&[Clock_Data_to_TMS_on_neg_ve_LSB_first, 0, 0b0, CmdImm])
+            .await?;
+        Ok(())
     }
 
     // go from rti to shift dr
     pub fn rti_to_shift_dr(&mut self) {
+        block_on(self.rti_to_shift_dr_async()).unwrap()
+    }
+
+    pub async fn rti_to_shift_dr_async(&mut self) -> Result<(), FtdiError> {
         self.device
-            .write(&[Clock_Data_to_TMS_on_neg_ve_LSB_first, 2, 0b001, CmdImm])
-            .unwrap();
+            .write_all_async(&[Clock_Data_to_TMS_on_neg_ve_LSB_first, 2, 0b001, CmdImm])
+            .await?;
+        Ok(())
     }
 
     // go from rti to shift ir
     pub fn rti_to_shift_ir(&mut self) {
+        block_on(self.rti_to_shift_ir_async()).unwrap()
+    }
+
+    pub async fn rti_to_shift_ir_async(&mut self) -> Result<(), FtdiError> {
         self.device
-            .write(&[Clock_Data_to_TMS_on_neg_ve_LSB_first, 3, 0b0011, CmdImm])
-            .unwrap();
+            .write_all_async(&[Clock_Data_to_TMS_on_neg_ve_LSB_first, 3, 0b0011, CmdImm])
+            .await?;
+        Ok(())
     }
 
     // go from dr back to rti
     pub fn dr_to_rti(&mut self) {
+        block_on(self.dr_to_rti_async()).unwrap()
+    }
+
+    pub async fn dr_to_rti_async(&mut self) -> Result<(), FtdiError> {
         self.device
-            .write(&[Clock_Data_to_TMS_on_neg_ve_LSB_first, 2, 0b011, CmdImm])
-            .unwrap();
+            .write_all_async(&[Clock_Data_to_TMS_on_neg_ve_LSB_first, 2, 0b011, CmdImm])
+            .await?;
+        Ok(())
     }
 
     // go from ir back to rti
     pub fn ir_to_rti(&mut self, bit7: u8) {
+        block_on(self.ir_to_rti_async(bit7)).unwrap()
+    }
+
+    pub async fn ir_to_rti_async(&mut self, bit7: u8) -> Result<(), FtdiError> {
         self.device
-            .write(&[
+            .write_all_async(&[
                 Clock_Data_to_TMS_on_neg_ve_LSB_first,
                 2,
                 bit7 | 0b011,
                 CmdImm,
             ])
-            .unwrap();
+            .await?;
+        Ok(())
     }
 
     // shift ir and go back to rti
     pub fn shift_ir(&mut self, ir: u8) {
+        block_on(self.shift_ir_async(ir)).unwrap()
+    }
+
+    pub async fn shift_ir_async(&mut self, ir: u8) -> Result<(), FtdiError> {
         // 5 bits of ir
         self.device
-            .write(&[Clock_Data_Bits_Out_on_neg_ve_LSB_first, 4, ir, CmdImm])
-            .unwrap();
+            .write_all_async(&[Clock_Data_Bits_Out_on_neg_ve_LSB_first, 4, ir, CmdImm])
+            .await?;
         // msb of ir as bit 7 of next transaction
-        self.ir_to_rti((ir & 0b10_0000) << 2);
+        self.ir_to_rti_async((ir & 0b10_0000) << 2).await
     }
 
     // shift ir and go back to rti
     pub fn shift_ir_bits(&mut self, ir: u8, bit_amount: u8) {
+        block_on(self.shift_ir_bits_async(ir, bit_amount)).unwrap()
+    }
+
+    pub async fn shift_ir_bits_async(&mut self, ir: u8, bit_amount: u8) -> Result<(), FtdiError> {
         // 5 bits of ir
         self.device
-            .write(&[
+            .write_all_async(&[
                 Clock_Data_Bits_Out_on_neg_ve_LSB_first,
                 bit_amount - 1,
                 ir,
                 CmdImm,
             ])
-            .unwrap();
+            .await?;
         // msb of ir as bit 7 of next transaction
-        self.ir_to_rti((ir & 0b10_0000) << 2);
+        self.ir_to_rti_async((ir & 0b10_0000) << 2).await
     }
 
     pub fn shift_ir_bytes(&mut self, bytes: &[u8]) {
+        block_on(self.shift_ir_bytes_async(bytes)).unwrap()
+    }
+
+    pub async fn shift_ir_bytes_async(&mut self, bytes: &[u8]) -> Result<(), FtdiError> {
         for byte in bytes {
             self.device
-                .write(&[Clock_Data_Bits_Out_on_neg_ve_LSB_first, 8, *byte, CmdImm])
-                .unwrap();
+                .write_all_async(&[Clock_Data_Bits_Out_on_neg_ve_LSB_first, 8, *byte, CmdImm])
+                .await?;
         }
-        self.ir_to_rti(0);
+        self.ir_to_rti_async(0).await
     }
 }
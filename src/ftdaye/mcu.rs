@@ -0,0 +1,221 @@
+//! MCU host bus emulation, see AN108 section 4.
+
+use async_io::block_on;
+use log::debug;
+
+use crate::ftdaye::mpsse::{
+    CmdMcuReadExtended, CmdMcuReadShort, CmdMcuWriteExtended, CmdMcuWriteShort, MpsseCommand,
+};
+use crate::ftdaye::{error::FtdiError, BitMode, ChipType, Device, Result};
+
+/// An address on the emulated bus.
+///
+/// Short addresses only drive the low address byte, extended ones drive both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum McuAddress {
+    Short(u8),
+    Extended(u16),
+}
+
+impl From<u8> for McuAddress {
+    fn from(address: u8) -> Self {
+        Self::Short(address)
+    }
+}
+
+impl From<u16> for McuAddress {
+    fn from(address: u16) -> Self {
+        Self::Extended(address)
+    }
+}
+
+impl McuAddress {
+    pub(crate) fn encode_read(self, command: &mut Vec<u8>) {
+        match self {
+            Self::Short(address) => command.extend([CmdMcuReadShort, address]),
+            Self::Extended(address) => {
+                let [high, low] = address.to_be_bytes();
+                command.extend([CmdMcuReadExtended, high, low]);
+            }
+        }
+    }
+
+    pub(crate) fn encode_write(self, data: u8, command: &mut Vec<u8>) {
+        match self {
+            Self::Short(address) => command.extend([CmdMcuWriteShort, address, data]),
+            Self::Extended(address) => {
+                let [high, low] = address.to_be_bytes();
+                command.extend([CmdMcuWriteExtended, high, low, data]);
+            }
+        }
+    }
+}
+
+/// A channel emulating the 8 bit data and 8 or 16 bit address bus of a microcontroller.
+///
+/// Supported by the FT2232C/D, FT2232H and FT232H.
+#[derive(Debug)]
+pub struct McuHostBus {
+    device: Device,
+}
+
+impl McuHostBus {
+    /// Switches `device` to MCU host bus emulation mode.
+    pub fn new(device: Device) -> Result<Self> {
+        block_on(Self::new_async(device))
+    }
+
+    pub async fn new_async(mut device: Device) -> Result<Self> {
+        match device.chip_type() {
+            Some(ChipType::FT2232C | ChipType::FT2232H | ChipType::FT232H) => {}
+            Some(chip_type) => Err(FtdiError::UnsupportedChipType(chip_type))?,
+            None => Err(FtdiError::Other("unknown chip type".to_string()))?,
+        }
+
+        device.set_bitmode_async(0, BitMode::Reset).await?;
+        device.set_bitmode_async(0, BitMode::Mcu).await?;
+        device.set_latency_timer_async(1).await?;
+        device.usb_purge_buffers_async().await?;
+
+        Ok(Self { device })
+    }
+
+    /// Reads the byte at `address`, an `u8` for a short and an `u16` for an extended address.
+    pub fn read(&mut self, address: impl Into<McuAddress>) -> Result<u8> {
+        block_on(self.read_async(address))
+    }
+
+    pub async fn read_async(&mut self, address: impl Into<McuAddress>) -> Result<u8> {
+        let mut data = [0];
+        self.read_many_async(&[address.into()], &mut data).await?;
+
+        Ok(data[0])
+    }
+
+    /// Reads the bytes at `addresses` into `data`, in a single round trip unless they don't fit
+    /// the chip's buffer.
+    pub fn read_many(&mut self, addresses: &[McuAddress], data: &mut [u8]) -> Result<()> {
+        block_on(self.read_many_async(addresses, data))
+    }
+
+    pub async fn read_many_async(
+        &mut self,
+        addresses: &[McuAddress],
+        data: &mut [u8],
+    ) -> Result<()> {
+        if addresses.len() != data.len() {
+            Err(FtdiError::Other(format!(
+                "one data byte is needed per address, got {} for {} addresses",
+                data.len(),
+                addresses.len()
+            )))?
+        }
+
+        let commands: Vec<_> = addresses
+            .iter()
+            .map(|&address| MpsseCommand::McuRead(address))
+            .collect();
+        debug!("mcu read {:x?}", addresses);
+
+        let response = self.device.run_commands_async(&commands).await?;
+        data.copy_from_slice(&response);
+
+        Ok(())
+    }
+
+    /// Writes `data` to `address`, an `u8` for a short and an `u16` for an extended address.
+    pub fn write(&mut self, address: impl Into<McuAddress>, data: u8) -> Result<()> {
+        block_on(self.write_async(address, data))
+    }
+
+    pub async fn write_async(&mut self, address: impl Into<McuAddress>, data: u8) -> Result<()> {
+        self.write_many_async(&[(address.into(), data)]).await
+    }
+
+    /// Writes each `(address, data)` pair in order, in as few transfers as the chip's buffer
+    /// allows.
+    pub fn write_many(&mut self, writes: &[(McuAddress, u8)]) -> Result<()> {
+        block_on(self.write_many_async(writes))
+    }
+
+    pub async fn write_many_async(&mut self, writes: &[(McuAddress, u8)]) -> Result<()> {
+        let commands: Vec<_> = writes
+            .iter()
+            .map(|&(address, data)| MpsseCommand::McuWrite(address, data))
+            .collect();
+        debug!("mcu write {:x?}", writes);
+
+        self.device.run_commands_async(&commands).await?;
+
+        Ok(())
+    }
+
+    /// Leaves MCU host bus mode and returns the device.
+    pub fn into_inner(self) -> Result<Device> {
+        block_on(self.into_inner_async())
+    }
+
+    pub async fn into_inner_async(mut self) -> Result<Device> {
+        self.device.set_bitmode_async(0, BitMode::Reset).await?;
+
+        Ok(self.device)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::transport::MemoryTransport;
+
+    #[test]
+    fn test_mcu_host_bus() {
+        let transport = MemoryTransport::default();
+        let device = transport.open();
+        let mut bus = McuHostBus::new(device).unwrap();
+        assert_eq!(
+            transport
+                .take_control_requests()
+                .iter()
+                .map(|r| (r.request, r.value))
+                .collect::<Vec<_>>(),
+            [(0x0B, 0), (0x0B, 0x0800), (0x09, 1), (0x00, 2), (0x00, 1)]
+        );
+
+        bus.write(0x12u8, 0xab).unwrap();
+        bus.write(0x1234u16, 0xcd).unwrap();
+        assert_eq!(
+            transport.take_written(),
+            [0x92, 0x12, 0xab, 0x93, 0x12, 0x34, 0xcd]
+        );
+
+        transport.push_response(&[0x55, 0x66]);
+        let mut data = [0; 2];
+        bus.read_many(
+            &[McuAddress::Short(0x10), McuAddress::Extended(0x2000)],
+            &mut data,
+        )
+        .unwrap();
+        assert_eq!(data, [0x55, 0x66]);
+        assert_eq!(
+            transport.take_written(),
+            [0x90, 0x10, 0x91, 0x20, 0x00, 0x87]
+        );
+
+        transport.push_response(&[0x77]);
+        assert_eq!(bus.read(0xffu8).unwrap(), 0x77);
+        assert_eq!(transport.take_written(), [0x90, 0xff, 0x87]);
+
+        // More reads than fit the 4 KiB buffer take two round trips.
+        let addresses: Vec<_> = (0..2000u16).map(McuAddress::Extended).collect();
+        let reply: Vec<u8> = (0..2000u32).map(|i| i as u8).collect();
+        transport.push_response(&reply);
+        let mut data = vec![0; reply.len()];
+        bus.read_many(&addresses, &mut data).unwrap();
+        assert_eq!(data, reply);
+        let written = transport.take_written();
+        assert_eq!(written.len(), 2000 * 3 + 2);
+        assert_eq!(written[4095], 0x87);
+
+        assert!(bus.read_many(&addresses, &mut [0; 1]).is_err());
+    }
+}
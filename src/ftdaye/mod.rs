@@ -1,19 +1,40 @@
+pub mod bitbang;
+pub mod capture;
+pub mod cbus;
+pub mod disasm;
+pub mod eeprom;
 pub mod error;
+pub mod fifo;
+pub mod i2c;
 pub mod jtag;
+pub mod mcu;
 pub mod mpsse;
+pub mod recording;
+pub mod spi;
+pub mod status;
+pub mod transport;
+pub mod uart;
 
 use std::collections::VecDeque;
 use std::io::{self, Read, Write};
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use async_io::block_on;
 use nusb::DeviceInfo;
 
+use eeprom::Eeprom;
 use error::FtdiError;
-use log::{debug, trace, warn};
-use nusb::transfer::{Control, ControlType, Direction, EndpointType, Recipient};
+use log::{debug, warn};
+use mpsse::{MpsseBuffer, MpsseCommand};
+use recording::{record, Event, Recorder};
 
-use crate::usb_util::InterfaceExt;
-use crate::JtagProbeError;
+use crate::{usb_util, JtagProbeError};
+use status::{LineStatus, ModemStatus, Packet};
+use transport::{DeviceDescriptor, NusbTransport, TransferSink, TransferSource, Transport};
+
+/// The smallest MPSSE buffer, of the FT2232C/D.
+const MIN_MPSSE_BUFFER_SIZE: usize = 128;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ChipType {
@@ -25,6 +46,80 @@ pub enum ChipType {
     FT4232H,
     FT232H,
     FT230X,
+    FT4232HA,
+    // Hi-Speed-plus, with USB Power Delivery
+    FT2233HP,
+    FT4233HP,
+    FT2232HP,
+    FT4232HP,
+    FT233HP,
+    FT232HP,
+}
+
+impl ChipType {
+    /// Returns the interfaces this chip provides.
+    pub fn interfaces(self) -> &'static [Interface] {
+        match self {
+            ChipType::FT2232C | ChipType::FT2232H | ChipType::FT2232HP | ChipType::FT2233HP => {
+                &[Interface::A, Interface::B]
+            }
+            ChipType::FT4232H | ChipType::FT4232HA | ChipType::FT4232HP | ChipType::FT4233HP => {
+                &[Interface::A, Interface::B, Interface::C, Interface::D]
+            }
+            _ => &[Interface::A],
+        }
+    }
+
+    /// Returns the interfaces that have an MPSSE.
+    ///
+    /// The quad channel chips only have one on A and B, the FT2232C/D only on A.
+    pub fn mpsse_interfaces(self) -> &'static [Interface] {
+        match self {
+            ChipType::FT2232H
+            | ChipType::FT2232HP
+            | ChipType::FT2233HP
+            | ChipType::FT4232H
+            | ChipType::FT4232HA
+            | ChipType::FT4232HP
+            | ChipType::FT4233HP => &[Interface::A, Interface::B],
+            ChipType::FT2232C | ChipType::FT232H | ChipType::FT232HP | ChipType::FT233HP => {
+                &[Interface::A]
+            }
+            ChipType::Am | ChipType::Bm | ChipType::R | ChipType::FT230X => &[],
+        }
+    }
+
+    /// Returns the size of the MPSSE buffers in bytes, the most commands or replies the chip can
+    /// hold before the host has to catch up, or `None` if the chip has no MPSSE.
+    pub fn mpsse_buffer_size(self) -> Option<usize> {
+        match self {
+            ChipType::FT2232H | ChipType::FT2232HP | ChipType::FT2233HP => Some(4096),
+            ChipType::FT4232H | ChipType::FT4232HA | ChipType::FT4232HP | ChipType::FT4233HP => {
+                Some(2048)
+            }
+            ChipType::FT232H | ChipType::FT232HP | ChipType::FT233HP => Some(1024),
+            ChipType::FT2232C => Some(MIN_MPSSE_BUFFER_SIZE),
+            ChipType::Am | ChipType::Bm | ChipType::R | ChipType::FT230X => None,
+        }
+    }
+
+    /// Returns whether this is a Hi-Speed chip, running its UART from a 120 MHz clock and its
+    /// MPSSE from a 60 MHz one.
+    pub fn is_high_speed(self) -> bool {
+        matches!(
+            self,
+            ChipType::FT2232H
+                | ChipType::FT4232H
+                | ChipType::FT232H
+                | ChipType::FT4232HA
+                | ChipType::FT2233HP
+                | ChipType::FT4233HP
+                | ChipType::FT2232HP
+                | ChipType::FT4232HP
+                | ChipType::FT233HP
+                | ChipType::FT232HP
+        )
+    }
 }
 
 #[repr(C)]
@@ -77,8 +172,8 @@ impl Interface {
 }
 
 struct FtdiContext {
-    /// USB device handle
-    handle: nusb::Interface,
+    /// USB transport
+    transport: Box<dyn Transport>,
 
     /// FTDI device interface
     interface: Interface,
@@ -89,144 +184,595 @@ struct FtdiContext {
     read_queue: VecDeque<u8>,
     read_buffer: Box<[u8]>,
     max_packet_size: usize,
-
+    /// Maximum number of bulk IN transfers kept in flight for large reads
+    read_queue_depth: usize,
+    /// Size of each queued bulk IN transfer, a multiple of `max_packet_size`
+    read_transfer_size: usize,
+    /// Status reported with the most recently received packet
+    status: (ModemStatus, LineStatus),
+
+    /// The last UART line properties, kept to toggle the break condition
+    line_properties: u16,
+
+    /// How often to retry control requests, bulk transfer recovery is enabled if set
+    control_retries: Option<usize>,
+    /// How long to wait for the device to come back after it disappeared, if at all
+    reconnect_timeout: Option<Duration>,
+    /// Configuration restored after a reconnect
+    latency_timer: Option<u8>,
+    bitmask: u8,
     bitbang: Option<BitMode>,
+
+    /// Where the USB traffic is recorded to, if at all
+    recorder: Option<Recorder>,
 }
 
 impl FtdiContext {
-    fn sio_write(&mut self, request: u8, value: u16) -> Result<()> {
-        let result = self
-            .handle
-            .control_out_blocking(
-                Control {
-                    control_type: ControlType::Vendor,
-                    recipient: Recipient::Device,
-                    request,
-                    value,
-                    index: self.interface.index(),
-                },
-                &[],
-                self.usb_write_timeout,
-            )
-            .map_err(std::io::Error::from)?;
+    async fn sio_write(&mut self, request: u8, value: u16) -> Result<()> {
+        self.sio_write_index(request, value, self.interface.index())
+            .await
+    }
+
+    /// Sends a request whose index is not the interface, e.g. an EEPROM address.
+    ///
+    /// All SIO requests are idempotent, so transient failures are retried if recovery is enabled.
+    async fn sio_write_index(&mut self, request: u8, value: u16, index: u16) -> Result<()> {
+        let mut attempt = 0;
+        let result = loop {
+            let result = self
+                .transport
+                .control_out(request, value, index, self.usb_write_timeout)
+                .await;
+            match result {
+                Err(e) if self.should_retry(&e, attempt) => {
+                    debug!("retrying request {request:02X} after {e}");
+                    attempt += 1;
+                }
+                result => break result,
+            }
+        };
+        self.check(result).await?;
+        record(&mut self.recorder, || Event::ControlOut {
+            request,
+            value,
+            index,
+        });
 
-        debug!("Response to {:02X}/{:04X}: {:?}", request, value, result);
+        Ok(())
+    }
+
+    async fn sio_read(
+        &mut self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+    ) -> Result<()> {
+        let mut attempt = 0;
+        let result = loop {
+            let result = self
+                .transport
+                .control_in(request, value, index, buf, self.usb_read_timeout)
+                .await;
+            match result {
+                Err(e) if self.should_retry(&e, attempt) => {
+                    debug!("retrying request {request:02X} after {e}");
+                    attempt += 1;
+                }
+                result => break result,
+            }
+        };
+        let read = self.check(result).await?;
+        record(&mut self.recorder, || Event::ControlIn {
+            request,
+            value,
+            index,
+            data: buf[..read].to_vec(),
+        });
+
+        if read != buf.len() {
+            Err(FtdiError::Other(format!(
+                "short response to request {:02X}: {} of {} bytes",
+                request,
+                read,
+                buf.len()
+            )))?
+        }
 
         Ok(())
     }
 
-    fn usb_reset(&mut self) -> Result<()> {
+    async fn read_pins(&mut self) -> Result<u8> {
+        const SIO_READ_PINS_REQUEST: u8 = 0x0C;
+
+        let mut pins = [0];
+        self.sio_read(SIO_READ_PINS_REQUEST, 0, self.interface.index(), &mut pins)
+            .await?;
+
+        Ok(pins[0])
+    }
+
+    async fn poll_modem_status(&mut self) -> Result<(ModemStatus, LineStatus)> {
+        const SIO_POLL_MODEM_STATUS_REQUEST: u8 = 0x05;
+
+        let mut status = [0; 2];
+        self.sio_read(
+            SIO_POLL_MODEM_STATUS_REQUEST,
+            0,
+            self.interface.index(),
+            &mut status,
+        )
+        .await?;
+
+        Ok((
+            ModemStatus::from_bits(status[0]),
+            LineStatus::from_bits(status[1]),
+        ))
+    }
+
+    async fn read_eeprom_word(&mut self, addr: u16) -> Result<u16> {
+        const SIO_READ_EEPROM_REQUEST: u8 = 0x90;
+
+        let mut word = [0; 2];
+        self.sio_read(SIO_READ_EEPROM_REQUEST, 0, addr, &mut word)
+            .await?;
+
+        Ok(u16::from_le_bytes(word))
+    }
+
+    async fn write_eeprom_word(&mut self, addr: u16, value: u16) -> Result<()> {
+        const SIO_WRITE_EEPROM_REQUEST: u8 = 0x91;
+
+        self.sio_write_index(SIO_WRITE_EEPROM_REQUEST, value, addr)
+            .await
+    }
+
+    async fn erase_eeprom(&mut self) -> Result<()> {
+        const SIO_ERASE_EEPROM_REQUEST: u8 = 0x92;
+
+        self.sio_write_index(SIO_ERASE_EEPROM_REQUEST, 0, 0).await
+    }
+
+    async fn usb_reset(&mut self) -> Result<()> {
         const SIO_RESET_REQUEST: u8 = 0;
         const SIO_RESET_SIO: u16 = 0;
 
-        self.sio_write(SIO_RESET_REQUEST, SIO_RESET_SIO)
+        self.sio_write(SIO_RESET_REQUEST, SIO_RESET_SIO).await
     }
 
     /// Clears the write buffer on the chip.
-    fn usb_purge_tx_buffer(&mut self) -> Result<()> {
+    async fn usb_purge_tx_buffer(&mut self) -> Result<()> {
         const SIO_RESET_REQUEST: u8 = 0;
         const SIO_RESET_PURGE_TX: u16 = 2;
 
-        self.sio_write(SIO_RESET_REQUEST, SIO_RESET_PURGE_TX)
+        self.sio_write(SIO_RESET_REQUEST, SIO_RESET_PURGE_TX).await
     }
 
-    fn usb_purge_rx_buffer(&mut self) -> Result<()> {
+    async fn usb_purge_rx_buffer(&mut self) -> Result<()> {
         const SIO_RESET_REQUEST: u8 = 0;
         const SIO_RESET_PURGE_RX: u16 = 1;
 
-        self.sio_write(SIO_RESET_REQUEST, SIO_RESET_PURGE_RX)?;
+        self.sio_write(SIO_RESET_REQUEST, SIO_RESET_PURGE_RX)
+            .await?;
 
         self.read_queue.clear();
 
         Ok(())
     }
 
-    fn usb_purge_buffers(&mut self) -> Result<()> {
-        self.usb_purge_tx_buffer()?;
-        self.usb_purge_rx_buffer()?;
+    async fn usb_purge_buffers(&mut self) -> Result<()> {
+        self.usb_purge_tx_buffer().await?;
+        self.usb_purge_rx_buffer().await?;
 
         Ok(())
     }
 
-    fn set_latency_timer(&mut self, value: u8) -> Result<()> {
+    async fn set_latency_timer(&mut self, value: u8) -> Result<()> {
         const SIO_SET_LATENCY_TIMER_REQUEST: u8 = 0x09;
 
         self.sio_write(SIO_SET_LATENCY_TIMER_REQUEST, value as u16)
+            .await?;
+
+        self.latency_timer = Some(value);
+
+        Ok(())
     }
 
-    fn set_bitmode(&mut self, bitmask: u8, mode: BitMode) -> Result<()> {
+    async fn set_bitmode(&mut self, bitmask: u8, mode: BitMode) -> Result<()> {
         const SIO_SET_BITMODE_REQUEST: u8 = 0x0B;
 
         self.sio_write(
             SIO_SET_BITMODE_REQUEST,
             u16::from_le_bytes([bitmask, mode as u8]),
-        )?;
+        )
+        .await?;
 
+        self.bitmask = bitmask;
         self.bitbang = (mode != BitMode::Reset).then_some(mode);
 
         Ok(())
     }
 
-    fn read_data(&mut self, mut data: &mut [u8]) -> io::Result<usize> {
-        let mut total = 0;
-        while !data.is_empty() {
-            // Move data out of the read queue
-            if !self.read_queue.is_empty() {
-                let read = self.read_queue.read(data).unwrap();
-                debug!("Copied {} bytes from queue", read);
+    async fn set_baud_rate(&mut self, value: u16, index: u16) -> Result<()> {
+        const SIO_SET_BAUDRATE_REQUEST: u8 = 0x03;
+
+        self.sio_write_index(SIO_SET_BAUDRATE_REQUEST, value, index)
+            .await
+    }
+
+    async fn set_line_properties(&mut self, properties: u16, break_on: bool) -> Result<()> {
+        const SIO_SET_DATA_REQUEST: u8 = 0x04;
+
+        self.sio_write(SIO_SET_DATA_REQUEST, properties | (break_on as u16) << 14)
+            .await?;
+
+        self.line_properties = properties;
+
+        Ok(())
+    }
+
+    async fn set_flow_control(&mut self, flow_control: uart::FlowControl) -> Result<()> {
+        const SIO_SET_FLOW_CTRL_REQUEST: u8 = 0x02;
+
+        let (value, index) = flow_control.request();
+        self.sio_write_index(
+            SIO_SET_FLOW_CTRL_REQUEST,
+            value,
+            index | self.interface.index(),
+        )
+        .await
+    }
+
+    /// Sets DTR and/or RTS, leaving a line alone if its value is `None`.
+    async fn set_modem_control(&mut self, dtr: Option<bool>, rts: Option<bool>) -> Result<()> {
+        const SIO_SET_MODEM_CTRL_REQUEST: u8 = 0x01;
+
+        // The high byte selects the lines to change, the low byte their level.
+        let mut value = 0;
+        if let Some(dtr) = dtr {
+            value |= 0x0100 | dtr as u16;
+        }
+        if let Some(rts) = rts {
+            value |= 0x0200 | (rts as u16) << 1;
+        }
+
+        self.sio_write(SIO_SET_MODEM_CTRL_REQUEST, value).await
+    }
+
+    /// Passes `result` through, unless it says the device is gone and reconnecting is enabled.
+    /// Then the device is reopened, the latency timer and bitmode are restored, and
+    /// [`FtdiError::Reconnected`] is returned instead.
+    async fn check<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        match result {
+            Err(e) if self.reconnect_timeout.is_some() && usb_util::is_disconnect(&e) => {
+                warn!("device disconnected: {e}");
+                self.reconnect().await?;
+                Err(io::Error::other(FtdiError::Reconnected))
+            }
+            result => result,
+        }
+    }
+
+    fn should_retry(&self, err: &io::Error, attempt: usize) -> bool {
+        self.control_retries
+            .is_some_and(|retries| attempt < retries && usb_util::is_transient(err))
+    }
+
+    /// Like [`Self::check`], but if recovery is enabled a transient failure of a bulk transfer on
+    /// `endpoint` makes the channel usable again and is reported as [`FtdiError::Recovered`].
+    ///
+    /// If recovering fails as well, the original error is returned.
+    async fn check_bulk<T>(&mut self, endpoint: u8, result: io::Result<T>) -> io::Result<T> {
+        match result {
+            Err(e) if self.control_retries.is_some() && usb_util::is_transient(&e) => {
+                warn!("bulk transfer on endpoint {endpoint:#04x} failed: {e}");
+                match self.recover(endpoint).await {
+                    Ok(state_lost) => Err(io::Error::other(FtdiError::Recovered { state_lost })),
+                    Err(recovery_error) => {
+                        warn!("recovery failed: {recovery_error}");
+                        Err(e)
+                    }
+                }
+            }
+            result => self.check(result).await,
+        }
+    }
+
+    /// Clears a halt on `endpoint`, purges the chip buffers and, in MPSSE mode, resynchronises
+    /// the command processor. Returns whether the MPSSE state was lost.
+    async fn recover(&mut self, endpoint: u8) -> io::Result<bool> {
+        self.transport.clear_halt(endpoint).await?;
+        self.usb_purge_buffers().await.map_err(io::Error::other)?;
+
+        if self.bitbang != Some(BitMode::Mpsse) {
+            return Ok(false);
+        }
+        self.sync_mpsse().await?;
+
+        // Whatever commands were in flight may or may not have been executed.
+        Ok(true)
+    }
+
+    /// Synchronises with the MPSSE command processor, see AN135 section 5.3: sends the bogus
+    /// opcodes 0xAA and 0xAB, waiting up to the read timeout for each to be echoed after 0xFA.
+    ///
+    /// Everything received before the echoes is dropped, anything after them stays queued.
+    async fn sync_mpsse(&mut self) -> io::Result<()> {
+        const BOGUS_OPCODES: [u8; 2] = [0xAA, 0xAB];
+        const BAD_COMMAND_RESPONSE: u8 = 0xFA;
+
+        for opcode in BOGUS_OPCODES {
+            self.transport
+                .write_bulk(self.interface.write_ep(), &[opcode], self.usb_write_timeout)
+                .await?;
+            record(&mut self.recorder, || Event::BulkOut(vec![opcode]));
+
+            let deadline = Instant::now() + self.usb_read_timeout;
+            loop {
+                let queue = &mut self.read_queue;
+                let echo = (1..queue.len())
+                    .find(|&i| queue[i - 1] == BAD_COMMAND_RESPONSE && queue[i] == opcode);
+                if let Some(i) = echo {
+                    queue.drain(..=i);
+                    break;
+                }
+                if Instant::now() >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("MPSSE did not echo bogus opcode {opcode:#04x}"),
+                    ));
+                }
+
+                let read = self
+                    .transport
+                    .read_bulk(
+                        self.interface.read_ep(),
+                        &mut self.read_buffer,
+                        self.usb_read_timeout,
+                    )
+                    .await?;
+                let transfer = &self.read_buffer[..read];
+                self.read_queue
+                    .extend(payload(transfer, self.max_packet_size));
+                record(&mut self.recorder, || {
+                    Event::bulk_in(transfer, self.max_packet_size)
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn reconnect(&mut self) -> io::Result<()> {
+        const SIO_RESET_REQUEST: u8 = 0;
+        const SIO_RESET_SIO: u16 = 0;
+        const SIO_SET_LATENCY_TIMER_REQUEST: u8 = 0x09;
+        const SIO_SET_BITMODE_REQUEST: u8 = 0x0B;
 
-                data = &mut data[read..];
-                total += read;
+        let timeout = self.reconnect_timeout.unwrap_or_default();
+        self.transport.reconnect(timeout).await?;
+
+        self.read_queue.clear();
+        self.status = Default::default();
+
+        // Talk to the transport directly, a failure here must not trigger another reconnect.
+        let index = self.interface.index();
+        let timeout = self.usb_write_timeout;
+        self.transport
+            .control_out(SIO_RESET_REQUEST, SIO_RESET_SIO, index, timeout)
+            .await?;
+        if let Some(latency) = self.latency_timer {
+            self.transport
+                .control_out(
+                    SIO_SET_LATENCY_TIMER_REQUEST,
+                    latency as u16,
+                    index,
+                    timeout,
+                )
+                .await?;
+        }
+        if let Some(mode) = self.bitbang {
+            let value = u16::from_le_bytes([self.bitmask, mode as u8]);
+            self.transport
+                .control_out(SIO_SET_BITMODE_REQUEST, value, index, timeout)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn read_data(&mut self, data: &mut [u8]) -> io::Result<usize> {
+        // Move data out of the read queue
+        let mut filled = self.read_queue.read(data).unwrap();
+        if filled > 0 {
+            debug!("Copied {} bytes from queue", filled);
+        }
+
+        while filled < data.len() {
+            // Large reads keep several transfers in flight
+            if data.len() - filled > self.max_packet_size - 2 && self.read_queue_depth > 1 {
+                filled += self.read_data_queued(&mut data[filled..]).await?;
+                break;
             }
 
             // Read from USB
-            if !data.is_empty() {
-                let read = self.handle.read_bulk(
+            let result = self
+                .transport
+                .read_bulk(
                     self.interface.read_ep(),
                     &mut self.read_buffer,
                     self.usb_read_timeout,
+                )
+                .await;
+            let read = self.check_bulk(self.interface.read_ep(), result).await?;
+
+            debug!("Read {:02x?} bytes from USB", &self.read_buffer[..read]);
+            record(&mut self.recorder, || {
+                Event::bulk_in(&self.read_buffer[..read], self.max_packet_size)
+            });
+
+            let mut received = false;
+            for packet in status::packets(&self.read_buffer[..read], self.max_packet_size) {
+                received |= !packet.payload.is_empty();
+                receive_packet(
+                    packet,
+                    data,
+                    &mut filled,
+                    &mut self.read_queue,
+                    &mut self.status,
                 )?;
+            }
 
-                debug!("Read {:02x?} bytes from USB", &self.read_buffer[..read]);
-
-                if read <= 2 {
-                    // No more data to read.
-                    break;
-                }
+            if !received {
+                // No more data to read.
+                break;
+            }
 
-                let (status, read_data) = self.read_buffer[..read].split_at(2);
+            if !self.read_queue.is_empty() {
+                debug!("Queued {} bytes from USB", self.read_queue.len());
+                break;
+            }
+        }
 
-                debug!("Status: {:02X?} [{} data]", status, read);
+        debug!("read {} bytes", filled);
 
-                let copy = read_data.len().min(data.len());
-                let (to_buffer, to_save) = read_data.split_at(copy);
+        Ok(filled)
+    }
 
-                if copy > 0 {
-                    data[..copy].copy_from_slice(to_buffer);
-                    data = &mut data[copy..];
-                    debug!("Copied {} bytes from USB", copy);
-                    total += copy;
+    /// Fills `data` using queued bulk IN transfers, keeping any surplus in the read queue.
+    ///
+    /// Stops early if a transfer comes back without data, like a single `read_bulk` would.
+    async fn read_data_queued(&mut self, data: &mut [u8]) -> io::Result<usize> {
+        let packet_size = self.max_packet_size;
+        let transfer_size = self.read_transfer_size;
+        let transfer_payload = transfer_size / packet_size * (packet_size - 2);
+        let depth = data
+            .len()
+            .div_ceil(transfer_payload)
+            .min(self.read_queue_depth);
+
+        let read_queue = &mut self.read_queue;
+        let status = &mut self.status;
+        let recorder = &mut self.recorder;
+        let mut filled = 0;
+        let mut result = Ok(());
+        let mut sink = |transfer: &[u8]| {
+            record(recorder, || Event::bulk_in(transfer, packet_size));
+            let mut received = false;
+            for packet in status::packets(transfer, packet_size) {
+                received |= !packet.payload.is_empty();
+                if result.is_ok() {
+                    result = receive_packet(packet, data, &mut filled, read_queue, status);
                 }
+            }
+            result.is_ok() && received && filled < data.len()
+        };
 
-                if !to_save.is_empty() {
-                    debug!("Queued {} bytes from USB", to_save.len());
-                    self.read_queue.extend(to_save);
-                    break;
+        let queued = self
+            .transport
+            .read_bulk_queued(
+                self.interface.read_ep(),
+                transfer_size,
+                depth,
+                self.usb_read_timeout,
+                &mut sink,
+            )
+            .await;
+        self.check_bulk(self.interface.read_ep(), queued).await?;
+        result?;
+
+        debug!(
+            "Read {} bytes from USB with {} queued transfers, queued {} bytes",
+            filled,
+            depth,
+            self.read_queue.len()
+        );
+
+        Ok(filled)
+    }
+
+    /// Passes received data to `sink` using queued bulk IN transfers until it returns `false`.
+    ///
+    /// Overruns are counted rather than treated as errors, the count is returned. Data received
+    /// after `sink` asked to stop is kept in the read queue.
+    async fn read_stream(&mut self, sink: &mut TransferSink<'_>) -> io::Result<u64> {
+        let queued: Vec<u8> = self.read_queue.drain(..).collect();
+        let mut keep_going = queued.is_empty() || sink(&queued);
+        if !keep_going {
+            return Ok(0);
+        }
+
+        let packet_size = self.max_packet_size;
+        let read_queue = &mut self.read_queue;
+        let status = &mut self.status;
+        let recorder = &mut self.recorder;
+        let mut overruns = 0;
+        let mut transfer_sink = |transfer: &[u8]| {
+            record(recorder, || Event::bulk_in(transfer, packet_size));
+            for packet in status::packets(transfer, packet_size) {
+                *status = (packet.modem, packet.line);
+                overruns += packet.line.overrun() as u64;
+                if packet.payload.is_empty() {
+                    continue;
+                }
+                if keep_going {
+                    keep_going = sink(packet.payload);
+                } else {
+                    read_queue.extend(packet.payload);
                 }
             }
-        }
+            keep_going
+        };
 
-        debug!("read {} bytes", total);
+        let queued = self
+            .transport
+            .read_bulk_queued(
+                self.interface.read_ep(),
+                self.read_transfer_size,
+                self.read_queue_depth,
+                self.usb_read_timeout,
+                &mut transfer_sink,
+            )
+            .await;
+        self.check_bulk(self.interface.read_ep(), queued).await?;
 
-        Ok(total)
+        Ok(overruns)
+    }
+
+    /// Writes the data produced by `source` using queued bulk OUT transfers, until it leaves
+    /// its buffer empty.
+    async fn write_stream(&mut self, source: &mut TransferSource<'_>) -> io::Result<()> {
+        let recorder = &mut self.recorder;
+        let mut source = |buf: &mut Vec<u8>| {
+            source(buf);
+            if !buf.is_empty() {
+                record(recorder, || Event::BulkOut(buf.clone()));
+            }
+        };
+        let result = self
+            .transport
+            .write_bulk_queued(
+                self.interface.write_ep(),
+                self.read_queue_depth,
+                self.usb_write_timeout,
+                &mut source,
+            )
+            .await;
+        self.check_bulk(self.interface.write_ep(), result).await
     }
 
-    fn write_data(&mut self, data: &[u8]) -> io::Result<usize> {
+    async fn write_data(&mut self, data: &[u8]) -> io::Result<usize> {
         let mut total = 0;
         for chunk in data.chunks(self.max_packet_size) {
-            total +=
-                self.handle
-                    .write_bulk(self.interface.write_ep(), chunk, self.usb_write_timeout)?;
+            let result = self
+                .transport
+                .write_bulk(self.interface.write_ep(), chunk, self.usb_write_timeout)
+                .await;
+            let written = self.check_bulk(self.interface.write_ep(), result).await?;
+            record(&mut self.recorder, || {
+                Event::BulkOut(chunk[..written].to_vec())
+            });
+            total += written;
         }
 
         debug!("wrote {} bytes", total);
@@ -235,10 +781,65 @@ impl FtdiContext {
     }
 }
 
+/// Returns the payload of the packets in `transfer`, without their status bytes.
+fn payload(transfer: &[u8], max_packet_size: usize) -> Vec<u8> {
+    status::packets(transfer, max_packet_size)
+        .flat_map(|packet| packet.payload)
+        .copied()
+        .collect()
+}
+
+/// Records the status of `packet` and moves its payload into `data[*filled..]`, queueing
+/// whatever doesn't fit.
+///
+/// Fails if the chip reports that its receive buffer overran.
+fn receive_packet(
+    packet: Packet,
+    data: &mut [u8],
+    filled: &mut usize,
+    read_queue: &mut VecDeque<u8>,
+    status: &mut (ModemStatus, LineStatus),
+) -> io::Result<()> {
+    *status = (packet.modem, packet.line);
+    if packet.line.overrun() {
+        return Err(io::Error::other(FtdiError::Overrun));
+    }
+
+    let copy = packet.payload.len().min(data.len() - *filled);
+    data[*filled..][..copy].copy_from_slice(&packet.payload[..copy]);
+    *filled += copy;
+    read_queue.extend(&packet.payload[copy..]);
+
+    Ok(())
+}
+
+/// Something that identifies a USB device to open with [`Builder::usb_open`].
+pub trait UsbTarget {
+    /// Finds the device, along with the interface to open if the target names one.
+    fn resolve(self) -> Result<(DeviceInfo, Option<Interface>), JtagProbeError>;
+}
+
+impl UsbTarget for DeviceInfo {
+    fn resolve(self) -> Result<(DeviceInfo, Option<Interface>), JtagProbeError> {
+        Ok((self, None))
+    }
+}
+
 pub struct Builder {
     interface: Interface,
     read_timeout: Duration,
     write_timeout: Duration,
+    read_queue_depth: usize,
+    read_transfer_size: usize,
+    reconnect_timeout: Option<Duration>,
+    control_retries: Option<usize>,
+    recording: Option<Box<dyn Write + Send>>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Builder {
@@ -247,6 +848,11 @@ impl Builder {
             interface: Interface::A,
             read_timeout: Duration::from_secs(5),
             write_timeout: Duration::from_secs(5),
+            read_queue_depth: 4,
+            read_transfer_size: 16384,
+            reconnect_timeout: None,
+            control_retries: None,
+            recording: None,
         }
     }
 
@@ -265,23 +871,200 @@ impl Builder {
         self
     }
 
-    pub fn usb_open(self, usb_device: DeviceInfo) -> Result<Device, JtagProbeError> {
+    /// Sets how many bulk IN transfers are kept in flight for reads larger than a packet.
+    ///
+    /// A depth of 1 reads one packet at a time.
+    pub const fn with_read_queue_depth(mut self, depth: usize) -> Self {
+        self.read_queue_depth = depth;
+        self
+    }
+
+    /// Sets the size of each queued bulk IN transfer, rounded down to whole packets.
+    pub const fn with_read_transfer_size(mut self, size: usize) -> Self {
+        self.read_transfer_size = size;
+        self
+    }
+
+    /// Reopens the device when it disappears, e.g. because the board was power cycled, waiting
+    /// up to `timeout` for it to come back.
+    ///
+    /// The operation that noticed the disconnect fails with [`FtdiError::Reconnected`], as
+    /// the chip lost its state. The latency timer and bitmode are restored before that.
+    pub const fn with_reconnect(mut self, timeout: Duration) -> Self {
+        self.reconnect_timeout = Some(timeout);
+        self
+    }
+
+    /// Recovers from transient USB errors such as stalls and timeouts.
+    ///
+    /// Failed control requests are retried up to `retries` times. After a failed bulk transfer,
+    /// the endpoint halt is cleared, the chip buffers are purged and the MPSSE, if enabled, is
+    /// resynchronised. The failed operation then returns [`FtdiError::Recovered`].
+    pub const fn with_recovery(mut self, retries: usize) -> Self {
+        self.control_retries = Some(retries);
+        self
+    }
+
+    /// Records all USB traffic of the device to `writer`, in the format described in
+    /// [`recording`]. It can be played back with a [`ReplayTransport`](transport::ReplayTransport).
+    pub fn with_recording(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.recording = Some(Box::new(writer));
+        self
+    }
+
+    /// Opens a USB device, given as a [`DeviceInfo`] or anything else that resolves to one,
+    /// such as a [`ProbeSelector`](crate::ProbeSelector).
+    ///
+    /// An interface named by `target` takes precedence over [`Builder::with_interface`].
+    pub fn usb_open(mut self, target: impl UsbTarget) -> Result<Device, JtagProbeError> {
         debug!("usb_open");
-        let mut device = Device::open(usb_device, self.interface)?;
+        let (usb_device, interface) = target.resolve()?;
+        if let Some(interface) = interface {
+            self.interface = interface;
+        }
+        let transport = NusbTransport::open(usb_device, self.interface)?;
+
+        self.transport_open(transport)
+    }
+
+    /// Opens the configured interface of a [`DeviceHandle`] as an independent channel.
+    ///
+    /// Fails if the chip has no such interface or it is already open.
+    pub fn handle_open(self, handle: &DeviceHandle) -> Result<Device, JtagProbeError> {
+        if !handle.interfaces().contains(&self.interface) {
+            Err(FtdiError::Other(format!(
+                "{:?} has no interface {:?}",
+                handle.chip_type, self.interface
+            )))?
+        }
+        let claim = ChannelClaim::new(&handle.claimed, self.interface).ok_or_else(|| {
+            FtdiError::Other(format!("interface {:?} is already open", self.interface))
+        })?;
+
+        let transport = NusbTransport::claim(&handle.handle, &handle.usb_device, self.interface)?;
+        let mut device = self.transport_open(transport)?;
+        device.channel = Some(claim);
+
+        Ok(device)
+    }
+
+    /// Opens a device on top of an arbitrary [`Transport`], e.g. an in-memory one for testing.
+    pub fn transport_open(
+        self,
+        transport: impl Transport + 'static,
+    ) -> Result<Device, JtagProbeError> {
+        let mut device = Device::new(Box::new(transport), self.interface);
 
         device.context.usb_read_timeout = self.read_timeout;
         device.context.usb_write_timeout = self.write_timeout;
+        device.context.read_queue_depth = self.read_queue_depth.max(1);
+        device.context.reconnect_timeout = self.reconnect_timeout;
+        device.context.control_retries = self.control_retries;
+        if let Some(writer) = self.recording {
+            let transport = &device.context.transport;
+            device.context.recorder = Some(
+                Recorder::new(writer, transport.descriptor(), transport.max_packet_size())
+                    .map_err(FtdiError::from)?,
+            );
+        }
+        let max_packet_size = device.context.max_packet_size;
+        device.context.read_transfer_size =
+            (self.read_transfer_size / max_packet_size).max(1) * max_packet_size;
 
         Ok(device)
     }
 }
 
+/// A USB device opened once, handing out a [`Device`] per interface.
+///
+/// On multi-channel chips such as the FT2232H and FT4232H each interface is an independent
+/// channel with its own bitmode, latency timer and buffers. The channels can be moved to
+/// different threads, e.g. to run JTAG on A while B is a UART.
+pub struct DeviceHandle {
+    handle: nusb::Device,
+    usb_device: DeviceInfo,
+    chip_type: Option<ChipType>,
+    claimed: Arc<Mutex<Vec<Interface>>>,
+}
+
+impl DeviceHandle {
+    /// Opens the USB device. An interface named by `target` is ignored, pick one per channel
+    /// with [`Builder::with_interface`] instead.
+    pub fn open(target: impl UsbTarget) -> Result<Self, JtagProbeError> {
+        let (usb_device, _) = target.resolve()?;
+        let handle = transport::open_device(&usb_device)?;
+        let chip_type = DeviceDescriptor::from(&usb_device).chip_type();
+
+        Ok(Self {
+            handle,
+            usb_device,
+            chip_type,
+            claimed: Default::default(),
+        })
+    }
+
+    pub fn chip_type(&self) -> Option<ChipType> {
+        self.chip_type
+    }
+
+    /// Returns the interfaces that can be opened as channels.
+    pub fn interfaces(&self) -> &'static [Interface] {
+        self.chip_type.map_or(&[Interface::A], ChipType::interfaces)
+    }
+
+    /// Opens `interface` with the default [`Builder`] settings.
+    pub fn open_channel(&self, interface: Interface) -> Result<Device, JtagProbeError> {
+        Builder::new().with_interface(interface).handle_open(self)
+    }
+}
+
+impl std::fmt::Debug for DeviceHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceHandle")
+            .field("chip_type", &self.chip_type)
+            .field("claimed", &self.claimed.lock().unwrap())
+            .finish()
+    }
+}
+
+/// Marks an interface of a [`DeviceHandle`] as open for as long as its [`Device`] lives.
+struct ChannelClaim {
+    claimed: Arc<Mutex<Vec<Interface>>>,
+    interface: Interface,
+}
+
+impl ChannelClaim {
+    fn new(claimed: &Arc<Mutex<Vec<Interface>>>, interface: Interface) -> Option<Self> {
+        let mut list = claimed.lock().unwrap();
+        if list.contains(&interface) {
+            return None;
+        }
+        list.push(interface);
+
+        Some(Self {
+            claimed: claimed.clone(),
+            interface,
+        })
+    }
+}
+
+impl Drop for ChannelClaim {
+    fn drop(&mut self) {
+        self.claimed
+            .lock()
+            .unwrap()
+            .retain(|&interface| interface != self.interface);
+    }
+}
+
 pub struct Device {
     context: FtdiContext,
     chip_type: Option<ChipType>,
     vendor_id: u16,
     product_id: u16,
     product_string: Option<String>,
+    /// Set when the device is a channel of a [`DeviceHandle`]
+    channel: Option<ChannelClaim>,
 }
 
 impl std::fmt::Debug for Device {
@@ -293,175 +1076,352 @@ impl std::fmt::Debug for Device {
 }
 
 impl Device {
-    fn open(usb_device: DeviceInfo, interface: Interface) -> Result<Self, JtagProbeError> {
-        fn open_error(e: std::io::Error, while_: &'static str) -> JtagProbeError {
-            let help = if cfg!(windows) {
-                "(this error may be caused by not having the WinUSB driver installed; use Zadig (https://zadig.akeo.ie/) to install it for the FTDI device; this will replace the FTDI driver)"
-            } else {
-                ""
-            };
-
-            JtagProbeError::Usb(std::io::Error::other(format!(
-                "error while {while_}: {e}{help}",
-            )))
-        }
-
-        let handle = usb_device
-            .open()
-            .map_err(|e| open_error(e, "opening the USB device"))?;
-
-        let configs: Vec<_> = handle.configurations().collect();
-
-        let conf = &configs[0];
-        if configs.len() != 1 {
-            warn!("device has {} configurations, expected 1", configs.len());
-
-            if configs.len() > 1 {
-                let configuration = handle
-                    .active_configuration()
-                    .map_err(FtdiError::ActiveConfigurationError)?
-                    .configuration_value();
-
-                if configuration != conf.configuration_value() {
-                    handle
-                        .set_configuration(conf.configuration_value())
-                        .map_err(FtdiError::Usb)?;
-                }
-            }
-        }
-
-        debug!("scanning {} interfaces", conf.interfaces().count());
-        trace!("active configuration descriptor: {:#x?}", conf);
-
-        let mut usb_interface = None;
-
-        // Try to find the specified interface
-        for intf in conf.interfaces() {
-            trace!("interface #{} descriptors:", intf.interface_number());
-
-            for descr in intf.alt_settings() {
-                trace!("{:#x?}", descr);
-
-                let endpoints: Vec<_> = descr.endpoints().collect();
-                trace!("endpoint descriptors: {:#x?}", endpoints);
-
-                if endpoints
-                    .iter()
-                    .any(|ep| ep.transfer_type() != EndpointType::Bulk)
-                {
-                    warn!(
-                        "encountered non-bulk endpoints, skipping interface: {:#x?}",
-                        endpoints
-                    );
-                    continue;
-                }
-
-                let endpoint_count = endpoints.len();
-                let Ok::<[_; 2], _>([read_ep, write_ep]) = endpoints.try_into() else {
-                    warn!(
-                        "skipping interface with {} endpoints, expected 2",
-                        endpoint_count
-                    );
-                    continue;
-                };
-
-                let (read_ep, write_ep) = if read_ep.direction() == Direction::In {
-                    (read_ep, write_ep)
-                } else {
-                    (write_ep, read_ep)
-                };
-
-                if read_ep.address() != interface.read_ep()
-                    || write_ep.address() != interface.write_ep()
-                {
-                    debug!(
-                        "interface {} does not match requested interface {:?}",
-                        descr.interface_number(),
-                        interface
-                    );
-                    continue;
-                }
-
-                if let Some((intf, _)) = usb_interface {
-                    Err(FtdiError::Other(format!(
-                        "found multiple matching USB interfaces ({} and {})",
-                        intf,
-                        descr.interface_number()
-                    )))?
-                }
-
-                usb_interface = Some((descr.interface_number(), write_ep.max_packet_size()));
-                debug!("Interface is #{}", descr.interface_number());
-            }
-        }
-
-        let Some((intf, max_packet_size)) = usb_interface else {
-            Err(FtdiError::Other("device is not a FTDI device".to_string()))?
-        };
-
-        let chip_type = match (
-            usb_device.device_version(),
-            usb_device.serial_number().unwrap_or(""),
-        ) {
-            (0x400, _) | (0x200, "") => Some(ChipType::Bm),
-            (0x200, _) => Some(ChipType::Am),
-            (0x500, _) => Some(ChipType::FT2232C),
-            (0x600, _) => Some(ChipType::R),
-            (0x700, _) => Some(ChipType::FT2232H),
-            (0x800, _) => Some(ChipType::FT4232H),
-            (0x900, _) => Some(ChipType::FT232H),
-            (0x1000, _) => Some(ChipType::FT230X),
-
-            (version, _) => {
-                warn!("Unknown FTDI device version: {:X?}", version);
-                None
-            }
-        };
-
-        let handle = handle
-            .detach_and_claim_interface(intf)
-            .map_err(|e| open_error(e, "taking control over USB device"))?;
+    fn new(transport: Box<dyn Transport>, interface: Interface) -> Self {
+        let descriptor = transport.descriptor().clone();
+        let chip_type = descriptor.chip_type();
+        let max_packet_size = transport.max_packet_size();
 
         debug!("Opened FTDI device: {:?}", chip_type);
 
-        Ok(Self {
+        Self {
             context: FtdiContext {
-                handle,
+                transport,
                 interface,
                 usb_read_timeout: Duration::from_secs(5),
                 usb_write_timeout: Duration::from_secs(5),
                 read_queue: VecDeque::new(),
                 read_buffer: vec![0; max_packet_size].into_boxed_slice(),
                 max_packet_size,
+                read_queue_depth: 1,
+                read_transfer_size: max_packet_size,
+                status: Default::default(),
+                line_properties: uart::line_properties(
+                    uart::DataBits::Eight,
+                    uart::Parity::None,
+                    uart::StopBits::One,
+                ),
+                control_retries: None,
+                reconnect_timeout: None,
+                latency_timer: None,
+                bitmask: 0,
                 bitbang: None,
+                recorder: None,
             },
             chip_type,
-            vendor_id: usb_device.vendor_id(),
-            product_id: usb_device.product_id(),
-            product_string: usb_device.product_string().map(|s| s.to_string()),
-        })
+            vendor_id: descriptor.vendor_id,
+            product_id: descriptor.product_id,
+            product_string: descriptor.product_string,
+            channel: None,
+        }
     }
 
     pub fn usb_reset(&mut self) -> Result<()> {
-        self.context.usb_reset()
+        block_on(self.usb_reset_async())
+    }
+
+    pub async fn usb_reset_async(&mut self) -> Result<()> {
+        self.context.usb_reset().await
     }
 
     pub fn usb_purge_buffers(&mut self) -> Result<()> {
-        self.context.usb_purge_buffers()
+        block_on(self.usb_purge_buffers_async())
+    }
+
+    pub async fn usb_purge_buffers_async(&mut self) -> Result<()> {
+        self.context.usb_purge_buffers().await
     }
 
     pub fn set_latency_timer(&mut self, value: u8) -> Result<()> {
-        self.context.set_latency_timer(value)
+        block_on(self.set_latency_timer_async(value))
+    }
+
+    pub async fn set_latency_timer_async(&mut self, value: u8) -> Result<()> {
+        self.context.set_latency_timer(value).await
     }
 
     pub fn set_bitmode(&mut self, bitmask: u8, mode: BitMode) -> Result<()> {
-        self.context.set_bitmode(bitmask, mode)
+        block_on(self.set_bitmode_async(bitmask, mode))
+    }
+
+    pub async fn set_bitmode_async(&mut self, bitmask: u8, mode: BitMode) -> Result<()> {
+        self.context.set_bitmode(bitmask, mode).await
     }
 
     pub fn chip_type(&self) -> Option<ChipType> {
         self.chip_type
     }
 
+    pub fn interface(&self) -> Interface {
+        self.context.interface
+    }
+
+    /// Returns the number of bytes received but not read yet, without asking the chip for more.
+    pub(crate) fn queued_len(&self) -> usize {
+        self.context.read_queue.len()
+    }
+
+    /// Returns the size of the chip's MPSSE buffers, assuming the smallest one if the chip type
+    /// is unknown so that batches never overflow it.
+    pub(crate) fn mpsse_buffer_size(&self) -> usize {
+        self.chip_type
+            .and_then(ChipType::mpsse_buffer_size)
+            .unwrap_or(MIN_MPSSE_BUFFER_SIZE)
+    }
+
+    /// Sets the UART baud rate and returns the rate the chip actually runs at.
+    ///
+    /// In bitbang mode this sets the pin update rate instead, the chip runs at 4 times the rate.
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<u32> {
+        block_on(self.set_baud_rate_async(baud_rate))
+    }
+
+    pub async fn set_baud_rate_async(&mut self, baud_rate: u32) -> Result<u32> {
+        let chip_type = self
+            .chip_type
+            .ok_or_else(|| FtdiError::Other("unknown chip type".to_string()))?;
+        let multiplier = match self.context.bitbang {
+            Some(_) => 4,
+            None => 1,
+        };
+
+        let (actual, value, index) = uart::baud_rate_request(
+            chip_type,
+            self.context.interface.index(),
+            baud_rate.saturating_mul(multiplier),
+        )?;
+        self.context.set_baud_rate(value, index).await?;
+
+        Ok(actual / multiplier)
+    }
+
+    pub fn set_line_properties(
+        &mut self,
+        data_bits: uart::DataBits,
+        parity: uart::Parity,
+        stop_bits: uart::StopBits,
+    ) -> Result<()> {
+        block_on(self.set_line_properties_async(data_bits, parity, stop_bits))
+    }
+
+    pub async fn set_line_properties_async(
+        &mut self,
+        data_bits: uart::DataBits,
+        parity: uart::Parity,
+        stop_bits: uart::StopBits,
+    ) -> Result<()> {
+        let properties = uart::line_properties(data_bits, parity, stop_bits);
+        self.context.set_line_properties(properties, false).await
+    }
+
+    /// Holds the TX line low (`true`) or releases it, keeping the line properties.
+    pub fn set_break(&mut self, on: bool) -> Result<()> {
+        block_on(self.set_break_async(on))
+    }
+
+    pub async fn set_break_async(&mut self, on: bool) -> Result<()> {
+        let properties = self.context.line_properties;
+        self.context.set_line_properties(properties, on).await
+    }
+
+    /// Sends a break condition for `duration`.
+    pub fn send_break(&mut self, duration: Duration) -> Result<()> {
+        block_on(self.send_break_async(duration))
+    }
+
+    pub async fn send_break_async(&mut self, duration: Duration) -> Result<()> {
+        self.set_break_async(true).await?;
+        async_io::Timer::after(duration).await;
+        self.set_break_async(false).await
+    }
+
+    pub fn set_flow_control(&mut self, flow_control: uart::FlowControl) -> Result<()> {
+        block_on(self.set_flow_control_async(flow_control))
+    }
+
+    pub async fn set_flow_control_async(&mut self, flow_control: uart::FlowControl) -> Result<()> {
+        self.context.set_flow_control(flow_control).await
+    }
+
+    pub fn set_dtr(&mut self, state: bool) -> Result<()> {
+        block_on(self.set_dtr_async(state))
+    }
+
+    pub async fn set_dtr_async(&mut self, state: bool) -> Result<()> {
+        self.context.set_modem_control(Some(state), None).await
+    }
+
+    pub fn set_rts(&mut self, state: bool) -> Result<()> {
+        block_on(self.set_rts_async(state))
+    }
+
+    pub async fn set_rts_async(&mut self, state: bool) -> Result<()> {
+        self.context.set_modem_control(None, Some(state)).await
+    }
+
+    /// Sets DTR and RTS with a single request.
+    pub fn set_dtr_rts(&mut self, dtr: bool, rts: bool) -> Result<()> {
+        block_on(self.set_dtr_rts_async(dtr, rts))
+    }
+
+    pub async fn set_dtr_rts_async(&mut self, dtr: bool, rts: bool) -> Result<()> {
+        self.context.set_modem_control(Some(dtr), Some(rts)).await
+    }
+
+    /// Reads the current state of the 8 pins of the channel.
+    pub fn read_pins(&mut self) -> Result<u8> {
+        block_on(self.read_pins_async())
+    }
+
+    pub async fn read_pins_async(&mut self) -> Result<u8> {
+        self.context.read_pins().await
+    }
+
+    /// Asks the chip for its current modem and line status.
+    pub fn poll_modem_status(&mut self) -> Result<(ModemStatus, LineStatus)> {
+        block_on(self.poll_modem_status_async())
+    }
+
+    pub async fn poll_modem_status_async(&mut self) -> Result<(ModemStatus, LineStatus)> {
+        let status = self.context.poll_modem_status().await?;
+        self.context.status = status;
+        Ok(status)
+    }
+
+    /// Reads the 16-bit word at word address `addr` of the configuration EEPROM.
+    pub fn read_eeprom_word(&mut self, addr: u16) -> Result<u16> {
+        block_on(self.read_eeprom_word_async(addr))
+    }
+
+    pub async fn read_eeprom_word_async(&mut self, addr: u16) -> Result<u16> {
+        self.context.read_eeprom_word(addr).await
+    }
+
+    /// Writes a single word of the configuration EEPROM, leaving the checksum stale.
+    pub fn write_eeprom_word(&mut self, addr: u16, value: u16) -> Result<()> {
+        block_on(self.write_eeprom_word_async(addr, value))
+    }
+
+    pub async fn write_eeprom_word_async(&mut self, addr: u16, value: u16) -> Result<()> {
+        self.context.write_eeprom_word(addr, value).await
+    }
+
+    /// Erases the external configuration EEPROM.
+    ///
+    /// The FT232R and FT-X keep their configuration in internal memory that can't be erased.
+    pub fn erase_eeprom(&mut self) -> Result<()> {
+        block_on(self.erase_eeprom_async())
+    }
+
+    pub async fn erase_eeprom_async(&mut self) -> Result<()> {
+        match self.eeprom_chip_type()? {
+            chip_type @ (ChipType::R | ChipType::FT230X) => {
+                Err(FtdiError::UnsupportedChipType(chip_type))
+            }
+            _ => self.context.erase_eeprom().await,
+        }
+    }
+
+    /// Reads the whole configuration EEPROM.
+    ///
+    /// External EEPROMs are probed for their size by checking whether addresses wrap around
+    /// after 128 bytes. A blank EEPROM is assumed to have 256 bytes.
+    pub fn read_eeprom(&mut self) -> Result<Eeprom> {
+        block_on(self.read_eeprom_async())
+    }
+
+    pub async fn read_eeprom_async(&mut self) -> Result<Eeprom> {
+        let chip_type = self.eeprom_chip_type()?;
+        let size = match chip_type {
+            ChipType::R => 128,
+            _ => 256,
+        };
+
+        let mut data = Vec::with_capacity(size);
+        for addr in 0..size as u16 / 2 {
+            let word = self.context.read_eeprom_word(addr).await?;
+            data.extend(word.to_le_bytes());
+        }
+
+        let (low, high) = data.split_at(128);
+        let blank = low.iter().all(|&b| b == 0xff);
+        if chip_type != ChipType::FT230X && size == 256 && low == high && !blank {
+            data.truncate(128);
+        }
+
+        Eeprom::new(chip_type, data)
+    }
+
+    /// Programs the whole configuration EEPROM and reads it back to verify it.
+    ///
+    /// Refuses to write an image with an invalid checksum, which the chip would ignore.
+    pub fn write_eeprom(&mut self, eeprom: &Eeprom) -> Result<()> {
+        block_on(self.write_eeprom_async(eeprom))
+    }
+
+    pub async fn write_eeprom_async(&mut self, eeprom: &Eeprom) -> Result<()> {
+        if eeprom.chip_type() != self.eeprom_chip_type()? {
+            Err(FtdiError::Other(format!(
+                "EEPROM image is for {:?}",
+                eeprom.chip_type()
+            )))?
+        }
+        if !eeprom.is_checksum_valid() {
+            Err(FtdiError::Other("EEPROM checksum is invalid".to_string()))?
+        }
+
+        // The sequence FTDI's own tools use to unlock the EEPROM for writing.
+        let latency_timer = self.context.latency_timer;
+        self.context.usb_reset().await?;
+        self.context.poll_modem_status().await?;
+        self.context.set_latency_timer(0x77).await?;
+
+        let result = self.write_eeprom_words(eeprom).await;
+
+        // Put back the latency timer the unlock clobbered, 16 ms after power-on.
+        self.context
+            .set_latency_timer(latency_timer.unwrap_or(16))
+            .await?;
+        self.context.latency_timer = latency_timer;
+
+        result
+    }
+
+    /// Writes every word of `eeprom` and reads them back to verify them.
+    async fn write_eeprom_words(&mut self, eeprom: &Eeprom) -> Result<()> {
+        let words = eeprom.as_bytes().len() / 2;
+        for addr in 0..words {
+            self.context
+                .write_eeprom_word(addr as u16, eeprom.word(addr))
+                .await?;
+        }
+
+        for addr in 0..words {
+            let word = self.context.read_eeprom_word(addr as u16).await?;
+            if word != eeprom.word(addr) {
+                Err(FtdiError::Other(format!(
+                    "EEPROM verification failed at word {addr:#x}: read {word:04x}, expected {:04x}",
+                    eeprom.word(addr)
+                )))?
+            }
+        }
+
+        Ok(())
+    }
+
+    fn eeprom_chip_type(&self) -> Result<ChipType> {
+        match self.chip_type {
+            Some(
+                chip_type @ (ChipType::R
+                | ChipType::FT2232H
+                | ChipType::FT4232H
+                | ChipType::FT232H
+                | ChipType::FT230X),
+            ) => Ok(chip_type),
+            Some(chip_type) => Err(FtdiError::UnsupportedChipType(chip_type)),
+            None => Err(FtdiError::Other("unknown chip type".to_string())),
+        }
+    }
+
     pub fn vendor_id(&self) -> u16 {
         self.vendor_id
     }
@@ -474,40 +1434,204 @@ impl Device {
         self.product_string.as_deref()
     }
 
+    /// Returns the modem status reported with the most recently received packet.
+    pub fn modem_status(&self) -> ModemStatus {
+        self.context.status.0
+    }
+
+    /// Returns the line status reported with the most recently received packet.
+    pub fn line_status(&self) -> LineStatus {
+        self.context.status.1
+    }
+
     pub fn set_pins(&mut self, level: u16, direction: u16) -> Result<()> {
-        self.write_all(&[0x80, level as u8, direction as u8])?;
-        self.write_all(&[0x82, (level >> 8) as u8, (direction >> 8) as u8])?;
+        block_on(self.set_pins_async(level, direction))
+    }
 
-        Ok(())
+    pub async fn set_pins_async(&mut self, level: u16, direction: u16) -> Result<()> {
+        let [low, high] = level.to_le_bytes();
+        let [low_direction, high_direction] = direction.to_le_bytes();
+        let buffer = MpsseBuffer::new()
+            .with(&MpsseCommand::SetLowBits {
+                value: low,
+                direction: low_direction,
+            })?
+            .with(&MpsseCommand::SetHighBits {
+                value: high,
+                direction: high_direction,
+            })?;
+
+        Ok(self.write_all_async(buffer.as_bytes()).await?)
     }
 
     pub fn disable_loopback(&mut self) -> Result<()> {
-        Ok(self.write_all(&[0x85])?)
+        block_on(self.disable_loopback_async())
+    }
+
+    pub async fn disable_loopback_async(&mut self) -> Result<()> {
+        self.write_command_async(&MpsseCommand::Loopback(false))
+            .await
     }
 
     pub fn disable_divide_by_5(&mut self) -> Result<()> {
-        Ok(self.write_all(&[0x8A])?)
+        block_on(self.disable_divide_by_5_async())
+    }
+
+    pub async fn disable_divide_by_5_async(&mut self) -> Result<()> {
+        self.write_command_async(&MpsseCommand::DivideBy5(false))
+            .await
     }
 
     pub fn enable_divide_by_5(&mut self) -> Result<()> {
-        Ok(self.write_all(&[0x8B])?)
+        block_on(self.enable_divide_by_5_async())
+    }
+
+    pub async fn enable_divide_by_5_async(&mut self) -> Result<()> {
+        self.write_command_async(&MpsseCommand::DivideBy5(true))
+            .await
     }
 
     pub fn configure_clock_divider(&mut self, divisor: u16) -> Result<()> {
-        let [l, h] = divisor.to_le_bytes();
-        Ok(self.write_all(&[0x86, l, h])?)
+        block_on(self.configure_clock_divider_async(divisor))
+    }
+
+    pub async fn configure_clock_divider_async(&mut self, divisor: u16) -> Result<()> {
+        self.write_command_async(&MpsseCommand::SetClockDivisor(divisor))
+            .await
+    }
+
+    /// Synchronises with the MPSSE as described in AN135, dropping any stale data.
+    ///
+    /// Fails if it doesn't echo the bogus opcodes it is sent within the read timeout.
+    pub fn sync_mpsse(&mut self) -> Result<()> {
+        block_on(self.sync_mpsse_async())
+    }
+
+    pub async fn sync_mpsse_async(&mut self) -> Result<()> {
+        Ok(self.context.sync_mpsse().await?)
+    }
+
+    async fn write_command_async(&mut self, command: &MpsseCommand) -> Result<()> {
+        let mut buf = vec![];
+        command.encode(&mut buf)?;
+        Ok(self.write_all_async(&buf).await?)
+    }
+
+    /// Sends `commands` in as few round trips as the MPSSE buffer of the chip allows, and
+    /// returns the data they read back.
+    ///
+    /// Nothing is sent if a command is out of range.
+    pub(crate) async fn run_commands_async(
+        &mut self,
+        commands: &[MpsseCommand],
+    ) -> Result<Vec<u8>> {
+        let encoded = commands
+            .iter()
+            .map(|command| {
+                let mut encoded = vec![];
+                command.encode(&mut encoded).map(|()| encoded)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let buffer_size = self.mpsse_buffer_size();
+        let mut response = vec![];
+        let mut batch = MpsseBuffer::new();
+        for (command, encoded) in commands.iter().zip(encoded) {
+            // One byte is reserved for the send immediate command.
+            if batch.as_bytes().len() + encoded.len() + 1 > buffer_size
+                || batch.response_len() + command.response_len() > buffer_size
+            {
+                self.flush_commands_async(&mut batch, &mut response).await?;
+            }
+            batch.push(command)?;
+        }
+        self.flush_commands_async(&mut batch, &mut response).await?;
+
+        Ok(response)
+    }
+
+    async fn flush_commands_async(
+        &mut self,
+        batch: &mut MpsseBuffer,
+        response: &mut Vec<u8>,
+    ) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let read = batch.response_len();
+        if read > 0 {
+            batch.push(&MpsseCommand::SendImmediate)?;
+        }
+        self.write_all_async(batch.as_bytes()).await?;
+        batch.clear();
+
+        let start = response.len();
+        response.resize(start + read, 0);
+        self.read_exact_async(&mut response[start..]).await?;
+
+        Ok(())
+    }
+
+    /// Async equivalent of [`Read::read`].
+    pub async fn read_async(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.context.read_data(buf).await
+    }
+
+    /// Async equivalent of [`Read::read_exact`].
+    pub async fn read_exact_async(&mut self, mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_async(buf).await? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                read => buf = &mut buf[read..],
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Async equivalent of [`Read::read_to_end`]: reads until the chip has no more data queued.
+    pub async fn read_to_end_async(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let mut chunk = [0; 512];
+        let mut total = 0;
+        loop {
+            match self.read_async(&mut chunk).await? {
+                0 => return Ok(total),
+                read => {
+                    buf.extend_from_slice(&chunk[..read]);
+                    total += read;
+                }
+            }
+        }
+    }
+
+    /// Async equivalent of [`Write::write`].
+    pub async fn write_async(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.context.write_data(buf).await
+    }
+
+    /// Async equivalent of [`Write::write_all`].
+    pub async fn write_all_async(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write_async(buf).await? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                written => buf = &buf[written..],
+            }
+        }
+
+        Ok(())
     }
 }
 
 impl Read for Device {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.context.read_data(buf)
+        block_on(self.read_async(buf))
     }
 }
 
 impl Write for Device {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.context.write_data(buf)
+        block_on(self.write_async(buf))
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -516,3 +1640,167 @@ impl Write for Device {
 }
 
 pub type Result<T, E = FtdiError> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpsse::{BitOrder, ClockEdge};
+    use transport::{descriptor, MemoryTransport};
+
+    #[test]
+    fn test_device_reconnect() {
+        let transport = MemoryTransport::default();
+        let mut device = Builder::new()
+            .with_reconnect(Duration::from_secs(1))
+            .transport_open(transport.clone())
+            .unwrap();
+        device.set_bitmode(0x0b, BitMode::Mpsse).unwrap();
+        device.set_latency_timer(1).unwrap();
+        transport.take_control_requests();
+
+        transport.unplug();
+        let err = device.write_all(&[0x87]).unwrap_err();
+        assert!(matches!(FtdiError::from(err), FtdiError::Reconnected));
+        assert_eq!(transport.reconnects(), 1);
+        // Reset, latency timer and bitmode are restored.
+        assert_eq!(
+            transport
+                .take_control_requests()
+                .iter()
+                .map(|r| (r.request, r.value))
+                .collect::<Vec<_>>(),
+            [(0x00, 0), (0x09, 1), (0x0B, 0x020b)]
+        );
+
+        device.write_all(&[0x87]).unwrap();
+        assert_eq!(transport.take_written(), [0x87]);
+
+        // Without opting in, the disconnect is passed through.
+        let mut device = transport.open();
+        transport.unplug();
+        assert!(matches!(
+            device.set_latency_timer(1),
+            Err(FtdiError::Usb(e)) if e.kind() == std::io::ErrorKind::NotConnected
+        ));
+        assert_eq!(transport.reconnects(), 1);
+    }
+
+    #[test]
+    fn test_device_recovery() {
+        let transport = MemoryTransport::default();
+        let mut device = Builder::new()
+            .with_recovery(2)
+            .transport_open(transport.clone())
+            .unwrap();
+        device.set_bitmode(0x0b, BitMode::Mpsse).unwrap();
+        transport.take_control_requests();
+
+        // Control requests are retried.
+        transport.stall(2);
+        device.set_latency_timer(1).unwrap();
+        assert_eq!(transport.take_control_requests().len(), 1);
+        transport.stall(3);
+        assert!(device.set_latency_timer(1).is_err());
+        transport.stall(0);
+
+        // A stalled bulk transfer clears the halt, purges and resynchronises the MPSSE.
+        transport.push_response(&[0x12]);
+        transport.stall(1);
+        let err = device.write_all(&[0x87]).unwrap_err();
+        assert!(matches!(
+            FtdiError::from(err),
+            FtdiError::Recovered { state_lost: true }
+        ));
+        assert_eq!(transport.take_cleared_halts(), [0x02]);
+        assert_eq!(
+            transport
+                .take_control_requests()
+                .iter()
+                .map(|r| (r.request, r.value))
+                .collect::<Vec<_>>(),
+            [(0x00, 2), (0x00, 1)]
+        );
+        assert_eq!(transport.take_written(), [0xaa, 0xab]);
+
+        device.write_all(&[0x87]).unwrap();
+        assert_eq!(transport.take_written(), [0x87]);
+
+        // Outside MPSSE mode nothing is lost.
+        device.set_bitmode(0, BitMode::Reset).unwrap();
+        transport.stall(1);
+        let err = device.write_all(&[0x87]).unwrap_err();
+        assert!(matches!(
+            FtdiError::from(err),
+            FtdiError::Recovered { state_lost: false }
+        ));
+        assert_eq!(transport.take_cleared_halts(), [0x02]);
+        assert!(transport.take_written().is_empty());
+
+        // Without opting in, the stall is passed through.
+        let mut device = transport.open();
+        transport.stall(1);
+        assert!(matches!(
+            device.set_latency_timer(1),
+            Err(FtdiError::Usb(_))
+        ));
+        assert!(transport.take_cleared_halts().is_empty());
+    }
+
+    #[test]
+    fn test_device_run_commands() {
+        // An FT232H, with 1 KiB MPSSE buffers.
+        let transport = MemoryTransport::new(descriptor(0x6014, 0x900));
+        let mut device = transport.open();
+
+        let read = MpsseCommand::ReadBytes {
+            edge: ClockEdge::Rising,
+            order: BitOrder::MsbFirst,
+            len: 700,
+        };
+        let write = MpsseCommand::WriteBytes {
+            edge: ClockEdge::Falling,
+            order: BitOrder::MsbFirst,
+            data: vec![0x55; 600],
+        };
+        let reply: Vec<u8> = (0..1400u32).map(|i| i as u8).collect();
+        transport.push_response(&reply);
+        let response = async_io::block_on(device.run_commands_async(&[
+            read.clone(),
+            write.clone(),
+            read.clone(),
+        ]))
+        .unwrap();
+        assert_eq!(response, reply);
+
+        // The commands would fit, but the replies of both reads wouldn't.
+        let batches = [
+            MpsseBuffer::new()
+                .with(&read)
+                .unwrap()
+                .with(&write)
+                .unwrap()
+                .with(&MpsseCommand::SendImmediate)
+                .unwrap()
+                .into_bytes(),
+            MpsseBuffer::new()
+                .with(&read)
+                .unwrap()
+                .with(&MpsseCommand::SendImmediate)
+                .unwrap()
+                .into_bytes(),
+        ];
+        assert_eq!(transport.take_written(), batches.concat());
+
+        // An out of range command fails before anything is sent.
+        let long = MpsseCommand::WriteBytes {
+            edge: ClockEdge::Falling,
+            order: BitOrder::MsbFirst,
+            data: vec![0; 65537],
+        };
+        assert!(matches!(
+            async_io::block_on(device.run_commands_async(&[read, long])),
+            Err(FtdiError::TransferLength(65537))
+        ));
+        assert!(transport.take_written().is_empty());
+    }
+}
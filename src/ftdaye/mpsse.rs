@@ -1,5 +1,8 @@
 #![allow(non_upper_case_globals)]
 
+use crate::ftdaye::disasm::{self, DecodeError};
+use crate::ftdaye::{error::FtdiError, mcu::McuAddress, ChipType};
+
 // 3.2 Data Shifting Command Overview
 pub const fn cmd_shift(
     neg_ve_clk_write: bool,
@@ -10,7 +13,7 @@ pub const fn cmd_shift(
     read_tdo: bool,
     write_tms: bool,
 ) -> u8 {
-    (neg_ve_clk_write as u8) << 0
+    (neg_ve_clk_write as u8)
         | (bit_mode as u8) << 1
         | (neg_ve_clk_read as u8) << 2
         | (lsb_first as u8) << 3
@@ -76,13 +79,26 @@ pub const Clock_Data_Bits_Out_on_neg_ve_LSB_first : u8 = cmd_shift(
 
 pub const CmdImm: u8 = 0x87;
 pub const CmdBadCommand: u8 = 0xAB;
+pub const BadCommandResponse: u8 = 0xFA;
+
+// 4 MCU Host Bus Emulation Commands
+pub const CmdMcuReadShort: u8 = 0x90;
+pub const CmdMcuReadExtended: u8 = 0x91;
+pub const CmdMcuWriteShort: u8 = 0x92;
+pub const CmdMcuWriteExtended: u8 = 0x93;
+
+/// The most bytes a single MPSSE data command can clock.
+pub const MAX_TRANSFER_LEN: usize = 65536;
 
-pub fn cmd_read_write_imm(data: &[u8]) -> Vec<u8> {
-    assert!(
-        data.len() > 0 && data.len() <= 65536,
-        "data length is {} must be in range 1..=65536 ",
-        data.len()
-    );
+fn check_transfer_len(len: usize) -> Result<(), FtdiError> {
+    match len {
+        1..=MAX_TRANSFER_LEN => Ok(()),
+        len => Err(FtdiError::TransferLength(len)),
+    }
+}
+
+pub fn cmd_read_write_imm(data: &[u8]) -> Result<Vec<u8>, FtdiError> {
+    check_transfer_len(data.len())?;
     let len = data.len() - 1;
     let mut v = vec![
         Clock_Data_Bytes_In_on_pos_ve_and_Out_on_neg_ve_LSB_first,
@@ -91,16 +107,11 @@ pub fn cmd_read_write_imm(data: &[u8]) -> Vec<u8> {
     ];
     v.extend_from_slice(data);
     v.push(CmdImm);
-    // println!("{:x?}", v);
-    v
+    Ok(v)
 }
 
-pub fn cmd_write_imm(data: &[u8]) -> Vec<u8> {
-    assert!(
-        data.len() > 0 && data.len() <= 65536,
-        "data length is {} must be in range 1..=65536 ",
-        data.len()
-    );
+pub fn cmd_write_imm(data: &[u8]) -> Result<Vec<u8>, FtdiError> {
+    check_transfer_len(data.len())?;
     let len = data.len() - 1;
     let mut v = vec![
         Clock_Data_Bytes_Out_on_neg_ve_LSB_first,
@@ -109,23 +120,451 @@ pub fn cmd_write_imm(data: &[u8]) -> Vec<u8> {
     ];
     v.extend_from_slice(data);
     v.push(CmdImm);
-    // println!("{:x?}", v);
-    v
+    Ok(v)
 }
 
-pub fn cmd_read_imm(len: usize) -> Vec<u8> {
-    assert!(
-        len > 0 && len <= 65536,
-        "data length is {} must be in range 1..=65536 ",
-        len
-    );
+pub fn cmd_read_imm(len: usize) -> Result<Vec<u8>, FtdiError> {
+    check_transfer_len(len)?;
     let len = len - 1;
-    vec![
+    Ok(vec![
         Clock_Data_Bytes_In_on_pos_ve_LSB_first,
         len as u8,
         (len >> 8) as u8,
         CmdImm,
-    ]
+    ])
+}
+
+/// The clock edge data is written or sampled on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockEdge {
+    Rising,
+    Falling,
+}
+
+impl ClockEdge {
+    const fn is_falling(self) -> bool {
+        matches!(self, Self::Falling)
+    }
+}
+
+/// The order bits of a byte are shifted in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+impl BitOrder {
+    const fn is_lsb_first(self) -> bool {
+        matches!(self, Self::LsbFirst)
+    }
+}
+
+/// A command of the MPSSE, see AN108.
+///
+/// Byte commands shift 1 to 65536 bytes, bit commands 1 to 8 bits and TMS commands 1 to 7 bits;
+/// encoding a command outside these ranges fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MpsseCommand {
+    /// 3.3 Clock data bytes out on TDI/DO.
+    WriteBytes {
+        edge: ClockEdge,
+        order: BitOrder,
+        data: Vec<u8>,
+    },
+    /// 3.3 Clock data bits out on TDI/DO, from the start of `data` in `order`.
+    WriteBits {
+        edge: ClockEdge,
+        order: BitOrder,
+        count: u8,
+        data: u8,
+    },
+    /// 3.3 Clock data bytes in from TDO/DI.
+    ReadBytes {
+        edge: ClockEdge,
+        order: BitOrder,
+        len: usize,
+    },
+    /// 3.3 Clock data bits in from TDO/DI, responding with one byte.
+    ReadBits {
+        edge: ClockEdge,
+        order: BitOrder,
+        count: u8,
+    },
+    /// 3.3 Clock data bytes out and in at the same time.
+    TransferBytes {
+        write_edge: ClockEdge,
+        read_edge: ClockEdge,
+        order: BitOrder,
+        data: Vec<u8>,
+    },
+    /// 3.3 Clock data bits out and in at the same time, responding with one byte.
+    TransferBits {
+        write_edge: ClockEdge,
+        read_edge: ClockEdge,
+        order: BitOrder,
+        count: u8,
+        data: u8,
+    },
+    /// 3.5 Clock data bits out on TMS, LSB first, while holding TDI/DO at `tdi`. If `read_edge`
+    /// is set, TDO/DI is sampled as well, responding with one byte.
+    WriteTms {
+        edge: ClockEdge,
+        read_edge: Option<ClockEdge>,
+        count: u8,
+        tdi: bool,
+        data: u8,
+    },
+    /// 3.6.1 Set the level and direction (1 for output) of the low byte pins.
+    SetLowBits { value: u8, direction: u8 },
+    /// 3.6.2 Set the level and direction (1 for output) of the high byte pins.
+    SetHighBits { value: u8, direction: u8 },
+    /// 3.6.3 Read the low byte pins.
+    ReadLowBits,
+    /// 3.6.4 Read the high byte pins.
+    ReadHighBits,
+    /// 3.7 Connect TDI/DO to TDO/DI internally.
+    Loopback(bool),
+    /// 3.8 Set the clock divisor, TCK = base clock / ((1 + divisor) * 2).
+    SetClockDivisor(u16),
+    /// 5.1 Flush the response buffer to the host right away.
+    SendImmediate,
+    /// 5.2 Wait until GPIOL1 (JTAG) or I/O1 (CPU) is high.
+    WaitOnIoHigh,
+    /// 5.3 Wait until GPIOL1 (JTAG) or I/O1 (CPU) is low.
+    WaitOnIoLow,
+    /// 4 Read a byte from the emulated MCU bus.
+    McuRead(McuAddress),
+    /// 4 Write a byte to the emulated MCU bus.
+    McuWrite(McuAddress, u8),
+    /// 6.1 / 6.2 Divide the 60 MHz base clock by 5, as the FT2232C/D does.
+    DivideBy5(bool),
+    /// 6.3 / 6.4 Clock data on three phases, for I2C.
+    ThreePhaseClocking(bool),
+    /// 6.5 Clock 1 to 8 bits without transferring data.
+    ClockBits(u8),
+    /// 6.6 Clock 1 to 65536 bytes without transferring data.
+    ClockBytes(usize),
+    /// 6.7 Clock until GPIOL1 is high.
+    ClockUntilIoHigh,
+    /// 6.8 Clock until GPIOL1 is low.
+    ClockUntilIoLow,
+    /// 6.9 / 6.10 Wait for RTCK on GPIOL3 after each clock edge.
+    AdaptiveClocking(bool),
+    /// 6.11 Clock up to 65536 bytes, stopping early once GPIOL1 is high.
+    ClockBytesUntilIoHigh(usize),
+    /// 6.12 Clock up to 65536 bytes, stopping early once GPIOL1 is low.
+    ClockBytesUntilIoLow(usize),
+    /// 7.1 Only drive the pins set in the masks low, tristating them instead of driving them
+    /// high, for open drain outputs.
+    DriveZeroOnly { low: u8, high: u8 },
+}
+
+impl MpsseCommand {
+    /// Returns the opcode the command starts with.
+    pub const fn opcode(&self) -> u8 {
+        match *self {
+            Self::WriteBytes { edge, order, .. } => cmd_shift(
+                edge.is_falling(),
+                false,
+                false,
+                order.is_lsb_first(),
+                true,
+                false,
+                false,
+            ),
+            Self::WriteBits { edge, order, .. } => cmd_shift(
+                edge.is_falling(),
+                true,
+                false,
+                order.is_lsb_first(),
+                true,
+                false,
+                false,
+            ),
+            Self::ReadBytes { edge, order, .. } => cmd_shift(
+                false,
+                false,
+                edge.is_falling(),
+                order.is_lsb_first(),
+                false,
+                true,
+                false,
+            ),
+            Self::ReadBits { edge, order, .. } => cmd_shift(
+                false,
+                true,
+                edge.is_falling(),
+                order.is_lsb_first(),
+                false,
+                true,
+                false,
+            ),
+            Self::TransferBytes {
+                write_edge,
+                read_edge,
+                order,
+                ..
+            } => cmd_shift(
+                write_edge.is_falling(),
+                false,
+                read_edge.is_falling(),
+                order.is_lsb_first(),
+                true,
+                true,
+                false,
+            ),
+            Self::TransferBits {
+                write_edge,
+                read_edge,
+                order,
+                ..
+            } => cmd_shift(
+                write_edge.is_falling(),
+                true,
+                read_edge.is_falling(),
+                order.is_lsb_first(),
+                true,
+                true,
+                false,
+            ),
+            Self::WriteTms {
+                edge, read_edge, ..
+            } => cmd_shift(
+                edge.is_falling(),
+                true,
+                matches!(read_edge, Some(ClockEdge::Falling)),
+                true,
+                false,
+                read_edge.is_some(),
+                true,
+            ),
+            Self::SetLowBits { .. } => 0x80,
+            Self::ReadLowBits => 0x81,
+            Self::SetHighBits { .. } => 0x82,
+            Self::ReadHighBits => 0x83,
+            Self::Loopback(true) => 0x84,
+            Self::Loopback(false) => 0x85,
+            Self::SetClockDivisor(_) => 0x86,
+            Self::SendImmediate => CmdImm,
+            Self::WaitOnIoHigh => 0x88,
+            Self::WaitOnIoLow => 0x89,
+            Self::DivideBy5(false) => 0x8A,
+            Self::DivideBy5(true) => 0x8B,
+            Self::ThreePhaseClocking(true) => 0x8C,
+            Self::ThreePhaseClocking(false) => 0x8D,
+            Self::ClockBits(_) => 0x8E,
+            Self::ClockBytes(_) => 0x8F,
+            Self::McuRead(McuAddress::Short(_)) => CmdMcuReadShort,
+            Self::McuRead(McuAddress::Extended(_)) => CmdMcuReadExtended,
+            Self::McuWrite(McuAddress::Short(_), _) => CmdMcuWriteShort,
+            Self::McuWrite(McuAddress::Extended(_), _) => CmdMcuWriteExtended,
+            Self::ClockUntilIoHigh => 0x94,
+            Self::ClockUntilIoLow => 0x95,
+            Self::AdaptiveClocking(true) => 0x96,
+            Self::AdaptiveClocking(false) => 0x97,
+            Self::ClockBytesUntilIoHigh(_) => 0x9C,
+            Self::ClockBytesUntilIoLow(_) => 0x9D,
+            Self::DriveZeroOnly { .. } => 0x9E,
+        }
+    }
+
+    /// Returns how many bytes the MPSSE sends back for this command.
+    pub fn response_len(&self) -> usize {
+        match self {
+            Self::ReadBytes { len, .. } => *len,
+            Self::TransferBytes { data, .. } => data.len(),
+            Self::ReadBits { .. }
+            | Self::TransferBits { .. }
+            | Self::WriteTms {
+                read_edge: Some(_), ..
+            }
+            | Self::ReadLowBits
+            | Self::ReadHighBits
+            | Self::McuRead(_) => 1,
+            _ => 0,
+        }
+    }
+
+    /// Returns whether the MPSSE of `chip` implements this command.
+    ///
+    /// The FT2232C/D only has the commands of AN108 sections 3 to 5. Of the Hi-Speed chips, the
+    /// quad channel ones lack the high byte pins and with them the MCU host bus and adaptive
+    /// clocking, and only the FT232H family can drive pins open drain.
+    pub fn is_supported_by(&self, chip: ChipType) -> bool {
+        let quad = matches!(
+            chip,
+            ChipType::FT4232H | ChipType::FT4232HA | ChipType::FT4232HP | ChipType::FT4233HP
+        );
+        if chip.mpsse_interfaces().is_empty() {
+            return false;
+        }
+
+        match self {
+            Self::SetHighBits { .. }
+            | Self::ReadHighBits
+            | Self::McuRead(_)
+            | Self::McuWrite(..) => !quad,
+            Self::DivideBy5(_)
+            | Self::ThreePhaseClocking(_)
+            | Self::ClockBits(_)
+            | Self::ClockBytes(_)
+            | Self::ClockUntilIoHigh
+            | Self::ClockUntilIoLow
+            | Self::ClockBytesUntilIoHigh(_)
+            | Self::ClockBytesUntilIoLow(_) => chip.is_high_speed(),
+            Self::AdaptiveClocking(_) => chip.is_high_speed() && !quad,
+            Self::DriveZeroOnly { .. } => {
+                matches!(
+                    chip,
+                    ChipType::FT232H | ChipType::FT232HP | ChipType::FT233HP
+                )
+            }
+            _ => true,
+        }
+    }
+
+    /// Appends the encoded command to `buf`.
+    ///
+    /// Fails with [`FtdiError::TransferLength`] or [`FtdiError::BitCount`] if the command shifts
+    /// more or less than it can, leaving `buf` untouched.
+    pub fn encode(&self, buf: &mut Vec<u8>) -> Result<(), FtdiError> {
+        let opcode = self.opcode();
+        match self {
+            Self::WriteBytes { data, .. } | Self::TransferBytes { data, .. } => {
+                let count = byte_count(data.len())?;
+                buf.push(opcode);
+                buf.extend(count);
+                buf.extend(data);
+            }
+            Self::ReadBytes { len, .. } => {
+                let count = byte_count(*len)?;
+                buf.push(opcode);
+                buf.extend(count);
+            }
+            Self::WriteBits { count, data, .. } | Self::TransferBits { count, data, .. } => {
+                buf.extend([opcode, bit_count(*count, 8)?, *data]);
+            }
+            Self::ReadBits { count, .. } => buf.extend([opcode, bit_count(*count, 8)?]),
+            Self::WriteTms {
+                count, tdi, data, ..
+            } => {
+                let data = (*tdi as u8) << 7 | (data & 0x7f);
+                buf.extend([opcode, bit_count(*count, 7)?, data]);
+            }
+            Self::SetLowBits { value, direction } | Self::SetHighBits { value, direction } => {
+                buf.extend([opcode, *value, *direction]);
+            }
+            Self::SetClockDivisor(divisor) => {
+                buf.push(opcode);
+                buf.extend(divisor.to_le_bytes());
+            }
+            Self::McuRead(address) => address.encode_read(buf),
+            Self::McuWrite(address, data) => address.encode_write(*data, buf),
+            Self::ClockBits(count) => buf.extend([opcode, bit_count(*count, 8)?]),
+            Self::ClockBytes(len)
+            | Self::ClockBytesUntilIoHigh(len)
+            | Self::ClockBytesUntilIoLow(len) => {
+                let count = byte_count(*len)?;
+                buf.push(opcode);
+                buf.extend(count);
+            }
+            Self::DriveZeroOnly { low, high } => buf.extend([opcode, *low, *high]),
+            Self::ReadLowBits
+            | Self::ReadHighBits
+            | Self::Loopback(_)
+            | Self::SendImmediate
+            | Self::WaitOnIoHigh
+            | Self::WaitOnIoLow
+            | Self::DivideBy5(_)
+            | Self::ThreePhaseClocking(_)
+            | Self::ClockUntilIoHigh
+            | Self::ClockUntilIoLow
+            | Self::AdaptiveClocking(_) => buf.push(opcode),
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds the echo of a bad command in `reply`, `0xFA` followed by the opcode, returning the
+/// opcode.
+///
+/// Only opcodes the MPSSE doesn't know count, as a `0xFA` in the data may be followed by
+/// anything.
+pub fn find_bad_command(reply: &[u8]) -> Option<u8> {
+    reply.windows(2).find_map(|w| match w {
+        [BadCommandResponse, opcode]
+            if disasm::decode(&[*opcode]) == Err(DecodeError::BadOpcode(*opcode)) =>
+        {
+            Some(*opcode)
+        }
+        _ => None,
+    })
+}
+
+/// Encodes a length of 1 to 65536 bytes as its little endian offset by one.
+fn byte_count(len: usize) -> Result<[u8; 2], FtdiError> {
+    check_transfer_len(len)?;
+    Ok(((len - 1) as u16).to_le_bytes())
+}
+
+/// Encodes a count of 1 to `max` bits offset by one.
+fn bit_count(count: u8, max: u8) -> Result<u8, FtdiError> {
+    match count {
+        1.. if count <= max => Ok(count - 1),
+        count => Err(FtdiError::BitCount { count, max }),
+    }
+}
+
+/// Builds a buffer of MPSSE commands to send in one go, keeping track of the response size.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MpsseBuffer {
+    bytes: Vec<u8>,
+    response_len: usize,
+}
+
+impl MpsseBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `command`, failing like [`MpsseCommand::encode`] if it is out of range.
+    pub fn push(&mut self, command: &MpsseCommand) -> Result<&mut Self, FtdiError> {
+        command.encode(&mut self.bytes)?;
+        self.response_len += command.response_len();
+        Ok(self)
+    }
+
+    /// Appends `command`, for building a buffer in one expression.
+    pub fn with(mut self, command: &MpsseCommand) -> Result<Self, FtdiError> {
+        self.push(command)?;
+        Ok(self)
+    }
+
+    /// Returns the encoded commands.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns how many bytes the MPSSE sends back for the commands.
+    pub fn response_len(&self) -> usize {
+        self.response_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Removes all commands.
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.response_len = 0;
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
 }
 
 #[cfg(test)]
@@ -134,24 +573,35 @@ mod test {
 
     #[test]
     fn test_cmd_write_imm_min() {
-        assert_eq!(&cmd_read_write_imm(&[0x12]), &[0x39, 0, 0, 0x12, 0x87]);
+        assert_eq!(
+            &cmd_read_write_imm(&[0x12]).unwrap(),
+            &[0x39, 0, 0, 0x12, 0x87]
+        );
     }
 
     #[test]
     fn test_cmd_write_imm_max() {
-        let cmd = cmd_read_write_imm(&[0u8; 65536]);
+        let cmd = cmd_read_write_imm(&[0u8; 65536]).unwrap();
         assert_eq!(cmd[1], 0xff);
         assert_eq!(cmd[2], 0xff);
     }
     #[test]
-    #[should_panic]
     fn test_cmd_write_imm_0() {
-        let _ = cmd_read_write_imm(&[]);
+        assert!(matches!(
+            cmd_read_write_imm(&[]),
+            Err(FtdiError::TransferLength(0))
+        ));
     }
     #[test]
-    #[should_panic]
     fn test_cmd_write_imm_too_large() {
-        let _ = cmd_read_write_imm(&[0u8; 65537]);
+        assert!(matches!(
+            cmd_write_imm(&[0u8; 65537]),
+            Err(FtdiError::TransferLength(65537))
+        ));
+        assert!(matches!(
+            cmd_read_imm(65537),
+            Err(FtdiError::TransferLength(65537))
+        ));
     }
 
     #[test]
@@ -165,4 +615,173 @@ mod test {
         assert_eq!(Clock_Data_to_TMS_on_neg_ve_LSB_first, 0x4b);
         assert_eq!(Clock_Data_Bits_Out_on_neg_ve_LSB_first, 0x1b);
     }
+
+    #[test]
+    fn test_mpsse_command() {
+        use ClockEdge::*;
+
+        // The opcodes of the AN108 command tables.
+        let cases = [
+            (
+                MpsseCommand::WriteBytes {
+                    edge: Falling,
+                    order: BitOrder::MsbFirst,
+                    data: vec![0xa5],
+                },
+                0x11,
+            ),
+            (
+                MpsseCommand::WriteBits {
+                    edge: Rising,
+                    order: BitOrder::MsbFirst,
+                    count: 3,
+                    data: 0,
+                },
+                0x12,
+            ),
+            (
+                MpsseCommand::ReadBytes {
+                    edge: Falling,
+                    order: BitOrder::MsbFirst,
+                    len: 2,
+                },
+                0x24,
+            ),
+            (
+                MpsseCommand::TransferBytes {
+                    write_edge: Rising,
+                    read_edge: Falling,
+                    order: BitOrder::MsbFirst,
+                    data: vec![1, 2],
+                },
+                0x34,
+            ),
+            (
+                MpsseCommand::TransferBits {
+                    write_edge: Falling,
+                    read_edge: Rising,
+                    order: BitOrder::LsbFirst,
+                    count: 8,
+                    data: 0,
+                },
+                0x3b,
+            ),
+            (
+                MpsseCommand::WriteTms {
+                    edge: Falling,
+                    read_edge: None,
+                    count: 1,
+                    tdi: false,
+                    data: 0,
+                },
+                0x4b,
+            ),
+            (
+                MpsseCommand::WriteTms {
+                    edge: Falling,
+                    read_edge: Some(Falling),
+                    count: 1,
+                    tdi: false,
+                    data: 0,
+                },
+                0x6f,
+            ),
+            (MpsseCommand::Loopback(false), 0x85),
+            (MpsseCommand::ThreePhaseClocking(true), 0x8c),
+            (MpsseCommand::AdaptiveClocking(false), 0x97),
+            (MpsseCommand::ClockBytesUntilIoLow(1), 0x9d),
+        ];
+        for (command, opcode) in &cases {
+            assert_eq!(command.opcode(), *opcode, "{command:?}");
+        }
+
+        let mut buffer = MpsseBuffer::new();
+        buffer
+            .push(&MpsseCommand::SetLowBits {
+                value: 0x08,
+                direction: 0x0b,
+            })
+            .unwrap()
+            .push(&MpsseCommand::ReadHighBits)
+            .unwrap()
+            .push(&MpsseCommand::SetClockDivisor(0x1234))
+            .unwrap()
+            .push(&cases[3].0)
+            .unwrap()
+            .push(&MpsseCommand::WriteTms {
+                edge: Falling,
+                read_edge: None,
+                count: 5,
+                tdi: true,
+                data: 0b1_1111,
+            })
+            .unwrap()
+            .push(&MpsseCommand::ClockBytes(256))
+            .unwrap()
+            .push(&MpsseCommand::McuRead(McuAddress::Extended(0x1234)))
+            .unwrap()
+            .push(&MpsseCommand::SendImmediate)
+            .unwrap();
+        assert_eq!(
+            buffer.as_bytes(),
+            [
+                0x80, 0x08, 0x0b, 0x83, 0x86, 0x34, 0x12, 0x34, 1, 0, 1, 2, 0x4b, 4, 0x9f, 0x8f,
+                0xff, 0, 0x91, 0x12, 0x34, 0x87
+            ]
+        );
+        assert_eq!(buffer.response_len(), 4);
+
+        assert!(MpsseCommand::ReadHighBits.is_supported_by(ChipType::FT2232C));
+        assert!(!MpsseCommand::ReadHighBits.is_supported_by(ChipType::FT4232H));
+        assert!(!MpsseCommand::ClockBits(1).is_supported_by(ChipType::FT2232C));
+        assert!(MpsseCommand::ClockBits(1).is_supported_by(ChipType::FT4232H));
+        assert!(!MpsseCommand::AdaptiveClocking(true).is_supported_by(ChipType::FT4232H));
+        assert!(MpsseCommand::DriveZeroOnly { low: 0, high: 0 }.is_supported_by(ChipType::FT232H));
+        assert!(!MpsseCommand::DriveZeroOnly { low: 0, high: 0 }.is_supported_by(ChipType::FT2232H));
+        assert!(!MpsseCommand::SendImmediate.is_supported_by(ChipType::R));
+    }
+
+    #[test]
+    fn test_find_bad_command() {
+        assert_eq!(find_bad_command(&[0x12, 0xfa, 0xab, 0x34]), Some(0xab));
+        // 0xFA followed by a valid opcode is data.
+        assert_eq!(find_bad_command(&[0xfa, 0x80, 0xfa]), None);
+        assert_eq!(find_bad_command(&[]), None);
+    }
+
+    #[test]
+    fn test_mpsse_command_out_of_range() {
+        let mut buf = vec![];
+        let tms = MpsseCommand::WriteTms {
+            edge: ClockEdge::Falling,
+            read_edge: None,
+            count: 8,
+            tdi: false,
+            data: 0,
+        };
+        assert!(matches!(
+            tms.encode(&mut buf),
+            Err(FtdiError::BitCount { count: 8, max: 7 })
+        ));
+        let bits = MpsseCommand::WriteBits {
+            edge: ClockEdge::Falling,
+            order: BitOrder::MsbFirst,
+            count: 0,
+            data: 0,
+        };
+        assert!(matches!(
+            bits.encode(&mut buf),
+            Err(FtdiError::BitCount { count: 0, max: 8 })
+        ));
+        let bytes = MpsseCommand::WriteBytes {
+            edge: ClockEdge::Falling,
+            order: BitOrder::MsbFirst,
+            data: vec![0; 65537],
+        };
+        assert!(matches!(
+            MpsseBuffer::new().with(&bytes),
+            Err(FtdiError::TransferLength(65537))
+        ));
+        assert!(buf.is_empty());
+    }
 }
@@ -0,0 +1,342 @@
+//! Recording of the USB traffic of a [`Device`](super::Device), to replay it without hardware
+//! using a [`ReplayTransport`](super::transport::ReplayTransport).
+//!
+//! A recording is a text file, so that it can be checked in and annotated. It starts with a
+//! header naming the device, followed by one timestamped event per line:
+//!
+//! ```text
+//! ftdaye-recording 1
+//! device 0403 6010 0700 512
+//! # Comments and blank lines are ignored.
+//! 0.000012 ctrl_out 09 0001 0001
+//! 0.000140 ctrl_in 05 0000 0001 3260
+//! 0.000301 out 8a9786
+//! 0.001207 in a5
+//! ```
+//!
+//! The device line holds the vendor and product ID, `bcdDevice` and the bulk packet size, all in
+//! hex but the last. Events hold the request, value and index of vendor requests, and the payload
+//! of bulk transfers in hex. Bulk IN payloads don't include the status bytes of each packet; a
+//! transfer that only carried status bytes is recorded as an empty `in` line. If a packet reported
+//! anything but an idle chip with CTS and DSR asserted, such as an overrun, the whole transfer is
+//! recorded as an `in_raw` line instead, status bytes included.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use super::status::{self, IDLE_STATUS};
+use super::transport::DeviceDescriptor;
+
+const MAGIC: &str = "ftdaye-recording 1";
+
+/// A USB operation of the driver, as recorded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A vendor request without data stage.
+    ControlOut { request: u8, value: u16, index: u16 },
+    /// A vendor request and the data the device responded with.
+    ControlIn {
+        request: u8,
+        value: u16,
+        index: u16,
+        data: Vec<u8>,
+    },
+    /// A bulk OUT transfer.
+    BulkOut(Vec<u8>),
+    /// The payload of a bulk IN transfer whose packets all had the idle status.
+    BulkIn(Vec<u8>),
+    /// A bulk IN transfer including the status bytes of each packet.
+    BulkInRaw(Vec<u8>),
+}
+
+impl Event {
+    /// Returns the event recording the bulk IN `transfer`, which only keeps the status bytes if
+    /// a packet's status is not [`IDLE_STATUS`].
+    pub(crate) fn bulk_in(transfer: &[u8], max_packet_size: usize) -> Self {
+        let mut packets = status::packets(transfer, max_packet_size);
+        if packets.all(|p| [p.modem.bits(), p.line.bits()] == IDLE_STATUS) {
+            Self::BulkIn(super::payload(transfer, max_packet_size))
+        } else {
+            Self::BulkInRaw(transfer.to_vec())
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ControlOut {
+                request,
+                value,
+                index,
+            } => write!(f, "ctrl_out {request:02x} {value:04x} {index:04x}"),
+            Self::ControlIn {
+                request,
+                value,
+                index,
+                data,
+            } => write!(
+                f,
+                "ctrl_in {request:02x} {value:04x} {index:04x} {}",
+                Hex(data)
+            ),
+            Self::BulkOut(data) => write!(f, "out {}", Hex(data)),
+            Self::BulkIn(data) => write!(f, "in {}", Hex(data)),
+            Self::BulkInRaw(data) => write!(f, "in_raw {}", Hex(data)),
+        }
+    }
+}
+
+impl std::str::FromStr for Event {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let kind = fields.next().ok_or("missing event")?;
+        let mut field = |what: &str| fields.next().ok_or(format!("missing {what}"));
+
+        let event = match kind {
+            "ctrl_out" => Self::ControlOut {
+                request: parse_int(field("request")?)?,
+                value: parse_int(field("value")?)?,
+                index: parse_int(field("index")?)?,
+            },
+            "ctrl_in" => Self::ControlIn {
+                request: parse_int(field("request")?)?,
+                value: parse_int(field("value")?)?,
+                index: parse_int(field("index")?)?,
+                data: parse_hex(field("data")?)?,
+            },
+            "out" => Self::BulkOut(parse_hex(fields.next().unwrap_or(""))?),
+            "in" => Self::BulkIn(parse_hex(fields.next().unwrap_or(""))?),
+            "in_raw" => Self::BulkInRaw(parse_hex(field("data")?)?),
+            kind => return Err(format!("unknown event {kind:?}")),
+        };
+        if let Some(extra) = fields.next() {
+            return Err(format!("unexpected {extra:?}"));
+        }
+
+        Ok(event)
+    }
+}
+
+/// A recorded session, see the [module documentation](self) for the format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recording {
+    pub descriptor: DeviceDescriptor,
+    pub max_packet_size: usize,
+    /// Events with the time they completed at, relative to the start of the recording.
+    pub events: Vec<(Duration, Event)>,
+}
+
+impl Recording {
+    /// Reads a recording from a file.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Parses a recording.
+    pub fn read(reader: impl BufRead) -> io::Result<Self> {
+        let mut lines = reader
+            .lines()
+            .enumerate()
+            .map(|(n, line)| (n + 1, line))
+            .filter(|(_, line)| {
+                line.as_ref()
+                    .map_or(true, |l| !l.trim().is_empty() && !l.starts_with('#'))
+            });
+        let invalid = |n: usize, e: String| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {n}: {e}"))
+        };
+
+        match lines.next() {
+            Some((_, Ok(line))) if line.trim() == MAGIC => {}
+            Some((_, Err(e))) => return Err(e),
+            _ => return Err(invalid(1, "not a recording".into())),
+        }
+
+        let (n, line) = lines
+            .next()
+            .ok_or_else(|| invalid(2, "missing device".into()))?;
+        let (descriptor, max_packet_size) = parse_device(&line?).map_err(|e| invalid(n, e))?;
+
+        let mut events = vec![];
+        for (n, line) in lines {
+            let line = line?;
+            let (time, event) = line
+                .trim()
+                .split_once(' ')
+                .ok_or_else(|| invalid(n, "missing event".into()))?;
+            let time = time
+                .parse::<f64>()
+                .ok()
+                .and_then(|t| Duration::try_from_secs_f64(t).ok())
+                .ok_or_else(|| invalid(n, format!("invalid time {time:?}")))?;
+            events.push((time, event.parse().map_err(|e| invalid(n, e))?));
+        }
+
+        Ok(Self {
+            descriptor,
+            max_packet_size,
+            events,
+        })
+    }
+}
+
+/// Writes the events of a device to a recording as they happen.
+pub(crate) struct Recorder {
+    writer: Box<dyn Write + Send>,
+    start: Instant,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("start", &self.start)
+            .finish()
+    }
+}
+
+impl Recorder {
+    /// Starts a recording of the device described by `descriptor`, writing the header.
+    ///
+    /// Every event is flushed to `writer` as it happens, so a recording of a session that
+    /// crashed is complete.
+    pub(crate) fn new(
+        writer: impl Write + Send + 'static,
+        descriptor: &DeviceDescriptor,
+        max_packet_size: usize,
+    ) -> io::Result<Self> {
+        let mut writer = LineWriter::new(writer);
+        writeln!(writer, "{MAGIC}")?;
+        writeln!(
+            writer,
+            "device {:04x} {:04x} {:04x} {}",
+            descriptor.vendor_id, descriptor.product_id, descriptor.device_version, max_packet_size
+        )?;
+
+        Ok(Self {
+            writer: Box::new(writer),
+            start: Instant::now(),
+        })
+    }
+
+    fn write(&mut self, event: &Event) -> io::Result<()> {
+        let time = self.start.elapsed().as_secs_f64();
+        writeln!(self.writer, "{time:.6} {event}")
+    }
+}
+
+/// Records the event built by `event`, if recording. A recording that fails to be written is
+/// stopped, rather than failing the operation.
+pub(crate) fn record(recorder: &mut Option<Recorder>, event: impl FnOnce() -> Event) {
+    if let Some(r) = recorder {
+        if let Err(e) = r.write(&event()) {
+            warn!("stopped recording: {e}");
+            *recorder = None;
+        }
+    }
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+fn parse_int<T: TryFrom<u32>>(s: &str) -> std::result::Result<T, String> {
+    u32::from_str_radix(s, 16)
+        .ok()
+        .and_then(|v| T::try_from(v).ok())
+        .ok_or(format!("invalid number {s:?}"))
+}
+
+fn parse_hex(s: &str) -> std::result::Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(format!("invalid data {s:?}"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| parse_int(&s[i..i + 2]))
+        .collect()
+}
+
+fn parse_device(line: &str) -> std::result::Result<(DeviceDescriptor, usize), String> {
+    let fields: Vec<_> = line.split_whitespace().collect();
+    let ["device", vendor_id, product_id, device_version, max_packet_size] = fields[..] else {
+        return Err(format!("invalid device {line:?}"));
+    };
+    let max_packet_size = max_packet_size
+        .parse()
+        .map_err(|_| format!("invalid packet size {max_packet_size:?}"))?;
+
+    Ok((
+        DeviceDescriptor {
+            vendor_id: parse_int(vendor_id)?,
+            product_id: parse_int(product_id)?,
+            device_version: parse_int(device_version)?,
+            serial_number: None,
+            product_string: None,
+        },
+        max_packet_size,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_recording_format() {
+        let events = [
+            Event::ControlOut {
+                request: 0x09,
+                value: 1,
+                index: 1,
+            },
+            Event::ControlIn {
+                request: 0x05,
+                value: 0,
+                index: 1,
+                data: vec![0x32, 0x60],
+            },
+            Event::BulkOut(vec![0x8a, 0x97, 0x86]),
+            Event::BulkIn(vec![]),
+            Event::BulkInRaw(vec![0x22, 0x60, 0x55]),
+        ];
+        for event in &events {
+            assert_eq!(event.to_string().parse::<Event>().as_ref(), Ok(event));
+        }
+        assert_eq!(events[1].to_string(), "ctrl_in 05 0000 0001 3260");
+        assert_eq!(events[3].to_string(), "in ");
+        assert_eq!(events[4].to_string(), "in_raw 226055");
+
+        let recording = Recording::read(
+            &b"ftdaye-recording 1\n\
+               device 0403 6010 0700 512\n\
+               # a comment\n\
+               \n\
+               0.000012 ctrl_out 09 0001 0001\n\
+               0.5 in\n"[..],
+        )
+        .unwrap();
+        assert_eq!(recording.descriptor.product_id, 0x6010);
+        assert_eq!(recording.max_packet_size, 512);
+        assert_eq!(recording.events[0].1, events[0]);
+        assert_eq!(
+            recording.events[1],
+            (Duration::from_millis(500), Event::BulkIn(vec![]))
+        );
+
+        let err = Recording::read(&b"ftdaye-recording 1\ndevice 0403 6010 0700 512\n0 out 8\n"[..])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "line 3: invalid data \"8\"");
+    }
+}
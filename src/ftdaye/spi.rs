@@ -0,0 +1,442 @@
+//! SPI master on an MPSSE channel, see AN108 and AN114.
+//!
+//! The bus uses the low byte pins: SCK on ADBUS0, MOSI on ADBUS1 and MISO on ADBUS2. Chip selects
+//! are active low GPIOs on any of the other pins.
+
+use async_io::block_on;
+use log::{debug, info};
+
+use crate::ftdaye::mpsse::{BitOrder, ClockEdge, MpsseCommand, MAX_TRANSFER_LEN};
+use crate::ftdaye::{error::FtdiError, BitMode, Device, Result};
+
+const SCK: u16 = 1 << 0;
+const MOSI: u16 = 1 << 1;
+
+/// The clock polarity (CPOL) and phase (CPHA) of the bus.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpiMode {
+    /// The clock idles low, data is sampled on the rising edge.
+    #[default]
+    Mode0,
+    /// The clock idles low, data is sampled on the falling edge.
+    Mode1,
+    /// The clock idles high, data is sampled on the falling edge.
+    Mode2,
+    /// The clock idles high, data is sampled on the rising edge.
+    Mode3,
+}
+
+impl SpiMode {
+    /// Returns whether the clock idles high.
+    pub fn cpol(self) -> bool {
+        matches!(self, Self::Mode2 | Self::Mode3)
+    }
+
+    /// Returns whether data is sampled on the second clock edge, rather than the first.
+    pub fn cpha(self) -> bool {
+        matches!(self, Self::Mode1 | Self::Mode3)
+    }
+
+    /// The edge MOSI changes on, one half clock before it is sampled.
+    fn write_edge(self) -> ClockEdge {
+        if self.cpol() == self.cpha() {
+            ClockEdge::Falling
+        } else {
+            ClockEdge::Rising
+        }
+    }
+
+    /// The edge MISO is sampled on.
+    fn read_edge(self) -> ClockEdge {
+        match self.write_edge() {
+            ClockEdge::Rising => ClockEdge::Falling,
+            ClockEdge::Falling => ClockEdge::Rising,
+        }
+    }
+}
+
+/// The settings of an [`Spi`] master.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpiConfig {
+    pub mode: SpiMode,
+    pub bit_order: BitOrder,
+    /// The requested SCK frequency, the actual one is the closest the chip can do below it.
+    pub clock_hz: u32,
+    /// The pins driving the chip selects, numbered like the bits of [`Device::set_pins`]: 3 to 7
+    /// on the low byte, 8 to 15 on the high byte.
+    pub chip_selects: Vec<u8>,
+}
+
+impl Default for SpiConfig {
+    /// Mode 0, MSB first at 1 MHz, with one chip select on ADBUS3.
+    fn default() -> Self {
+        Self {
+            mode: SpiMode::Mode0,
+            bit_order: BitOrder::MsbFirst,
+            clock_hz: 1_000_000,
+            chip_selects: vec![3],
+        }
+    }
+}
+
+/// A step of an SPI transaction.
+#[derive(Debug)]
+pub enum SpiOperation<'a> {
+    /// Clocks out the bytes, ignoring MISO.
+    Write(&'a [u8]),
+    /// Clocks in bytes, with MOSI held low.
+    Read(&'a mut [u8]),
+    /// Clocks out the bytes while replacing them with the ones clocked in.
+    Transfer(&'a mut [u8]),
+}
+
+/// An SPI master on a channel with an MPSSE.
+#[derive(Debug)]
+pub struct Spi {
+    device: Device,
+    mode: SpiMode,
+    bit_order: BitOrder,
+    clock_hz: u32,
+    chip_selects: Vec<u8>,
+    buffer_size: usize,
+}
+
+impl Spi {
+    /// Switches `device` to MPSSE mode and sets up the bus with `config`, leaving all chip
+    /// selects deasserted.
+    pub fn new(device: Device, config: SpiConfig) -> Result<Self> {
+        block_on(Self::new_async(device, config))
+    }
+
+    pub async fn new_async(mut device: Device, config: SpiConfig) -> Result<Self> {
+        let interface = device.interface();
+        match device.chip_type() {
+            Some(chip_type) if chip_type.mpsse_interfaces().contains(&interface) => {}
+            Some(chip_type) => Err(FtdiError::NoMpsse(chip_type, interface))?,
+            None => Err(FtdiError::Other("unknown chip type".to_string()))?,
+        }
+        let buffer_size = device.mpsse_buffer_size();
+        if let Some(pin) = config
+            .chip_selects
+            .iter()
+            .find(|&&pin| !(3..16).contains(&pin))
+        {
+            Err(FtdiError::Other(format!(
+                "pin {pin} can't be a chip select, only 3 to 15 are free"
+            )))?
+        }
+
+        device.set_bitmode_async(0, BitMode::Reset).await?;
+        device.set_bitmode_async(0, BitMode::Mpsse).await?;
+        device.set_latency_timer_async(1).await?;
+        device.usb_purge_buffers_async().await?;
+        device.sync_mpsse_async().await?;
+        device.disable_loopback_async().await?;
+
+        let mut spi = Self {
+            device,
+            mode: config.mode,
+            bit_order: config.bit_order,
+            clock_hz: 0,
+            chip_selects: config.chip_selects,
+            buffer_size,
+        };
+        spi.set_clock_async(config.clock_hz).await?;
+        let idle = [spi.pins_command(false, None), spi.pins_command(true, None)];
+        spi.device.run_commands_async(&idle).await?;
+
+        Ok(spi)
+    }
+
+    pub fn mode(&self) -> SpiMode {
+        self.mode
+    }
+
+    /// Changes the mode, moving SCK to its new idle level.
+    pub fn set_mode(&mut self, mode: SpiMode) -> Result<()> {
+        block_on(self.set_mode_async(mode))
+    }
+
+    pub async fn set_mode_async(&mut self, mode: SpiMode) -> Result<()> {
+        self.mode = mode;
+        let command = self.pins_command(false, None);
+        self.device.run_commands_async(&[command]).await?;
+
+        Ok(())
+    }
+
+    pub fn bit_order(&self) -> BitOrder {
+        self.bit_order
+    }
+
+    pub fn set_bit_order(&mut self, bit_order: BitOrder) {
+        self.bit_order = bit_order;
+    }
+
+    /// Returns the actual SCK frequency.
+    pub fn clock_hz(&self) -> u32 {
+        self.clock_hz
+    }
+
+    /// Sets SCK to the fastest frequency not above `hz` and returns it.
+    pub fn set_clock(&mut self, hz: u32) -> Result<u32> {
+        block_on(self.set_clock_async(hz))
+    }
+
+    pub async fn set_clock_async(&mut self, hz: u32) -> Result<u32> {
+        if hz == 0 {
+            Err(FtdiError::Other("the SPI clock can't be 0 Hz".to_string()))?
+        }
+
+        // Hi-Speed chips run the MPSSE from 60 MHz unless divided by 5 for compatibility with
+        // the 12 MHz of the FT2232C/D.
+        let base_hz: u32 = if self.device.chip_type().is_some_and(|c| c.is_high_speed()) {
+            self.device.disable_divide_by_5_async().await?;
+            30_000_000
+        } else {
+            6_000_000
+        };
+        let divisor = base_hz.div_ceil(hz).clamp(1, 0x10000) - 1;
+        self.device
+            .configure_clock_divider_async(divisor as u16)
+            .await?;
+        self.clock_hz = base_hz / (divisor + 1);
+        info!(
+            "Setting SPI clock to {} Hz (divisor: {}, actual: {} Hz)",
+            hz, divisor, self.clock_hz
+        );
+
+        Ok(self.clock_hz)
+    }
+
+    /// Writes `data` to the device selected by chip select `cs`, an index into the configured
+    /// ones.
+    pub fn write(&mut self, cs: usize, data: &[u8]) -> Result<()> {
+        block_on(self.write_async(cs, data))
+    }
+
+    pub async fn write_async(&mut self, cs: usize, data: &[u8]) -> Result<()> {
+        self.transaction_async(cs, &mut [SpiOperation::Write(data)])
+            .await
+    }
+
+    /// Reads `data` from the device selected by chip select `cs`.
+    pub fn read(&mut self, cs: usize, data: &mut [u8]) -> Result<()> {
+        block_on(self.read_async(cs, data))
+    }
+
+    pub async fn read_async(&mut self, cs: usize, data: &mut [u8]) -> Result<()> {
+        self.transaction_async(cs, &mut [SpiOperation::Read(data)])
+            .await
+    }
+
+    /// Writes `data` to the device selected by chip select `cs` while reading into it.
+    pub fn transfer(&mut self, cs: usize, data: &mut [u8]) -> Result<()> {
+        block_on(self.transfer_async(cs, data))
+    }
+
+    pub async fn transfer_async(&mut self, cs: usize, data: &mut [u8]) -> Result<()> {
+        self.transaction_async(cs, &mut [SpiOperation::Transfer(data)])
+            .await
+    }
+
+    /// Runs `operations` in order with chip select `cs` asserted for all of them, e.g. a flash
+    /// command followed by reading its reply.
+    ///
+    /// Everything is sent in a single round trip, unless the commands or the data read back
+    /// don't fit the chip's buffer.
+    pub fn transaction(&mut self, cs: usize, operations: &mut [SpiOperation<'_>]) -> Result<()> {
+        block_on(self.transaction_async(cs, operations))
+    }
+
+    pub async fn transaction_async(
+        &mut self,
+        cs: usize,
+        operations: &mut [SpiOperation<'_>],
+    ) -> Result<()> {
+        let Some(&pin) = self.chip_selects.get(cs) else {
+            Err(FtdiError::Other(format!(
+                "no chip select {cs}, only {} are configured",
+                self.chip_selects.len()
+            )))?
+        };
+
+        let (write_edge, read_edge, order) = (
+            self.mode.write_edge(),
+            self.mode.read_edge(),
+            self.bit_order,
+        );
+        // Leave room for the opcode, length and send immediate.
+        let chunk_len = (self.buffer_size - 4).min(MAX_TRANSFER_LEN);
+
+        let high = pin >= 8;
+        let mut commands = vec![self.pins_command(high, Some(pin))];
+        for operation in operations.iter() {
+            match operation {
+                SpiOperation::Write(data) => {
+                    commands.extend(
+                        data.chunks(chunk_len)
+                            .map(|chunk| MpsseCommand::WriteBytes {
+                                edge: write_edge,
+                                order,
+                                data: chunk.to_vec(),
+                            }),
+                    )
+                }
+                SpiOperation::Read(data) => {
+                    commands.extend(data.chunks(chunk_len).map(|chunk| MpsseCommand::ReadBytes {
+                        edge: read_edge,
+                        order,
+                        len: chunk.len(),
+                    }))
+                }
+                SpiOperation::Transfer(data) => {
+                    commands.extend(data.chunks(chunk_len).map(|chunk| {
+                        MpsseCommand::TransferBytes {
+                            write_edge,
+                            read_edge,
+                            order,
+                            data: chunk.to_vec(),
+                        }
+                    }))
+                }
+            }
+        }
+        commands.push(self.pins_command(high, None));
+        debug!("spi transaction on cs {cs}: {operations:x?}");
+
+        let response = self.device.run_commands_async(&commands).await?;
+
+        let mut response = &response[..];
+        for operation in operations {
+            if let SpiOperation::Read(data) | SpiOperation::Transfer(data) = operation {
+                let (head, tail) = response.split_at(data.len());
+                data.copy_from_slice(head);
+                response = tail;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Leaves MPSSE mode and returns the device.
+    pub fn into_inner(self) -> Result<Device> {
+        block_on(self.into_inner_async())
+    }
+
+    pub async fn into_inner_async(mut self) -> Result<Device> {
+        self.device.set_bitmode_async(0, BitMode::Reset).await?;
+
+        Ok(self.device)
+    }
+
+    /// Returns the command setting the pins of the low or `high` byte, with SCK idle and only
+    /// chip select `selected` asserted.
+    fn pins_command(&self, high: bool, selected: Option<u8>) -> MpsseCommand {
+        let mut level = 0;
+        let mut direction = SCK | MOSI;
+        if self.mode.cpol() {
+            level |= SCK;
+        }
+        for &pin in &self.chip_selects {
+            level |= 1 << pin;
+            direction |= 1 << pin;
+        }
+        if let Some(pin) = selected {
+            level &= !(1 << pin);
+        }
+
+        let [value, high_value] = level.to_le_bytes();
+        let [direction, high_direction] = direction.to_le_bytes();
+        if high {
+            MpsseCommand::SetHighBits {
+                value: high_value,
+                direction: high_direction,
+            }
+        } else {
+            MpsseCommand::SetLowBits { value, direction }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::transport::MemoryTransport;
+
+    #[test]
+    fn test_spi_master() {
+        let transport = MemoryTransport::default();
+        let device = transport.open();
+        let mut spi = Spi::new(
+            device,
+            SpiConfig {
+                mode: SpiMode::Mode3,
+                chip_selects: vec![3, 9],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(spi.clock_hz(), 1_000_000);
+        // Sync, no loopback, 60 MHz / 2 / 30, then SCK idle high and both selects high.
+        assert_eq!(
+            transport.take_written(),
+            [0xaa, 0xab, 0x85, 0x8a, 0x86, 0x1d, 0x00, 0x80, 0x09, 0x0b, 0x82, 0x02, 0x02]
+        );
+
+        // A command and its reply in one round trip, on the select of the high byte.
+        transport.push_response(&[0xef, 0x40, 0x18]);
+        let mut id = [0; 3];
+        spi.transaction(
+            1,
+            &mut [SpiOperation::Write(&[0x9f]), SpiOperation::Read(&mut id)],
+        )
+        .unwrap();
+        assert_eq!(id, [0xef, 0x40, 0x18]);
+        assert_eq!(
+            transport.take_written(),
+            [0x82, 0x00, 0x02, 0x11, 0, 0, 0x9f, 0x20, 2, 0, 0x82, 0x02, 0x02, 0x87]
+        );
+
+        spi.set_mode(SpiMode::Mode0).unwrap();
+        spi.set_bit_order(BitOrder::LsbFirst);
+        assert_eq!(transport.take_written(), [0x80, 0x08, 0x0b]);
+        transport.push_response(&[0x12, 0x34]);
+        let mut data = [0xa5, 0x5a];
+        spi.transfer(0, &mut data).unwrap();
+        assert_eq!(data, [0x12, 0x34]);
+        assert_eq!(
+            transport.take_written(),
+            [0x80, 0x00, 0x0b, 0x39, 1, 0, 0xa5, 0x5a, 0x80, 0x08, 0x0b, 0x87]
+        );
+
+        // Mode 1 shifts out on the rising edge, writes need no round trip.
+        spi.set_mode(SpiMode::Mode1).unwrap();
+        spi.set_bit_order(BitOrder::MsbFirst);
+        transport.take_written();
+        spi.write(0, &[0x06]).unwrap();
+        assert_eq!(
+            transport.take_written(),
+            [0x80, 0x00, 0x0b, 0x10, 0, 0, 0x06, 0x80, 0x08, 0x0b]
+        );
+
+        // More than the 4 KiB buffer takes two round trips.
+        let reply: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        transport.push_response(&reply);
+        let mut data = vec![0; reply.len()];
+        spi.read(0, &mut data).unwrap();
+        assert_eq!(data, reply);
+        let written = transport.take_written();
+        assert_eq!(written.iter().filter(|&&b| b == 0x87).count(), 2);
+
+        assert!(spi.write(2, &[0]).is_err());
+        assert!(Spi::new(
+            MemoryTransport::default().open(),
+            SpiConfig {
+                chip_selects: vec![2],
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+}
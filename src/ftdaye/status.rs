@@ -0,0 +1,129 @@
+//! The modem and line status header the chip prepends to every bulk IN packet.
+
+use std::fmt;
+
+macro_rules! status_flags {
+    ($name:ident { $($(#[$doc:meta])* $flag:ident = $bit:expr,)* }) => {
+        impl $name {
+            $(
+                $(#[$doc])*
+                pub const fn $flag(self) -> bool {
+                    self.0 & (1 << $bit) != 0
+                }
+            )*
+
+            pub const fn from_bits(bits: u8) -> Self {
+                Self(bits)
+            }
+
+            /// Returns the raw status byte.
+            pub const fn bits(self) -> u8 {
+                self.0
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut set = f.debug_set();
+                $(
+                    if self.$flag() {
+                        set.entry(&format_args!(stringify!($flag)));
+                    }
+                )*
+                set.finish()
+            }
+        }
+    };
+}
+
+/// The first status byte, reflecting the modem control inputs.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModemStatus(u8);
+
+status_flags!(ModemStatus {
+    /// Clear To Send is asserted.
+    cts = 4,
+    /// Data Set Ready is asserted.
+    dsr = 5,
+    /// Ring Indicator is asserted.
+    ri = 6,
+    /// Data Carrier Detect is asserted.
+    dcd = 7,
+});
+
+/// The second status byte, reflecting the state of the UART receiver and transmitter.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineStatus(u8);
+
+status_flags!(LineStatus {
+    /// The receive buffer holds data.
+    data_ready = 0,
+    /// The receive buffer overflowed and data was lost.
+    overrun = 1,
+    /// A character was received with a parity error.
+    parity_error = 2,
+    /// A character was received without a valid stop bit.
+    framing_error = 3,
+    /// A break condition was received.
+    break_interrupt = 4,
+    /// The transmit holding register is empty.
+    transmitter_holding_empty = 5,
+    /// Both the transmit holding and shift registers are empty.
+    transmitter_empty = 6,
+    /// At least one character in the receive FIFO has an error.
+    fifo_error = 7,
+});
+
+/// The status bytes of a chip with CTS and DSR asserted and the transmitter empty, the usual
+/// state of an idle channel.
+pub(crate) const IDLE_STATUS: [u8; 2] = [0x32, 0x60];
+
+/// A single bulk IN packet, split into its status header and payload.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Packet<'a> {
+    pub modem: ModemStatus,
+    pub line: LineStatus,
+    pub payload: &'a [u8],
+}
+
+/// Splits a bulk IN transfer into its `max_packet_size` packets.
+///
+/// Each packet starts with its own two status bytes, not just the first one in the transfer.
+pub(crate) fn packets(transfer: &[u8], max_packet_size: usize) -> impl Iterator<Item = Packet<'_>> {
+    transfer
+        .chunks(max_packet_size)
+        .filter_map(|packet| match packet {
+            [modem, line, payload @ ..] => Some(Packet {
+                modem: ModemStatus::from_bits(*modem),
+                line: LineStatus::from_bits(*line),
+                payload,
+            }),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_packets() {
+        let mut transfer = vec![0x32, 0x60];
+        transfer.extend(0..62);
+        transfer.extend([0x12, 0x62, 0xaa, 0xbb]);
+
+        let packets: Vec<_> = packets(&transfer, 64).collect();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].payload, (0..62).collect::<Vec<u8>>());
+        assert_eq!(packets[1].payload, [0xaa, 0xbb]);
+
+        assert!(packets[0].modem.cts() && packets[0].modem.dsr());
+        assert!(!packets[1].modem.dsr());
+        assert!(packets[1].line.overrun());
+        assert!(packets[1].line.transmitter_empty());
+        assert_eq!(
+            format!("{:?}", packets[1].line),
+            "{overrun, transmitter_holding_empty, transmitter_empty}"
+        );
+    }
+}
@@ -0,0 +1,619 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use nusb::transfer::TransferError;
+
+use super::{DeviceDescriptor, TransferSink, Transport, TransportFuture};
+
+const SIO_POLL_MODEM_STATUS_REQUEST: u8 = 0x05;
+const SIO_SET_BITMODE_REQUEST: u8 = 0x0B;
+const SIO_READ_PINS_REQUEST: u8 = 0x0C;
+const SIO_READ_EEPROM_REQUEST: u8 = 0x90;
+const SIO_WRITE_EEPROM_REQUEST: u8 = 0x91;
+const SIO_ERASE_EEPROM_REQUEST: u8 = 0x92;
+
+/// A vendor control request as seen by the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ControlRequest {
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+}
+
+/// An in-memory [`Transport`] for running the driver without hardware.
+///
+/// Everything written to the bulk OUT endpoint and every control request is recorded, and bulk IN
+/// reads are served from a queue of scripted response bytes, packetized with the two FTDI status
+/// bytes like a real chip would. Queued reads keep `depth` transfers in flight, which pick up the
+/// data already waiting even after the driver stopped reading. The configuration EEPROM is
+/// modelled as well, starting out blank. In MPSSE mode, a bulk write of just the bogus opcode 0xAA
+/// or 0xAB is answered with its bad command echo, so that the driver can synchronise. The
+/// transport is a cheap handle: clone it before moving it into a
+/// [`Device`](crate::ftdaye::Device) to keep access to the recorded traffic.
+#[derive(Clone, Debug)]
+pub struct MemoryTransport {
+    descriptor: DeviceDescriptor,
+    state: Arc<Mutex<MemoryState>>,
+}
+
+#[derive(Debug)]
+struct MemoryState {
+    max_packet_size: usize,
+    status: [u8; 2],
+    pins: u8,
+    mpsse: bool,
+    control_requests: Vec<ControlRequest>,
+    written: Vec<u8>,
+    responses: VecDeque<u8>,
+    eeprom: Vec<u16>,
+    connected: bool,
+    reconnects: usize,
+    bulk_reads: usize,
+    stalls: usize,
+    cleared_halts: Vec<u8>,
+}
+
+impl MemoryState {
+    /// Returns the error the next operation fails with, if any.
+    fn fault(&mut self) -> io::Result<()> {
+        if !self.connected {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        if self.stalls > 0 {
+            self.stalls -= 1;
+            return Err(io::Error::other(TransferError::Stall));
+        }
+
+        Ok(())
+    }
+}
+
+impl MemoryTransport {
+    /// Creates a transport presenting `descriptor`, with 512 byte (high speed) packets.
+    pub fn new(descriptor: DeviceDescriptor) -> Self {
+        Self {
+            descriptor,
+            state: Arc::new(Mutex::new(MemoryState {
+                max_packet_size: 512,
+                // CTS and DSR asserted, transmitter empty.
+                status: [0x32, 0x60],
+                pins: 0,
+                mpsse: false,
+                control_requests: vec![],
+                written: vec![],
+                responses: VecDeque::new(),
+                eeprom: vec![0xffff; 128],
+                connected: true,
+                reconnects: 0,
+                bulk_reads: 0,
+                stalls: 0,
+                cleared_halts: vec![],
+            })),
+        }
+    }
+
+    /// Overrides the bulk endpoint packet size (64 bytes for full speed chips).
+    pub fn with_max_packet_size(self, max_packet_size: usize) -> Self {
+        self.state().max_packet_size = max_packet_size;
+        self
+    }
+
+    /// Sets the modem and line status bytes sent at the start of every packet.
+    pub fn set_status(&self, modem: u8, line: u8) {
+        self.state().status = [modem, line];
+    }
+
+    /// Sets the pin state returned by the read pins request.
+    pub fn set_pin_state(&self, pins: u8) {
+        self.state().pins = pins;
+    }
+
+    /// Queues bytes to be returned by subsequent bulk IN reads.
+    pub fn push_response(&self, data: &[u8]) {
+        self.state().responses.extend(data);
+    }
+
+    /// Replaces the contents of the configuration EEPROM, which also sets its size.
+    pub fn with_eeprom(self, image: &[u8]) -> Self {
+        self.state().eeprom = image
+            .chunks(2)
+            .map(|w| u16::from_le_bytes([w[0], w.get(1).copied().unwrap_or(0xff)]))
+            .collect();
+        self
+    }
+
+    /// Returns the contents of the configuration EEPROM.
+    pub fn eeprom(&self) -> Vec<u8> {
+        self.state()
+            .eeprom
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect()
+    }
+
+    /// Returns the number of queued response bytes not read yet.
+    pub fn pending_response(&self) -> usize {
+        self.state().responses.len()
+    }
+
+    /// Returns and clears everything written to the bulk OUT endpoint so far.
+    pub fn take_written(&self) -> Vec<u8> {
+        std::mem::take(&mut self.state().written)
+    }
+
+    /// Returns and clears the control requests issued so far.
+    pub fn take_control_requests(&self) -> Vec<ControlRequest> {
+        std::mem::take(&mut self.state().control_requests)
+    }
+
+    /// Simulates unplugging the device: every operation fails until the driver reconnects.
+    pub fn unplug(&self) {
+        self.state().connected = false;
+    }
+
+    /// Makes the next `count` operations fail with a stall.
+    pub fn stall(&self, count: usize) {
+        self.state().stalls = count;
+    }
+
+    /// Returns and clears the endpoints whose halt the driver cleared.
+    pub fn take_cleared_halts(&self) -> Vec<u8> {
+        std::mem::take(&mut self.state().cleared_halts)
+    }
+
+    /// Returns how often the driver reconnected.
+    pub fn reconnects(&self) -> usize {
+        self.state().reconnects
+    }
+
+    /// Returns how many bulk IN transfers the driver made.
+    pub fn bulk_reads(&self) -> usize {
+        self.state().bulk_reads
+    }
+
+    /// Opens a [`Device`](crate::ftdaye::Device) on a clone of the transport, with the default
+    /// [`Builder`](crate::ftdaye::Builder) settings.
+    #[cfg(test)]
+    pub(crate) fn open(&self) -> crate::ftdaye::Device {
+        crate::ftdaye::Builder::new()
+            .transport_open(self.clone())
+            .unwrap()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Completes a single bulk IN transfer from the scripted responses.
+    fn read_transfer(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state();
+        state.fault()?;
+        let state = &mut *state;
+        state.bulk_reads += 1;
+
+        Ok(fill_packets(
+            buf,
+            state.max_packet_size,
+            state.status,
+            &mut state.responses,
+        ))
+    }
+}
+
+impl Default for MemoryTransport {
+    /// An FT2232H with the stock FTDI VID/PID.
+    fn default() -> Self {
+        Self::new(DeviceDescriptor {
+            vendor_id: 0x0403,
+            product_id: 0x6010,
+            device_version: 0x700,
+            serial_number: None,
+            product_string: None,
+        })
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn descriptor(&self) -> &DeviceDescriptor {
+        &self.descriptor
+    }
+
+    fn max_packet_size(&self) -> usize {
+        self.state().max_packet_size
+    }
+
+    fn control_out(
+        &mut self,
+        request: u8,
+        value: u16,
+        index: u16,
+        _timeout: Duration,
+    ) -> TransportFuture<'_, ()> {
+        let mut state = self.state();
+        if let Err(e) = state.fault() {
+            return Box::pin(std::future::ready(Err(e)));
+        }
+        state.control_requests.push(ControlRequest {
+            request,
+            value,
+            index,
+        });
+
+        let size = state.eeprom.len();
+        match request {
+            SIO_SET_BITMODE_REQUEST => state.mpsse = value >> 8 == 0x02,
+            SIO_WRITE_EEPROM_REQUEST => state.eeprom[index as usize % size] = value,
+            SIO_ERASE_EEPROM_REQUEST => state.eeprom.fill(0xffff),
+            _ => {}
+        }
+
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn control_in<'a>(
+        &'a mut self,
+        request: u8,
+        _value: u16,
+        index: u16,
+        buf: &'a mut [u8],
+        _timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        let mut state = self.state();
+        if let Err(e) = state.fault() {
+            return Box::pin(std::future::ready(Err(e)));
+        }
+        let data = match request {
+            SIO_POLL_MODEM_STATUS_REQUEST => state.status,
+            // Answered with a single byte.
+            SIO_READ_PINS_REQUEST => [state.pins, 0],
+            // Addresses wrap around like on a real EEPROM.
+            SIO_READ_EEPROM_REQUEST => {
+                state.eeprom[index as usize % state.eeprom.len()].to_le_bytes()
+            }
+            _ => {
+                return Box::pin(std::future::ready(Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("unsupported control request {request:#04x}"),
+                ))))
+            }
+        };
+
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Box::pin(std::future::ready(Ok(n)))
+    }
+
+    fn read_bulk<'a>(
+        &'a mut self,
+        _endpoint: u8,
+        buf: &'a mut [u8],
+        _timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        Box::pin(std::future::ready(self.read_transfer(buf)))
+    }
+
+    fn write_bulk<'a>(
+        &'a mut self,
+        _endpoint: u8,
+        buf: &'a [u8],
+        _timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        let mut state = self.state();
+        if let Err(e) = state.fault() {
+            return Box::pin(std::future::ready(Err(e)));
+        }
+        state.written.extend_from_slice(buf);
+        if let (true, [opcode @ (0xaa | 0xab)]) = (state.mpsse, buf) {
+            state.responses.extend([0xfa, *opcode]);
+        }
+        Box::pin(std::future::ready(Ok(buf.len())))
+    }
+
+    fn clear_halt(&mut self, endpoint: u8) -> TransportFuture<'_, ()> {
+        self.state().cleared_halts.push(endpoint);
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn reconnect(&mut self, _timeout: Duration) -> TransportFuture<'_, ()> {
+        let mut state = self.state();
+        state.connected = true;
+        state.reconnects += 1;
+        // A fresh device has nothing to say yet.
+        state.responses.clear();
+        state.mpsse = false;
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn read_bulk_queued<'a>(
+        &'a mut self,
+        _endpoint: u8,
+        transfer_size: usize,
+        depth: usize,
+        _timeout: Duration,
+        sink: &'a mut TransferSink<'_>,
+    ) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            let mut buf = vec![0; transfer_size];
+            loop {
+                let read = self.read_transfer(&mut buf)?;
+                if !sink(&buf[..read]) {
+                    break;
+                }
+            }
+
+            // The other transfers in flight pick up whatever data is already waiting before
+            // they are cancelled.
+            for _ in 1..depth.max(1) {
+                if self.pending_response() == 0 {
+                    break;
+                }
+                let read = self.read_transfer(&mut buf)?;
+                sink(&buf[..read]);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Moves bytes from `data` into `buf` the way an FTDI chip packetizes them on the bulk IN
+/// endpoint: every max-packet sized chunk starts with the two status bytes, even if it carries no
+/// data. Returns the length of the transfer.
+pub(crate) fn fill_packets(
+    buf: &mut [u8],
+    max_packet_size: usize,
+    status: [u8; 2],
+    data: &mut VecDeque<u8>,
+) -> usize {
+    let mut read = 0;
+    for packet in buf.chunks_mut(max_packet_size) {
+        if packet.len() < 2 || (read > 0 && data.is_empty()) {
+            break;
+        }
+
+        packet[..2].copy_from_slice(&status);
+        let count = data.len().min(packet.len() - 2);
+        for (dst, src) in packet[2..].iter_mut().zip(data.drain(..count)) {
+            *dst = src;
+        }
+        read += 2 + count;
+
+        if count < packet.len() - 2 {
+            // Short packet, the transfer ends here.
+            break;
+        }
+    }
+
+    read
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::mpsse::MpsseCommand;
+    use crate::ftdaye::transport::descriptor;
+    use crate::ftdaye::{error::FtdiError, jtag::FtdiMpsse, BitMode, Builder};
+    use crate::{JtagAdapter, JtagProbeError, FTDI_COMPAT_DEVICES};
+    use bitvec::field::BitField;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_device_control_and_bulk() {
+        let transport = MemoryTransport::default();
+        let mut device = transport.open();
+
+        device.set_bitmode(0x0b, BitMode::Mpsse).unwrap();
+        device.set_latency_timer(1).unwrap();
+        assert_eq!(
+            transport.take_control_requests(),
+            [
+                ControlRequest {
+                    request: 0x0B,
+                    value: 0x020b,
+                    index: 1
+                },
+                ControlRequest {
+                    request: 0x09,
+                    value: 1,
+                    index: 1
+                },
+            ]
+        );
+
+        device.write_all(&[0x87]).unwrap();
+        assert_eq!(transport.take_written(), [0x87]);
+
+        transport.push_response(&[0x12, 0x34]);
+        let mut data = [0; 2];
+        device.read_exact(&mut data).unwrap();
+        assert_eq!(data, [0x12, 0x34]);
+    }
+
+    #[test]
+    fn test_device_queued_read() {
+        let transport = MemoryTransport::default();
+        let mut device = Builder::new()
+            .with_read_queue_depth(4)
+            .with_read_transfer_size(2048)
+            .transport_open(transport.clone())
+            .unwrap();
+
+        let response: Vec<u8> = (0..10_000u32).map(|i| (i * 7) as u8).collect();
+        transport.push_response(&response);
+
+        // Two transfers of 2040 data bytes are needed, the second one and the one still in flight
+        // when it completes leave a surplus in the read queue.
+        let mut data = vec![0; 3000];
+        device.read_exact(&mut data).unwrap();
+        assert_eq!(data, response[..3000]);
+        assert_eq!(transport.bulk_reads(), 3);
+        assert_eq!(device.queued_len(), 1080 + 2040);
+        assert_eq!(transport.pending_response(), 10_000 - 3000 - 3120);
+
+        let mut rest = vec![0; 7000];
+        device.read_exact(&mut rest).unwrap();
+        assert_eq!(rest, response[3000..]);
+        assert_eq!(transport.pending_response(), 0);
+    }
+
+    #[test]
+    fn test_device_status() {
+        let transport = MemoryTransport::default().with_max_packet_size(64);
+        let mut device = transport.open();
+
+        let response: Vec<u8> = (0..200).collect();
+        transport.push_response(&response);
+        let mut data = vec![0; 200];
+        device.read_exact(&mut data).unwrap();
+        assert_eq!(data, response);
+        assert!(device.modem_status().cts());
+        assert!(device.line_status().transmitter_empty());
+        assert!(!device.line_status().overrun());
+
+        transport.set_status(0x02, 0x62);
+        transport.push_response(&[0x55]);
+        let err = device.read(&mut [0; 1]).unwrap_err();
+        assert!(matches!(
+            err.get_ref().and_then(|e| e.downcast_ref()),
+            Some(FtdiError::Overrun)
+        ));
+        assert!(!device.modem_status().cts());
+        assert!(device.line_status().overrun());
+    }
+
+    #[test]
+    fn test_mpsse_read_register() {
+        let transport = MemoryTransport::default();
+        let device = transport.open();
+        let mut ft = FtdiMpsse::new(device, 1000);
+        transport.take_written();
+
+        transport.push_response(&[0x93, 0xd0, 0x62, 0x03]);
+        let mut data = [0; 4];
+        ft.read_register(0x09, &mut data);
+        assert_eq!(u32::from_le_bytes(data), 0x0362d093);
+
+        let written = transport.take_written();
+        // Read 4 bytes in on +ve edge, followed by send immediate.
+        assert!(written.windows(4).any(|w| w == [0x28, 3, 0, 0x87]));
+
+        // The echo of a rejected command is not data.
+        transport.push_response(&[0xfa, 0xab, 0x93, 0xd0, 0x62, 0x03]);
+        assert!(matches!(
+            async_io::block_on(ft.read_register_async(0x09, &mut data)),
+            Err(FtdiError::BadCommand(0xab))
+        ));
+        assert_eq!(transport.pending_response(), 0);
+        // Also when it straddles the end of the reply.
+        transport.push_response(&[0x93, 0xd0, 0x62, 0xfa, 0xab]);
+        assert!(matches!(
+            async_io::block_on(ft.read_register_async(0x09, &mut data)),
+            Err(FtdiError::BadCommand(0xab))
+        ));
+
+        // Or when the rest is still in the chip after a full packet of reply.
+        let mut long = vec![0; 510];
+        transport.push_response(&[0xfa, 0xab]);
+        transport.push_response(&long);
+        assert!(matches!(
+            async_io::block_on(ft.read_register_async(0x09, &mut long)),
+            Err(FtdiError::BadCommand(0xab))
+        ));
+        assert_eq!(transport.pending_response(), 0);
+        // Without anything after the reply, an echo is data.
+        transport.push_response(&[0xfa, 0xab, 0x62, 0x03]);
+        ft.read_register(0x09, &mut data);
+        assert_eq!(data, [0xfa, 0xab, 0x62, 0x03]);
+
+        // A good reply takes a single read.
+        transport.push_response(&[0x93, 0xd0, 0x62, 0x03]);
+        let reads = transport.bulk_reads();
+        ft.read_register(0x09, &mut data);
+        assert_eq!(transport.bulk_reads(), reads + 1);
+
+        // An unknown chip is assumed to have the smallest buffer.
+        let device = MemoryTransport::new(descriptor(0x6010, 0x1234)).open();
+        assert_eq!(device.mpsse_buffer_size(), 128);
+    }
+
+    #[test]
+    fn test_mpsse_chunked_scan() {
+        use crate::ftdaye::disasm::disassemble;
+
+        let transport = MemoryTransport::default();
+        let device = transport.open();
+        let mut ft = FtdiMpsse::new(device, 1000);
+        transport.take_written();
+
+        // Longer than an MPSSE command can clock, split into batches fitting the 4 KiB buffer
+        // of the FT2232H.
+        let out: Vec<u8> = (0..70_000u32).map(|i| i as u8).collect();
+        let reply: Vec<u8> = out.iter().map(|b| !b).collect();
+        transport.push_response(&reply);
+        let mut data = out.clone();
+        ft.read_write_register(0x09, &mut data);
+        assert_eq!(data, reply);
+
+        let written = transport.take_written();
+        let mut sent = vec![];
+        for (_, command) in disassemble(&written) {
+            if let MpsseCommand::TransferBytes { data, .. } = command.unwrap() {
+                assert!(data.len() <= 4092);
+                sent.extend(data);
+            }
+        }
+        assert_eq!(sent, out);
+
+        transport.push_response(&reply);
+        let mut data = vec![0; out.len()];
+        ft.read_register(0x09, &mut data);
+        assert_eq!(data, reply);
+        let reads: Vec<usize> = disassemble(&transport.take_written())
+            .filter_map(|(_, command)| match command.unwrap() {
+                MpsseCommand::ReadBytes { len, .. } => Some(len),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(reads.iter().sum::<usize>(), out.len());
+        assert!(reads.iter().all(|&len| len <= 4096));
+
+        // Nothing to clock just moves the TAP.
+        ft.write_register(0x09, &[]);
+        assert!(
+            disassemble(&transport.take_written()).all(|(_, command)| matches!(
+                command.unwrap(),
+                MpsseCommand::WriteTms { .. }
+                    | MpsseCommand::WriteBits { .. }
+                    | MpsseCommand::SendImmediate
+            ))
+        );
+    }
+
+    #[test]
+    fn test_jtag_adapter_captured_bits() {
+        let transport = MemoryTransport::default();
+        let device = transport.open();
+        let mut adapter = JtagAdapter::new(FTDI_COMPAT_DEVICES[0], device).unwrap();
+        adapter.attach().unwrap();
+        transport.take_written();
+
+        for _ in 0..8 {
+            adapter.shift_bit(false, true, true).unwrap();
+        }
+        transport.push_response(&[0xa5]);
+        let bits = adapter.read_captured_bits().unwrap();
+
+        assert_eq!(bits.len(), 8);
+        assert_eq!(bits.load_le::<u8>(), 0xa5);
+        assert_eq!(transport.take_written(), [0x39, 0, 0, 0xff, 0x87]);
+
+        adapter.shift_bit(false, true, true).unwrap();
+        transport.push_response(&[0xfa, 0xab, 0x01]);
+        assert!(matches!(
+            adapter.read_captured_bits(),
+            Err(JtagProbeError::BadCommand(0xab))
+        ));
+    }
+}
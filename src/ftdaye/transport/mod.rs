@@ -0,0 +1,244 @@
+//! USB transports underneath [`Device`](super::Device).
+//!
+//! The FTDI driver only needs a few primitives from the USB stack: vendor control requests on
+//! the default endpoint (the "SIO" requests), and bulk IN/OUT transfers on the endpoints of the
+//! claimed interface. [`Transport`] captures exactly those, so the same `Device`, `FtdiMpsse`
+//! and `JtagAdapter` code can run on top of real hardware ([`NusbTransport`]), an in-memory
+//! backend ([`MemoryTransport`]) or a recorded session ([`ReplayTransport`]).
+//!
+//! Transfers are asynchronous; the blocking driver API drives them to completion with
+//! `async_io::block_on`.
+
+mod memory;
+mod replay;
+mod usb;
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+
+use nusb::DeviceInfo;
+
+use super::ChipType;
+
+pub(crate) use memory::fill_packets;
+pub use memory::{ControlRequest, MemoryTransport};
+pub use replay::ReplayTransport;
+pub(crate) use usb::open_device;
+pub use usb::NusbTransport;
+
+/// The future returned by [`Transport`] operations.
+pub type TransportFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// The operations the FTDI driver performs on the USB device.
+pub trait Transport: Send {
+    /// Returns the USB descriptor information of the device behind this transport.
+    fn descriptor(&self) -> &DeviceDescriptor;
+
+    /// Returns the maximum packet size of the bulk endpoints, in bytes.
+    fn max_packet_size(&self) -> usize;
+
+    /// Sends a vendor request to the device, without a data stage.
+    fn control_out(
+        &mut self,
+        request: u8,
+        value: u16,
+        index: u16,
+        timeout: Duration,
+    ) -> TransportFuture<'_, ()>;
+
+    /// Sends a vendor request to the device and reads its data stage into `buf`.
+    ///
+    /// Returns the number of bytes the device sent.
+    fn control_in<'a>(
+        &'a mut self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &'a mut [u8],
+        timeout: Duration,
+    ) -> TransportFuture<'a, usize>;
+
+    /// Reads a single bulk IN transfer from `endpoint` into `buf`.
+    ///
+    /// The returned data still contains the FTDI modem/line status header.
+    fn read_bulk<'a>(
+        &'a mut self,
+        endpoint: u8,
+        buf: &'a mut [u8],
+        timeout: Duration,
+    ) -> TransportFuture<'a, usize>;
+
+    /// Writes `buf` to `endpoint` as a single bulk OUT transfer.
+    fn write_bulk<'a>(
+        &'a mut self,
+        endpoint: u8,
+        buf: &'a [u8],
+        timeout: Duration,
+    ) -> TransportFuture<'a, usize>;
+
+    /// Clears a halt condition on `endpoint`, e.g. after a stall.
+    ///
+    /// The default implementation does nothing.
+    fn clear_halt(&mut self, endpoint: u8) -> TransportFuture<'_, ()> {
+        let _ = endpoint;
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    /// Reopens the device after it disappeared, waiting up to `timeout` for it to come back.
+    ///
+    /// The default implementation fails with [`io::ErrorKind::Unsupported`].
+    fn reconnect(&mut self, timeout: Duration) -> TransportFuture<'_, ()> {
+        let _ = timeout;
+        Box::pin(std::future::ready(Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "transport can't reconnect",
+        ))))
+    }
+
+    /// Reads from `endpoint` while keeping up to `depth` bulk IN transfers of `transfer_size`
+    /// bytes queued, so the bus never idles waiting for the host.
+    ///
+    /// Completed transfers are passed to `sink` in order. Once `sink` returns `false` no new
+    /// transfers are submitted and the ones still in flight are cancelled; whatever data they
+    /// received before that is passed to `sink` as well, so nothing is lost. `timeout` applies to
+    /// each transfer.
+    ///
+    /// The default implementation has a single transfer in flight at a time.
+    fn read_bulk_queued<'a>(
+        &'a mut self,
+        endpoint: u8,
+        transfer_size: usize,
+        depth: usize,
+        timeout: Duration,
+        sink: &'a mut TransferSink<'_>,
+    ) -> TransportFuture<'a, ()> {
+        let _ = depth;
+        Box::pin(async move {
+            let mut buf = vec![0; transfer_size];
+            loop {
+                let read = self.read_bulk(endpoint, &mut buf, timeout).await?;
+                if !sink(&buf[..read]) {
+                    return Ok(());
+                }
+            }
+        })
+    }
+
+    /// Writes to `endpoint` while keeping up to `depth` bulk OUT transfers queued.
+    ///
+    /// `source` fills the (cleared) buffer of each transfer before it is submitted; leaving it
+    /// empty ends the stream, which completes once all queued transfers did. `timeout` applies
+    /// to each transfer.
+    ///
+    /// The default implementation has a single transfer in flight at a time.
+    fn write_bulk_queued<'a>(
+        &'a mut self,
+        endpoint: u8,
+        depth: usize,
+        timeout: Duration,
+        source: &'a mut TransferSource<'_>,
+    ) -> TransportFuture<'a, ()> {
+        let _ = depth;
+        Box::pin(async move {
+            let mut buf = vec![];
+            loop {
+                buf.clear();
+                source(&mut buf);
+                if buf.is_empty() {
+                    return Ok(());
+                }
+
+                let mut written = 0;
+                while written < buf.len() {
+                    written += self.write_bulk(endpoint, &buf[written..], timeout).await?;
+                }
+            }
+        })
+    }
+}
+
+/// Receives the data of completed bulk IN transfers, returns whether to keep reading.
+pub type TransferSink<'a> = dyn FnMut(&[u8]) -> bool + Send + 'a;
+
+/// Fills the buffer of the next bulk OUT transfer, an empty buffer ends the stream.
+pub type TransferSource<'a> = dyn FnMut(&mut Vec<u8>) + Send + 'a;
+
+impl std::fmt::Debug for dyn Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transport")
+            .field("descriptor", self.descriptor())
+            .finish()
+    }
+}
+
+/// The parts of the USB device descriptor the driver cares about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// The `bcdDevice` field, used to tell FTDI chip generations apart.
+    pub device_version: u16,
+    pub serial_number: Option<String>,
+    pub product_string: Option<String>,
+}
+
+impl DeviceDescriptor {
+    /// Detects the FTDI chip type from the `bcdDevice` field.
+    ///
+    /// Returns `None` if the version is not recognized.
+    pub fn chip_type(&self) -> Option<ChipType> {
+        let chip_type = match (
+            self.device_version,
+            self.serial_number.as_deref().unwrap_or(""),
+        ) {
+            (0x400, _) | (0x200, "") => ChipType::Bm,
+            (0x200, _) => ChipType::Am,
+            (0x500, _) => ChipType::FT2232C,
+            (0x600, _) => ChipType::R,
+            (0x700, _) => ChipType::FT2232H,
+            (0x800, _) => ChipType::FT4232H,
+            (0x900, _) => ChipType::FT232H,
+            (0x1000, _) => ChipType::FT230X,
+            (0x2800, _) => ChipType::FT2233HP,
+            (0x2900, _) => ChipType::FT4233HP,
+            (0x3000, _) => ChipType::FT2232HP,
+            (0x3100, _) => ChipType::FT4232HP,
+            (0x3200, _) => ChipType::FT233HP,
+            (0x3300, _) => ChipType::FT232HP,
+            (0x3600, _) => ChipType::FT4232HA,
+
+            (version, _) => {
+                log::warn!("Unknown FTDI device version: {:X?}", version);
+                return None;
+            }
+        };
+
+        Some(chip_type)
+    }
+}
+
+/// Returns the descriptor of a chip with the stock FTDI vendor ID, for tests.
+#[cfg(test)]
+pub(crate) fn descriptor(product_id: u16, device_version: u16) -> DeviceDescriptor {
+    DeviceDescriptor {
+        vendor_id: 0x0403,
+        product_id,
+        device_version,
+        serial_number: None,
+        product_string: None,
+    }
+}
+
+impl From<&DeviceInfo> for DeviceDescriptor {
+    fn from(info: &DeviceInfo) -> Self {
+        Self {
+            vendor_id: info.vendor_id(),
+            product_id: info.product_id(),
+            device_version: info.device_version(),
+            serial_number: info.serial_number().map(|s| s.to_string()),
+            product_string: info.product_string().map(|s| s.to_string()),
+        }
+    }
+}
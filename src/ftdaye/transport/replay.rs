@@ -0,0 +1,357 @@
+//! A transport that plays back a [`Recording`] of the traffic with a real chip.
+
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::{fill_packets, DeviceDescriptor, TransferSink, Transport, TransportFuture};
+use crate::ftdaye::recording::{Event, Recording};
+use crate::ftdaye::status::IDLE_STATUS;
+
+/// A [`Transport`] playing back a [`Recording`], for turning a session captured on real hardware
+/// into a test.
+///
+/// Every operation of the driver has to match the next recorded event: control requests and
+/// bulk OUT data must be the same, while control and bulk IN responses are served from the
+/// recording. Anything else fails with [`io::ErrorKind::InvalidData`]. Timing is not replayed.
+/// Bulk IN transfers recorded without their status bytes get those of an idle chip, CTS and DSR
+/// asserted and the transmitter empty.
+/// Queued reads pick up as many of the following bulk IN events as the queue had transfers in
+/// flight when the driver stopped reading.
+///
+/// Like [`MemoryTransport`](super::MemoryTransport) this is a cheap handle, so a clone can check
+/// that the whole recording was replayed.
+#[derive(Clone, Debug)]
+pub struct ReplayTransport {
+    descriptor: DeviceDescriptor,
+    max_packet_size: usize,
+    state: Arc<Mutex<ReplayState>>,
+}
+
+#[derive(Debug)]
+struct ReplayState {
+    events: VecDeque<Event>,
+    position: usize,
+}
+
+impl ReplayTransport {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            descriptor: recording.descriptor,
+            max_packet_size: recording.max_packet_size,
+            state: Arc::new(Mutex::new(ReplayState {
+                events: recording.events.into_iter().map(|(_, e)| e).collect(),
+                position: 0,
+            })),
+        }
+    }
+
+    /// Reads the recording to replay from a file.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(Recording::open(path)?))
+    }
+
+    /// Returns how many recorded events were not replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.state().events.len()
+    }
+
+    /// Fails unless the whole recording was replayed.
+    pub fn finish(&self) -> io::Result<()> {
+        let state = self.state();
+        match state.events.front() {
+            Some(event) => Err(mismatch(state.position, Some(event), "end of replay")),
+            None => Ok(()),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, ReplayState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Completes a bulk IN transfer with the next recorded one.
+    fn read_transfer(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let max_packet_size = self.max_packet_size;
+        let capacity = buf.len() / max_packet_size * (max_packet_size - 2)
+            + (buf.len() % max_packet_size).saturating_sub(2);
+        self.next(
+            || format!("in of up to {capacity} bytes"),
+            |e| match e {
+                Event::BulkIn(data) if data.len() <= capacity => {
+                    let mut data = data.iter().copied().collect();
+                    Some(fill_packets(buf, max_packet_size, IDLE_STATUS, &mut data))
+                }
+                Event::BulkInRaw(data) if data.len() <= buf.len() => {
+                    buf[..data.len()].copy_from_slice(data);
+                    Some(data.len())
+                }
+                _ => None,
+            },
+        )
+    }
+
+    /// Takes the next event, which `matches` has to accept. `actual` describes the operation
+    /// for the error otherwise.
+    fn next<T>(
+        &self,
+        actual: impl FnOnce() -> String,
+        matches: impl FnOnce(&Event) -> Option<T>,
+    ) -> io::Result<T> {
+        let mut state = self.state();
+        let Some(result) = state.events.front().and_then(matches) else {
+            return Err(mismatch(state.position, state.events.front(), &actual()));
+        };
+        state.events.pop_front();
+        state.position += 1;
+
+        Ok(result)
+    }
+}
+
+fn mismatch(position: usize, expected: Option<&Event>, actual: &str) -> io::Error {
+    let expected = expected.map_or("end of recording".into(), |e| e.to_string());
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("replay diverged at event {position}: expected {expected}, got {actual}"),
+    )
+}
+
+impl Transport for ReplayTransport {
+    fn descriptor(&self) -> &DeviceDescriptor {
+        &self.descriptor
+    }
+
+    fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    fn control_out(
+        &mut self,
+        request: u8,
+        value: u16,
+        index: u16,
+        _timeout: Duration,
+    ) -> TransportFuture<'_, ()> {
+        let actual = Event::ControlOut {
+            request,
+            value,
+            index,
+        };
+        let result = self.next(|| actual.to_string(), |e| (*e == actual).then_some(()));
+        Box::pin(std::future::ready(result))
+    }
+
+    fn control_in<'a>(
+        &'a mut self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &'a mut [u8],
+        _timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        let result = self.next(
+            || format!("ctrl_in {request:02x} {value:04x} {index:04x}"),
+            |e| match e {
+                Event::ControlIn {
+                    request: r,
+                    value: v,
+                    index: i,
+                    data,
+                } if (*r, *v, *i) == (request, value, index) && data.len() <= buf.len() => {
+                    buf[..data.len()].copy_from_slice(data);
+                    Some(data.len())
+                }
+                _ => None,
+            },
+        );
+        Box::pin(std::future::ready(result))
+    }
+
+    fn read_bulk<'a>(
+        &'a mut self,
+        _endpoint: u8,
+        buf: &'a mut [u8],
+        _timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        Box::pin(std::future::ready(self.read_transfer(buf)))
+    }
+
+    fn write_bulk<'a>(
+        &'a mut self,
+        _endpoint: u8,
+        buf: &'a [u8],
+        _timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        let actual = Event::BulkOut(buf.to_vec());
+        let result = self.next(
+            || actual.to_string(),
+            |e| (*e == actual).then_some(buf.len()),
+        );
+        Box::pin(std::future::ready(result))
+    }
+
+    fn read_bulk_queued<'a>(
+        &'a mut self,
+        _endpoint: u8,
+        transfer_size: usize,
+        depth: usize,
+        _timeout: Duration,
+        sink: &'a mut TransferSink<'_>,
+    ) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            let mut buf = vec![0; transfer_size];
+            loop {
+                let read = self.read_transfer(&mut buf)?;
+                if !sink(&buf[..read]) {
+                    break;
+                }
+            }
+
+            // The transfers that were still in flight when the driver stopped reading were
+            // recorded right after the last one.
+            for _ in 1..depth.max(1) {
+                let in_flight = matches!(
+                    self.state().events.front(),
+                    Some(Event::BulkIn(_) | Event::BulkInRaw(_))
+                );
+                if !in_flight {
+                    break;
+                }
+                let read = self.read_transfer(&mut buf)?;
+                sink(&buf[..read]);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::error::FtdiError;
+    use crate::ftdaye::transport::MemoryTransport;
+    use crate::ftdaye::{BitMode, Builder, Device};
+    use std::io::{Read, Write};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record_replay() {
+        fn session(device: &mut Device) -> Vec<u8> {
+            device.set_bitmode(0x0b, BitMode::Mpsse).unwrap();
+            device.set_latency_timer(1).unwrap();
+            device.write_all(&[0x81, 0x87]).unwrap();
+            let mut data = [0; 3];
+            device.read_exact(&mut data).unwrap();
+            let pins = device.read_pins().unwrap();
+            [&data[..], &[pins]].concat()
+        }
+
+        let transport = MemoryTransport::default();
+        transport.push_response(&[1, 2, 3]);
+        transport.set_pin_state(0xa5);
+        let log = SharedBuffer::default();
+        let mut device = Builder::new()
+            .with_recording(log.clone())
+            .transport_open(transport)
+            .unwrap();
+        let recorded = session(&mut device);
+        assert_eq!(recorded, [1, 2, 3, 0xa5]);
+
+        let recording = Recording::read(&log.0.lock().unwrap()[..]).unwrap();
+        assert_eq!(recording.descriptor.product_id, 0x6010);
+        let replay = ReplayTransport::new(recording);
+        let mut device = Builder::new().transport_open(replay.clone()).unwrap();
+        assert_eq!(session(&mut device), recorded);
+        replay.finish().unwrap();
+
+        // Diverging traffic is an error.
+        let replay = ReplayTransport::new(Recording::read(&log.0.lock().unwrap()[..]).unwrap());
+        let mut device = Builder::new().transport_open(replay.clone()).unwrap();
+        device.set_bitmode(0x0b, BitMode::Mpsse).unwrap();
+        let err = device.set_latency_timer(16).unwrap_err();
+        assert!(matches!(
+            err,
+            FtdiError::Usb(e) if e.to_string()
+                == "replay diverged at event 1: expected ctrl_out 09 0001 0001, got ctrl_out 09 0010 0001"
+        ));
+        assert!(replay.finish().is_err());
+    }
+
+    #[test]
+    fn test_replay_status() {
+        // With CTS deasserted the status bytes are recorded as well.
+        let transport = MemoryTransport::default();
+        transport.set_status(0x22, 0x60);
+        transport.push_response(&[0x55]);
+        let log = SharedBuffer::default();
+        let mut device = Builder::new()
+            .with_recording(log.clone())
+            .transport_open(transport)
+            .unwrap();
+        device.read_exact(&mut [0; 1]).unwrap();
+        assert!(!device.modem_status().cts());
+
+        let recording = Recording::read(&log.0.lock().unwrap()[..]).unwrap();
+        assert_eq!(
+            recording.events.last().unwrap().1,
+            Event::BulkInRaw(vec![0x22, 0x60, 0x55])
+        );
+        let replay = ReplayTransport::new(recording);
+        let mut device = Builder::new().transport_open(replay.clone()).unwrap();
+        let mut data = [0; 1];
+        device.read_exact(&mut data).unwrap();
+        assert_eq!(data, [0x55]);
+        assert!(!device.modem_status().cts());
+        replay.finish().unwrap();
+    }
+
+    #[test]
+    fn test_replay_queued_read() {
+        fn session(device: &mut Device) -> Vec<u8> {
+            let mut data = vec![0; 10_000];
+            // The first read leaves the surplus of a transfer in flight in the read queue, which
+            // has to be replayed before the write.
+            device.read_exact(&mut data[..3000]).unwrap();
+            device.write_all(&[0x87]).unwrap();
+            device.read_exact(&mut data[3000..]).unwrap();
+            data
+        }
+
+        let transport = MemoryTransport::default();
+        let response: Vec<u8> = (0..10_000u32).map(|i| (i * 7) as u8).collect();
+        transport.push_response(&response);
+        let log = SharedBuffer::default();
+        let mut device = Builder::new()
+            .with_read_queue_depth(4)
+            .with_read_transfer_size(2048)
+            .with_recording(log.clone())
+            .transport_open(transport.clone())
+            .unwrap();
+        assert_eq!(session(&mut device), response);
+        assert_eq!(transport.bulk_reads(), 5);
+
+        let replay = ReplayTransport::new(Recording::read(&log.0.lock().unwrap()[..]).unwrap());
+        let mut device = Builder::new()
+            .with_read_queue_depth(4)
+            .with_read_transfer_size(2048)
+            .transport_open(replay.clone())
+            .unwrap();
+        assert_eq!(session(&mut device), response);
+        replay.finish().unwrap();
+    }
+}
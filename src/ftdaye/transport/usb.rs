@@ -0,0 +1,364 @@
+use std::time::{Duration, Instant};
+
+use async_io::Timer;
+use log::{debug, info, trace, warn};
+use nusb::transfer::{Direction, EndpointType, RequestBuffer};
+use nusb::DeviceInfo;
+
+use super::{DeviceDescriptor, TransferSink, TransferSource, Transport, TransportFuture};
+use crate::ftdaye::{error::FtdiError, Interface};
+use crate::usb_util::{self, with_timeout, InterfaceExt};
+use crate::{JtagProbeError, ProbeFilter, ProbeSelector};
+
+/// A [`Transport`] talking to a real device through `nusb`.
+pub struct NusbTransport {
+    /// USB device handle
+    handle: nusb::Interface,
+
+    descriptor: DeviceDescriptor,
+    max_packet_size: usize,
+
+    /// Finds the same probe again after a reconnect: by serial number, or by USB path if the
+    /// probe has none.
+    selector: ProbeSelector,
+}
+
+impl NusbTransport {
+    /// Opens `usb_device` and claims the USB interface backing the FTDI `interface`.
+    pub fn open(usb_device: DeviceInfo, interface: Interface) -> Result<Self, JtagProbeError> {
+        let handle = open_device(&usb_device)?;
+
+        Self::claim(&handle, &usb_device, interface)
+    }
+
+    /// Claims the USB interface backing the FTDI `interface` on an already opened device.
+    ///
+    /// Every interface of a multi-channel chip can be claimed from the same handle, see
+    /// [`DeviceHandle`](crate::ftdaye::DeviceHandle).
+    pub fn claim(
+        handle: &nusb::Device,
+        usb_device: &DeviceInfo,
+        interface: Interface,
+    ) -> Result<Self, JtagProbeError> {
+        let configs: Vec<_> = handle.configurations().collect();
+
+        let conf = &configs[0];
+        if configs.len() != 1 {
+            warn!("device has {} configurations, expected 1", configs.len());
+
+            if configs.len() > 1 {
+                let configuration = handle
+                    .active_configuration()
+                    .map_err(FtdiError::ActiveConfigurationError)?
+                    .configuration_value();
+
+                if configuration != conf.configuration_value() {
+                    handle
+                        .set_configuration(conf.configuration_value())
+                        .map_err(FtdiError::Usb)?;
+                }
+            }
+        }
+
+        debug!("scanning {} interfaces", conf.interfaces().count());
+        trace!("active configuration descriptor: {:#x?}", conf);
+
+        let mut usb_interface = None;
+
+        // Try to find the specified interface
+        for intf in conf.interfaces() {
+            trace!("interface #{} descriptors:", intf.interface_number());
+
+            for descr in intf.alt_settings() {
+                trace!("{:#x?}", descr);
+
+                let endpoints: Vec<_> = descr.endpoints().collect();
+                trace!("endpoint descriptors: {:#x?}", endpoints);
+
+                if endpoints
+                    .iter()
+                    .any(|ep| ep.transfer_type() != EndpointType::Bulk)
+                {
+                    warn!(
+                        "encountered non-bulk endpoints, skipping interface: {:#x?}",
+                        endpoints
+                    );
+                    continue;
+                }
+
+                let endpoint_count = endpoints.len();
+                let Ok::<[_; 2], _>([read_ep, write_ep]) = endpoints.try_into() else {
+                    warn!(
+                        "skipping interface with {} endpoints, expected 2",
+                        endpoint_count
+                    );
+                    continue;
+                };
+
+                let (read_ep, write_ep) = if read_ep.direction() == Direction::In {
+                    (read_ep, write_ep)
+                } else {
+                    (write_ep, read_ep)
+                };
+
+                if read_ep.address() != interface.read_ep()
+                    || write_ep.address() != interface.write_ep()
+                {
+                    debug!(
+                        "interface {} does not match requested interface {:?}",
+                        descr.interface_number(),
+                        interface
+                    );
+                    continue;
+                }
+
+                if let Some((intf, _)) = usb_interface {
+                    Err(FtdiError::Other(format!(
+                        "found multiple matching USB interfaces ({} and {})",
+                        intf,
+                        descr.interface_number()
+                    )))?
+                }
+
+                usb_interface = Some((descr.interface_number(), write_ep.max_packet_size()));
+                debug!("Interface is #{}", descr.interface_number());
+            }
+        }
+
+        let Some((intf, max_packet_size)) = usb_interface else {
+            Err(FtdiError::Other("device is not a FTDI device".to_string()))?
+        };
+
+        let handle = handle
+            .detach_and_claim_interface(intf)
+            .map_err(|e| open_error(e, "taking control over USB device"))?;
+
+        let descriptor = DeviceDescriptor::from(usb_device);
+        let filter = match &descriptor.serial_number {
+            Some(serial_number) => ProbeFilter::Id {
+                vendor_id: descriptor.vendor_id,
+                product_id: descriptor.product_id,
+                serial_number: Some(serial_number.clone()),
+            },
+            None => ProbeFilter::UsbPath(usb_util::usb_path(usb_device)),
+        };
+
+        Ok(Self {
+            handle,
+            descriptor,
+            max_packet_size,
+            selector: ProbeSelector {
+                filter,
+                interface: Some(interface),
+            },
+        })
+    }
+}
+
+/// Opens `usb_device` without claiming any interface.
+pub(crate) fn open_device(usb_device: &DeviceInfo) -> Result<nusb::Device, JtagProbeError> {
+    usb_device
+        .open()
+        .map_err(|e| open_error(e, "opening the USB device"))
+}
+
+fn open_error(e: std::io::Error, while_: &'static str) -> JtagProbeError {
+    let help = if cfg!(windows) {
+        "(this error may be caused by not having the WinUSB driver installed; use Zadig (https://zadig.akeo.ie/) to install it for the FTDI device; this will replace the FTDI driver)"
+    } else {
+        ""
+    };
+
+    JtagProbeError::Usb(std::io::Error::other(format!(
+        "error while {while_}: {e}{help}",
+    )))
+}
+
+impl Transport for NusbTransport {
+    fn descriptor(&self) -> &DeviceDescriptor {
+        &self.descriptor
+    }
+
+    fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    fn control_out(
+        &mut self,
+        request: u8,
+        value: u16,
+        index: u16,
+        timeout: Duration,
+    ) -> TransportFuture<'_, ()> {
+        Box::pin(async move {
+            self.handle
+                .control_out_vendor(request, value, index, timeout)
+                .await?;
+
+            debug!("Sent request {:02X}/{:04X}", request, value);
+
+            Ok(())
+        })
+    }
+
+    fn control_in<'a>(
+        &'a mut self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &'a mut [u8],
+        timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        Box::pin(
+            self.handle
+                .control_in_vendor(request, value, index, buf, timeout),
+        )
+    }
+
+    fn read_bulk<'a>(
+        &'a mut self,
+        endpoint: u8,
+        buf: &'a mut [u8],
+        timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        Box::pin(self.handle.read_bulk(endpoint, buf, timeout))
+    }
+
+    fn write_bulk<'a>(
+        &'a mut self,
+        endpoint: u8,
+        buf: &'a [u8],
+        timeout: Duration,
+    ) -> TransportFuture<'a, usize> {
+        Box::pin(self.handle.write_bulk(endpoint, buf, timeout))
+    }
+
+    fn clear_halt(&mut self, endpoint: u8) -> TransportFuture<'_, ()> {
+        Box::pin(std::future::ready(self.handle.clear_halt(endpoint)))
+    }
+
+    fn reconnect(&mut self, timeout: Duration) -> TransportFuture<'_, ()> {
+        Box::pin(async move {
+            let deadline = Instant::now() + timeout;
+            info!("waiting for {} to reconnect", self.selector);
+
+            loop {
+                let interface = self.selector.interface.unwrap_or(Interface::A);
+                let reopened = self
+                    .selector
+                    .resolve()
+                    .and_then(|usb_device| NusbTransport::open(usb_device, interface));
+
+                match reopened {
+                    Ok(transport) => {
+                        info!("reconnected to {}", self.selector);
+                        *self = transport;
+                        return Ok(());
+                    }
+                    Err(e) if Instant::now() >= deadline => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::TimedOut,
+                            format!("{} did not reconnect: {e}", self.selector),
+                        ));
+                    }
+                    Err(e) => debug!("reconnect attempt failed: {e}"),
+                }
+
+                Timer::after(Duration::from_millis(100)).await;
+            }
+        })
+    }
+
+    fn read_bulk_queued<'a>(
+        &'a mut self,
+        endpoint: u8,
+        transfer_size: usize,
+        depth: usize,
+        timeout: Duration,
+        sink: &'a mut TransferSink<'_>,
+    ) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            let mut queue = self.handle.bulk_in_queue(endpoint);
+            for _ in 0..depth.max(1) {
+                queue.submit(RequestBuffer::new(transfer_size));
+            }
+
+            let result = loop {
+                let completion =
+                    with_timeout(async { Ok(queue.next_complete().await) }, timeout).await;
+                let completion = match completion {
+                    Ok(completion) => completion,
+                    Err(e) => break Err(e),
+                };
+                if let Err(e) = completion.status {
+                    break Err(std::io::Error::other(e));
+                }
+
+                if !sink(&completion.data) {
+                    break Ok(());
+                }
+                queue.submit(RequestBuffer::reuse(completion.data, transfer_size));
+            };
+
+            // Cancelled transfers may still have received part of the data.
+            queue.cancel_all();
+            while queue.pending() > 0 {
+                let completion = queue.next_complete().await;
+                if result.is_ok() && !completion.data.is_empty() {
+                    sink(&completion.data);
+                }
+            }
+
+            result
+        })
+    }
+
+    fn write_bulk_queued<'a>(
+        &'a mut self,
+        endpoint: u8,
+        depth: usize,
+        timeout: Duration,
+        source: &'a mut TransferSource<'_>,
+    ) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            let mut queue = self.handle.bulk_out_queue(endpoint);
+            let mut exhausted = false;
+            let mut spare = vec![];
+
+            let result = loop {
+                // Keep the queue full until the source runs dry.
+                while !exhausted && queue.pending() < depth.max(1) {
+                    let mut buf = std::mem::take(&mut spare);
+                    buf.clear();
+                    source(&mut buf);
+                    if buf.is_empty() {
+                        exhausted = true;
+                    } else {
+                        queue.submit(buf);
+                    }
+                }
+                if queue.pending() == 0 {
+                    break Ok(());
+                }
+
+                let completion =
+                    with_timeout(async { Ok(queue.next_complete().await) }, timeout).await;
+                let completion = match completion {
+                    Ok(completion) => completion,
+                    Err(e) => break Err(e),
+                };
+                if let Err(e) = completion.status {
+                    break Err(std::io::Error::other(e));
+                }
+                spare = completion.data.reuse();
+            };
+
+            queue.cancel_all();
+            while queue.pending() > 0 {
+                let _ = queue.next_complete().await;
+            }
+
+            result
+        })
+    }
+}
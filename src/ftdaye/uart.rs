@@ -0,0 +1,255 @@
+//! Settings for channels used as a UART, and the baud rate divisor math of the chip.
+
+use super::{error::FtdiError, ChipType};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataBits {
+    Seven = 7,
+    Eight = 8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None = 0,
+    Odd = 1,
+    Even = 2,
+    Mark = 3,
+    Space = 4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One = 0,
+    OneAndHalf = 1,
+    Two = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    RtsCts,
+    DtrDsr,
+    /// Software flow control with the given XON and XOFF characters.
+    XonXoff {
+        xon: u8,
+        xoff: u8,
+    },
+}
+
+impl FlowControl {
+    /// Software flow control with the usual DC1/DC3 characters.
+    pub const XON_XOFF: Self = Self::XonXoff {
+        xon: 0x11,
+        xoff: 0x13,
+    };
+
+    /// Returns the value and the high byte of the index of the flow control request.
+    pub(crate) fn request(self) -> (u16, u16) {
+        match self {
+            Self::None => (0, 0),
+            Self::RtsCts => (0, 0x0100),
+            Self::DtrDsr => (0, 0x0200),
+            Self::XonXoff { xon, xoff } => (u16::from_le_bytes([xon, xoff]), 0x0400),
+        }
+    }
+}
+
+/// Returns the value of the set data request, without the break bit.
+pub(crate) fn line_properties(data_bits: DataBits, parity: Parity, stop_bits: StopBits) -> u16 {
+    data_bits as u16 | (parity as u16) << 8 | (stop_bits as u16) << 11
+}
+
+/// Computes the value and index of the set baud rate request for `interface_index`, along with
+/// the baud rate the chip will actually run at.
+///
+/// Fails if the closest rate the chip supports is more than 5% off.
+pub(crate) fn baud_rate_request(
+    chip_type: ChipType,
+    interface_index: u16,
+    baud_rate: u32,
+) -> Result<(u32, u16, u16), FtdiError> {
+    const H_CLK: u32 = 120_000_000;
+    const C_CLK: u32 = 48_000_000;
+
+    if baud_rate == 0 {
+        return Err(FtdiError::UnsupportedBaudRate(baud_rate));
+    }
+
+    let (actual, divisor) = if chip_type.is_high_speed() {
+        // The 12 MHz base clock can't divide down to the lowest rates.
+        if baud_rate as u64 * 10 > (H_CLK / 0x3fff) as u64 {
+            let (actual, divisor) = clock_divisor(baud_rate, H_CLK, 10);
+            (actual, divisor | 0x20000)
+        } else {
+            clock_divisor(baud_rate, C_CLK, 16)
+        }
+    } else if chip_type == ChipType::Am {
+        am_divisor(baud_rate)
+    } else {
+        clock_divisor(baud_rate, C_CLK, 16)
+    };
+
+    if actual.abs_diff(baud_rate) as u64 * 20 > baud_rate as u64 {
+        return Err(FtdiError::UnsupportedBaudRate(baud_rate));
+    }
+
+    let value = divisor as u16;
+    // Multi-channel and Hi-Speed chips take the interface in the low byte of the index, so the
+    // upper divisor bits move to the high byte.
+    let index = if chip_type.is_high_speed() || chip_type.interfaces().len() > 1 {
+        ((divisor >> 8) as u16 & 0xff00) | interface_index
+    } else {
+        (divisor >> 16) as u16
+    };
+
+    Ok((actual, value, index))
+}
+
+/// Encodes the sub-integer part of a divisor, in eighths.
+const FRAC_CODE: [u32; 8] = [0, 3, 2, 4, 1, 5, 6, 7];
+
+/// Finds the divisor of `clk / clk_div` closest to `baud_rate`, in eighths, for every chip but
+/// the AM.
+fn clock_divisor(baud_rate: u32, clk: u32, clk_div: u32) -> (u32, u32) {
+    // The smallest divisors are special encodings for the highest rates.
+    if baud_rate >= clk / clk_div {
+        (clk / clk_div, 0)
+    } else if baud_rate >= clk / (clk_div + clk_div / 2) {
+        (clk / (clk_div + clk_div / 2), 1)
+    } else if baud_rate >= clk / (2 * clk_div) {
+        (clk / (2 * clk_div), 2)
+    } else {
+        // Compute in sixteenths and round to eighths.
+        let base = clk as u64 * 16 / clk_div as u64;
+        let divisor = (base / baud_rate as u64) as u32;
+        let mut best_divisor = divisor.div_ceil(2);
+        if best_divisor > 0x20000 {
+            best_divisor = 0x1ffff;
+        }
+        let actual = ((base / best_divisor as u64) as u32).div_ceil(2);
+
+        (
+            actual,
+            (best_divisor >> 3) | FRAC_CODE[best_divisor as usize & 7] << 14,
+        )
+    }
+}
+
+/// The AM only supports fractions of 0, 1/4 and 1/2, and no divisors between 1 and 2.
+fn am_divisor(baud_rate: u32) -> (u32, u32) {
+    const AM_ADJUST_UP: [u32; 8] = [0, 0, 0, 1, 0, 3, 2, 1];
+    const AM_ADJUST_DOWN: [u32; 8] = [0, 0, 0, 1, 0, 1, 2, 3];
+    const CLK: u32 = 24_000_000;
+
+    let mut divisor = CLK / baud_rate;
+    divisor -= AM_ADJUST_DOWN[divisor as usize & 7];
+
+    // Try the rounded down divisor and the next one up.
+    let (actual, divisor) = (0..2)
+        .map(|i| {
+            let try_divisor = divisor + i;
+            let try_divisor = if try_divisor <= 8 {
+                8
+            } else if divisor < 16 {
+                16
+            } else {
+                (try_divisor + AM_ADJUST_UP[try_divisor as usize & 7]).min(0x1fff8)
+            };
+            ((CLK + try_divisor / 2) / try_divisor, try_divisor)
+        })
+        .min_by_key(|(actual, _)| actual.abs_diff(baud_rate))
+        .unwrap();
+
+    let encoded = match (divisor >> 3) | FRAC_CODE[divisor as usize & 7] << 14 {
+        // 3 Mbaud
+        1 => 0,
+        // 2 Mbaud, BM only
+        0x4001 => 1,
+        encoded => encoded,
+    };
+
+    (actual, encoded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::transport::MemoryTransport;
+    use crate::ftdaye::{Builder, Interface};
+
+    #[test]
+    fn test_baud_rate_request() {
+        let request = |chip_type, baud_rate| baud_rate_request(chip_type, 1, baud_rate).unwrap();
+
+        assert_eq!(request(ChipType::Bm, 115200), (115385, 0x001a, 0));
+        assert_eq!(request(ChipType::Am, 115200), (115385, 0x001a, 0));
+        assert_eq!(request(ChipType::R, 9600), (9600, 0x4138, 0));
+        assert_eq!(request(ChipType::R, 3_000_000), (3_000_000, 0, 0));
+        assert_eq!(request(ChipType::FT2232C, 115200), (115385, 0x001a, 1));
+        assert_eq!(request(ChipType::FT2232H, 115200), (115246, 0xc068, 0x0201));
+        assert_eq!(
+            request(ChipType::FT232H, 12_000_000),
+            (12_000_000, 0, 0x0201)
+        );
+        // Too slow for the 12 MHz clock, falls back to the 3 MHz one.
+        assert_eq!(request(ChipType::FT4232H, 300), (300, 0x2710, 1));
+
+        assert!(matches!(
+            baud_rate_request(ChipType::R, 1, 5_000_000),
+            Err(FtdiError::UnsupportedBaudRate(_))
+        ));
+        assert!(baud_rate_request(ChipType::R, 1, 0).is_err());
+    }
+
+    #[test]
+    fn test_line_properties() {
+        assert_eq!(
+            line_properties(DataBits::Eight, Parity::None, StopBits::One),
+            0x0008
+        );
+        assert_eq!(
+            line_properties(DataBits::Seven, Parity::Even, StopBits::Two),
+            0x1207
+        );
+        assert_eq!(FlowControl::XON_XOFF.request(), (0x1311, 0x0400));
+    }
+
+    #[test]
+    fn test_device_uart() {
+        let transport = MemoryTransport::default();
+        let mut device = Builder::new()
+            .with_interface(Interface::B)
+            .transport_open(transport.clone())
+            .unwrap();
+
+        assert_eq!(device.set_baud_rate(115200).unwrap(), 115246);
+        device
+            .set_line_properties(DataBits::Seven, Parity::Even, StopBits::Two)
+            .unwrap();
+        device.set_break(true).unwrap();
+        device.set_flow_control(FlowControl::RtsCts).unwrap();
+        device.set_dtr_rts(true, false).unwrap();
+        device.set_rts(true).unwrap();
+
+        assert_eq!(
+            transport
+                .take_control_requests()
+                .iter()
+                .map(|r| (r.request, r.value, r.index))
+                .collect::<Vec<_>>(),
+            [
+                (0x03, 0xc068, 0x0202),
+                (0x04, 0x1207, 2),
+                (0x04, 0x5207, 2),
+                (0x02, 0, 0x0102),
+                (0x01, 0x0301, 2),
+                (0x01, 0x0202, 2),
+            ]
+        );
+
+        assert!(matches!(
+            device.set_baud_rate(20_000_000),
+            Err(FtdiError::UnsupportedBaudRate(_))
+        ));
+    }
+}
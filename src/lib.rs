@@ -17,21 +17,24 @@
 //         ProbeCreationError, ProbeFactory, ScanChainElement, WireProtocol,
 //     },
 // };
+use async_io::block_on;
 use bitvec::prelude::*;
 use nusb::DeviceInfo;
-use std::{
-    io::{Read, Write},
-    time::{Duration, Instant},
-};
+use std::time::{Duration, Instant};
 
 pub mod command_compacter;
+pub mod emulator;
 pub mod ftdaye;
 use log::*;
+pub mod selector;
 pub mod usb_util;
 pub mod xilinx7;
 
 use command_compacter::Command;
+use ftdaye::mpsse::find_bad_command;
+use ftdaye::transport::DeviceDescriptor;
 pub use ftdaye::{error::FtdiError, ChipType};
+pub use selector::{ProbeFilter, ProbeSelector};
 
 #[derive(Debug)]
 pub struct JtagAdapter {
@@ -56,12 +59,27 @@ pub enum JtagProbeError {
     Other(String),
     /// A timeout occurred during probe operation.
     Timeout,
+    /// Invalid probe selector {0}
+    InvalidSelector(String),
+    /// No probe matches `{0}`
+    ProbeNotFound(String),
+    /// {count} probes match `{selector}`, add a serial number or USB path to pick one
+    AmbiguousProbe { selector: String, count: usize },
+    /// The probe was disconnected and reopened, pending commands and captured bits were lost
+    Reconnected,
+    /// A USB transfer failed and was recovered from, pending commands and captured bits were lost (TAP state lost: {state_lost})
+    Recovered { state_lost: bool },
+    /// The MPSSE rejected bad command {0:#04x}, the reply and captured bits were discarded
+    BadCommand(u8),
 }
 
 impl From<FtdiError> for JtagProbeError {
     fn from(err: FtdiError) -> Self {
         match err {
             FtdiError::Usb(error) => Self::Usb(error),
+            FtdiError::Reconnected => Self::Reconnected,
+            FtdiError::Recovered { state_lost } => Self::Recovered { state_lost },
+            FtdiError::BadCommand(opcode) => Self::BadCommand(opcode),
             ftdi_err => Self::FtdiError(ftdi_err),
         }
     }
@@ -74,7 +92,12 @@ impl JtagAdapter {
             .with_write_timeout(Duration::from_secs(5))
             .usb_open(usb_device)?;
 
-        let ftdi = FtdiProperties::try_from((ftdi, device.chip_type()))?;
+        Self::new(ftdi, device)
+    }
+
+    /// Wraps an already opened [`ftdaye::Device`], e.g. one running on a non-USB transport.
+    pub fn new(ftdi: FtdiDevice, device: ftdaye::Device) -> Result<Self, JtagProbeError> {
+        let ftdi = FtdiProperties::try_from((ftdi, device.chip_type(), device.interface()))?;
 
         Ok(Self {
             device,
@@ -88,14 +111,18 @@ impl JtagAdapter {
     }
 
     pub fn attach(&mut self) -> Result<(), FtdiError> {
-        self.device.usb_reset()?;
-        // 0x0B configures pins for JTAG
-        self.device.set_bitmode(0x0b, ftdaye::BitMode::Mpsse)?;
-        self.device.set_latency_timer(1)?;
-        self.device.usb_purge_buffers()?;
+        block_on(self.attach_async())
+    }
 
-        let mut junk = vec![];
-        let _ = self.device.read_to_end(&mut junk);
+    pub async fn attach_async(&mut self) -> Result<(), FtdiError> {
+        self.device.usb_reset_async().await?;
+        // 0x0B configures pins for JTAG
+        self.device
+            .set_bitmode_async(0x0b, ftdaye::BitMode::Mpsse)
+            .await?;
+        self.device.set_latency_timer_async(1).await?;
+        self.device.usb_purge_buffers_async().await?;
+        self.device.sync_mpsse_async().await?;
 
         let (output, direction) = self.pin_layout();
         debug!(
@@ -105,15 +132,41 @@ impl JtagAdapter {
             self.device.product_string()
         );
         debug!("pinmode {:x} {:x}", output, direction);
-        self.device.set_pins(output, direction)?;
+        self.device.set_pins_async(output, direction).await?;
 
-        self.apply_clock_speed(self.speed_khz)?;
+        self.apply_clock_speed_async(self.speed_khz).await?;
 
-        self.device.disable_loopback()?;
+        self.device.disable_loopback_async().await?;
 
         Ok(())
     }
 
+    /// Recovers from [`JtagProbeError::Reconnected`] and [`JtagProbeError::Recovered`] by
+    /// dropping everything queued for the failed transfer. If the probe lost its state, it is
+    /// attached again. Either way the caller has to restart its transaction, resetting the TAP
+    /// if its state was lost.
+    async fn recover(&mut self, err: JtagProbeError) -> JtagProbeError {
+        let state_lost = match err {
+            JtagProbeError::Reconnected => true,
+            JtagProbeError::Recovered { state_lost } => state_lost,
+            err => return err,
+        };
+
+        self.command = Command::default();
+        self.commands.clear();
+        self.in_bit_counts.clear();
+        self.in_bits.clear();
+
+        if state_lost {
+            info!("Probe lost its state, attaching again");
+            if let Err(e) = self.attach_async().await {
+                return e.into();
+            }
+        }
+
+        err
+    }
+
     pub fn pin_layout(&self) -> (u16, u16) {
         let (output, direction) = match (
             self.device.vendor_id(),
@@ -146,16 +199,20 @@ impl JtagAdapter {
     }
 
     pub fn apply_clock_speed(&mut self, speed_khz: u32) -> Result<u32, FtdiError> {
+        block_on(self.apply_clock_speed_async(speed_khz))
+    }
+
+    pub async fn apply_clock_speed_async(&mut self, speed_khz: u32) -> Result<u32, FtdiError> {
         // Disable divide-by-5 mode if available
         if self.ftdi.has_divide_by_5 {
-            self.device.disable_divide_by_5()?;
+            self.device.disable_divide_by_5_async().await?;
         } else {
             // Force enable divide-by-5 mode if not available or unknown
-            self.device.enable_divide_by_5()?;
+            self.device.enable_divide_by_5_async().await?;
         }
 
         // If `speed_khz` is not a divisor of the maximum supported speed, we need to round up
-        let is_exact = self.ftdi.max_clock % speed_khz == 0;
+        let is_exact = self.ftdi.max_clock.is_multiple_of(speed_khz);
 
         // If `speed_khz` is 0, use the maximum supported speed
         let divisor =
@@ -168,13 +225,19 @@ impl JtagAdapter {
             speed_khz, divisor, actual_speed
         );
 
-        self.device.configure_clock_divider(divisor as u16)?;
+        self.device
+            .configure_clock_divider_async(divisor as u16)
+            .await?;
 
         self.speed_khz = actual_speed;
         Ok(actual_speed)
     }
 
     pub fn read_response(&mut self) -> Result<(), JtagProbeError> {
+        block_on(self.read_response_async())
+    }
+
+    pub async fn read_response_async(&mut self) -> Result<(), JtagProbeError> {
         if self.in_bit_counts.is_empty() {
             return Ok(());
         }
@@ -184,16 +247,20 @@ impl JtagAdapter {
 
         let mut reply = Vec::with_capacity(self.in_bit_counts.len());
         while reply.len() < self.in_bit_counts.len() {
-            let read = self
-                .device
-                .read_to_end(&mut reply)
-                .map_err(FtdiError::from)?;
+            let read = match self.device.read_to_end_async(&mut reply).await {
+                Ok(read) => read,
+                Err(e) => return Err(self.recover(FtdiError::from(e).into()).await),
+            };
 
             if read > 0 {
                 t0 = Instant::now();
             }
 
             if t0.elapsed() > timeout {
+                if let Some(opcode) = find_bad_command(&reply) {
+                    self.in_bit_counts.clear();
+                    return Err(JtagProbeError::BadCommand(opcode));
+                }
                 warn!(
                     "Read {} bytes, expected {}",
                     reply.len(),
@@ -204,6 +271,10 @@ impl JtagAdapter {
         }
 
         if reply.len() != self.in_bit_counts.len() {
+            if let Some(opcode) = find_bad_command(&reply) {
+                self.in_bit_counts.clear();
+                return Err(JtagProbeError::BadCommand(opcode));
+            }
             return Err(JtagProbeError::Other(format!(
                 "Read more data than expected. Expected {} bytes, got {} bytes",
                 self.in_bit_counts.len(),
@@ -221,19 +292,27 @@ impl JtagAdapter {
     }
 
     pub fn flush(&mut self) -> Result<(), JtagProbeError> {
-        self.finalize_command()?;
-        self.send_buffer()?;
-        self.read_response()?;
+        block_on(self.flush_async())
+    }
+
+    pub async fn flush_async(&mut self) -> Result<(), JtagProbeError> {
+        self.finalize_command_async().await?;
+        self.send_buffer_async().await?;
+        self.read_response_async().await?;
 
         Ok(())
     }
 
     pub fn append_command(&mut self, command: Command) -> Result<(), JtagProbeError> {
+        block_on(self.append_command_async(command))
+    }
+
+    pub async fn append_command_async(&mut self, command: Command) -> Result<(), JtagProbeError> {
         trace!("Appending {:?}", command);
         // 1 byte is reserved for the send immediate command
         if self.commands.len() + command.len() + 1 >= self.ftdi.buffer_size {
-            self.send_buffer()?;
-            self.read_response()?;
+            self.send_buffer_async().await?;
+            self.read_response_async().await?;
         }
 
         command.add_captured_bits(&mut self.in_bit_counts);
@@ -243,22 +322,39 @@ impl JtagAdapter {
     }
 
     pub fn finalize_command(&mut self) -> Result<(), JtagProbeError> {
+        block_on(self.finalize_command_async())
+    }
+
+    pub async fn finalize_command_async(&mut self) -> Result<(), JtagProbeError> {
         if let Some(command) = self.command.take() {
-            self.append_command(command)?;
+            self.append_command_async(command).await?;
         }
 
         Ok(())
     }
 
     pub fn shift_bit(&mut self, tms: bool, tdi: bool, capture: bool) -> Result<(), JtagProbeError> {
+        block_on(self.shift_bit_async(tms, tdi, capture))
+    }
+
+    pub async fn shift_bit_async(
+        &mut self,
+        tms: bool,
+        tdi: bool,
+        capture: bool,
+    ) -> Result<(), JtagProbeError> {
         if let Some(command) = self.command.append_jtag_bit(tms, tdi, capture) {
-            self.append_command(command)?;
+            self.append_command_async(command).await?;
         }
 
         Ok(())
     }
 
     pub fn send_buffer(&mut self) -> Result<(), JtagProbeError> {
+        block_on(self.send_buffer_async())
+    }
+
+    pub async fn send_buffer_async(&mut self) -> Result<(), JtagProbeError> {
         if self.commands.is_empty() {
             return Ok(());
         }
@@ -268,11 +364,14 @@ impl JtagAdapter {
         // section 5.1
         self.commands.push(0x87);
 
-        trace!("Sending buffer: {:X?}", self.commands);
+        trace!(
+            "Sending buffer:\n{}",
+            ftdaye::disasm::Listing(&self.commands)
+        );
 
-        self.device
-            .write_all(&self.commands)
-            .map_err(FtdiError::from)?;
+        if let Err(e) = self.device.write_all_async(&self.commands).await {
+            return Err(self.recover(FtdiError::from(e).into()).await);
+        }
 
         self.commands.clear();
 
@@ -280,7 +379,11 @@ impl JtagAdapter {
     }
 
     pub fn read_captured_bits(&mut self) -> Result<BitVec<u8, Lsb0>, JtagProbeError> {
-        self.flush()?;
+        block_on(self.read_captured_bits_async())
+    }
+
+    pub async fn read_captured_bits_async(&mut self) -> Result<BitVec<u8, Lsb0>, JtagProbeError> {
+        self.flush_async().await?;
 
         Ok(std::mem::take(&mut self.in_bits))
     }
@@ -555,10 +658,12 @@ struct FtdiProperties {
     has_divide_by_5: bool,
 }
 
-impl TryFrom<(FtdiDevice, Option<ChipType>)> for FtdiProperties {
+impl TryFrom<(FtdiDevice, Option<ChipType>, ftdaye::Interface)> for FtdiProperties {
     type Error = FtdiError;
 
-    fn try_from((ftdi, chip_type): (FtdiDevice, Option<ChipType>)) -> Result<Self, Self::Error> {
+    fn try_from(
+        (ftdi, chip_type, interface): (FtdiDevice, Option<ChipType>, ftdaye::Interface),
+    ) -> Result<Self, Self::Error> {
         let chip_type = match chip_type {
             Some(ty) => ty,
             None => {
@@ -567,28 +672,27 @@ impl TryFrom<(FtdiDevice, Option<ChipType>)> for FtdiProperties {
             }
         };
 
+        let Some(buffer_size) = chip_type.mpsse_buffer_size() else {
+            warn!("Unsupported FTDI chip: {:?}", chip_type);
+            return Err(FtdiError::UnsupportedChipType(chip_type));
+        };
         let properties = match chip_type {
-            ChipType::FT2232H | ChipType::FT4232H => Self {
-                buffer_size: 4096,
-                max_clock: 30_000,
-                has_divide_by_5: true,
-            },
-            ChipType::FT232H => Self {
-                buffer_size: 1024,
-                max_clock: 30_000,
-                has_divide_by_5: true,
-            },
             ChipType::FT2232C => Self {
-                buffer_size: 128,
+                buffer_size,
                 max_clock: 6_000,
                 has_divide_by_5: false,
             },
-            not_mpsse => {
-                warn!("Unsupported FTDI chip: {:?}", not_mpsse);
-                return Err(FtdiError::UnsupportedChipType(not_mpsse));
-            }
+            _ => Self {
+                buffer_size,
+                max_clock: 30_000,
+                has_divide_by_5: true,
+            },
         };
 
+        if !chip_type.mpsse_interfaces().contains(&interface) {
+            return Err(FtdiError::NoMpsse(chip_type, interface));
+        }
+
         Ok(properties)
     }
 }
@@ -596,7 +700,6 @@ impl TryFrom<(FtdiDevice, Option<ChipType>)> for FtdiProperties {
 #[derive(Debug, Clone, Copy)]
 pub struct FtdiDevice {
     /// The (VID, PID) pair of this device.
-    #[allow(dead_code)]
     id: (u16, u16),
 
     /// FTDI chip type to use if the device is not recognized.
@@ -608,11 +711,35 @@ pub struct FtdiDevice {
     fallback_chip_type: ChipType,
 }
 
-// impl FtdiDevice {
-//     fn matches(&self, device: &DeviceInfo) -> bool {
-//         self.id == (device.vendor_id(), device.product_id())
-//     }
-// }
+impl FtdiDevice {
+    fn matches(&self, descriptor: &DeviceDescriptor) -> bool {
+        self.id == (descriptor.vendor_id, descriptor.product_id)
+    }
+
+    /// Finds the entry of [`FTDI_COMPAT_DEVICES`] matching the VID/PID of `descriptor`.
+    pub fn find(descriptor: &DeviceDescriptor) -> Option<Self> {
+        FTDI_COMPAT_DEVICES
+            .iter()
+            .find(|ftdi| ftdi.matches(descriptor))
+            .copied()
+    }
+
+    /// The (VID, PID) pair of this device.
+    pub fn id(&self) -> (u16, u16) {
+        self.id
+    }
+
+    /// Detects the chip type of `descriptor` from its `bcdDevice`, falling back to the chip
+    /// type this VID/PID pair usually has.
+    ///
+    /// Returns whether the chip type was detected along with it.
+    pub fn chip_type(&self, descriptor: &DeviceDescriptor) -> (ChipType, bool) {
+        match descriptor.chip_type() {
+            Some(chip_type) => (chip_type, true),
+            None => (self.fallback_chip_type, false),
+        }
+    }
+}
 
 /// Known FTDI device variants.
 pub static FTDI_COMPAT_DEVICES: &[FtdiDevice] = &[
@@ -634,6 +761,41 @@ pub static FTDI_COMPAT_DEVICES: &[FtdiDevice] = &[
         id: (0x0403, 0x6014),
         fallback_chip_type: ChipType::FT232H,
     },
+    // FTDI Ltd. FT4232HA Quad HS USB-UART/FIFO IC, automotive grade
+    FtdiDevice {
+        id: (0x0403, 0x6048),
+        fallback_chip_type: ChipType::FT4232HA,
+    },
+    // FTDI Ltd. FT2233HP Dual HS USB-UART/FIFO IC with Type-C PD
+    FtdiDevice {
+        id: (0x0403, 0x6040),
+        fallback_chip_type: ChipType::FT2233HP,
+    },
+    // FTDI Ltd. FT4233HP Quad HS USB-UART/FIFO IC with Type-C PD
+    FtdiDevice {
+        id: (0x0403, 0x6041),
+        fallback_chip_type: ChipType::FT4233HP,
+    },
+    // FTDI Ltd. FT2232HP Dual HS USB-UART/FIFO IC with PD
+    FtdiDevice {
+        id: (0x0403, 0x6042),
+        fallback_chip_type: ChipType::FT2232HP,
+    },
+    // FTDI Ltd. FT4232HP Quad HS USB-UART/FIFO IC with PD
+    FtdiDevice {
+        id: (0x0403, 0x6043),
+        fallback_chip_type: ChipType::FT4232HP,
+    },
+    // FTDI Ltd. FT233HP Single HS USB-UART/FIFO IC with Type-C PD
+    FtdiDevice {
+        id: (0x0403, 0x6044),
+        fallback_chip_type: ChipType::FT233HP,
+    },
+    // FTDI Ltd. FT232HP Single HS USB-UART/FIFO IC with PD
+    FtdiDevice {
+        id: (0x0403, 0x6045),
+        fallback_chip_type: ChipType::FT232HP,
+    },
     //
     // --- Third-party VID/PID pairs ---
     //
@@ -659,24 +821,267 @@ pub static FTDI_COMPAT_DEVICES: &[FtdiDevice] = &[
     },
 ];
 
-// fn get_device_info(device: &DeviceInfo) -> Option<DebugProbeInfo> {
-//     FTDI_COMPAT_DEVICES.iter().find_map(|ftdi| {
-//         ftdi.matches(device).then(|| DebugProbeInfo {
-//             identifier: device.product_string().unwrap_or("FTDI").to_string(),
-//             vendor_id: device.vendor_id(),
-//             product_id: device.product_id(),
-//             serial_number: device.serial_number().map(|s| s.to_string()),
-//             probe_factory: &FtdiProbeFactory,
-//             hid_interface: None,
-//         })
-//     })
-// }
+/// A connected probe matching one of [`FTDI_COMPAT_DEVICES`].
+#[derive(Debug, Clone)]
+pub struct ProbeInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial_number: Option<String>,
+    pub product_string: Option<String>,
+    /// The bus number and port chain the probe is connected to, e.g. `1-4.2`.
+    pub usb_path: String,
+    /// The chip type, detected from `bcdDevice` or the fallback of the matching
+    /// [`FtdiDevice`].
+    pub chip_type: ChipType,
+    /// Whether `chip_type` was detected rather than assumed.
+    pub chip_type_detected: bool,
+    /// The interfaces the chip provides.
+    pub interfaces: &'static [ftdaye::Interface],
+    pub ftdi: FtdiDevice,
+    device_info: DeviceInfo,
+}
 
-// fn list_ftdi_devices() -> Vec<DebugProbeInfo> {
-//     match nusb::list_devices() {
-//         Ok(devices) => devices
-//             .filter_map(|device| get_device_info(&device))
-//             .collect(),
-//         Err(_) => vec![],
-//     }
-// }
+impl ftdaye::UsbTarget for &ProbeInfo {
+    fn resolve(self) -> Result<(DeviceInfo, Option<ftdaye::Interface>), JtagProbeError> {
+        Ok((self.device_info.clone(), None))
+    }
+}
+
+impl ProbeInfo {
+    /// Returns `None` if `device_info` is not in [`FTDI_COMPAT_DEVICES`].
+    pub fn new(device_info: DeviceInfo) -> Option<Self> {
+        let descriptor = DeviceDescriptor::from(&device_info);
+        let ftdi = FtdiDevice::find(&descriptor)?;
+        let (chip_type, chip_type_detected) = ftdi.chip_type(&descriptor);
+
+        Some(Self {
+            vendor_id: descriptor.vendor_id,
+            product_id: descriptor.product_id,
+            serial_number: descriptor.serial_number,
+            product_string: descriptor.product_string,
+            usb_path: usb_util::usb_path(&device_info),
+            chip_type,
+            chip_type_detected,
+            interfaces: chip_type.interfaces(),
+            ftdi,
+            device_info,
+        })
+    }
+
+    pub fn device_info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    /// Opens the probe as a [`JtagAdapter`] on interface A.
+    pub fn open(&self) -> Result<JtagAdapter, JtagProbeError> {
+        JtagAdapter::open(self.ftdi, self.device_info.clone())
+    }
+}
+
+/// Lists the connected probes matching [`FTDI_COMPAT_DEVICES`].
+pub fn list_probes() -> Result<Vec<ProbeInfo>, JtagProbeError> {
+    Ok(nusb::list_devices()
+        .map_err(JtagProbeError::Usb)?
+        .filter_map(ProbeInfo::new)
+        .collect())
+}
+
+/// A change reported by [`ProbeWatcher`].
+#[derive(Debug, Clone)]
+pub enum ProbeEvent {
+    Connected(ProbeInfo),
+    Disconnected(ProbeInfo),
+}
+
+/// Watches for probes matching [`FTDI_COMPAT_DEVICES`] being plugged in or removed.
+pub struct ProbeWatcher {
+    watch: nusb::hotplug::HotplugWatch,
+    connected: Vec<ProbeInfo>,
+}
+
+impl ProbeWatcher {
+    /// Starts watching. Probes that are already connected are returned by [`Self::probes`],
+    /// not reported as events.
+    pub fn new() -> Result<Self, JtagProbeError> {
+        // Start watching first so no probe falls between the listing and the watch.
+        let watch = nusb::watch_devices().map_err(JtagProbeError::Usb)?;
+
+        Ok(Self {
+            watch,
+            connected: list_probes()?,
+        })
+    }
+
+    /// Returns the probes that are currently connected.
+    pub fn probes(&self) -> &[ProbeInfo] {
+        &self.connected
+    }
+
+    /// Blocks until a probe is connected or disconnected. Returns `None` if the operating system
+    /// stopped reporting hotplug events.
+    pub fn wait(&mut self) -> Option<ProbeEvent> {
+        block_on(self.wait_async())
+    }
+
+    pub async fn wait_async(&mut self) -> Option<ProbeEvent> {
+        use futures_lite::StreamExt;
+        use nusb::hotplug::HotplugEvent;
+
+        loop {
+            let event = self.watch.next().await?;
+
+            match event {
+                HotplugEvent::Connected(device_info) => {
+                    let id = device_info.id();
+                    if self.connected.iter().any(|p| p.device_info.id() == id) {
+                        continue;
+                    }
+                    if let Some(probe) = ProbeInfo::new(device_info) {
+                        self.connected.push(probe.clone());
+                        return Some(ProbeEvent::Connected(probe));
+                    }
+                }
+                HotplugEvent::Disconnected(id) => {
+                    if let Some(index) =
+                        self.connected.iter().position(|p| p.device_info.id() == id)
+                    {
+                        return Some(ProbeEvent::Disconnected(self.connected.remove(index)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ftdaye::transport::{descriptor, MemoryTransport};
+    use crate::ftdaye::Builder;
+
+    #[test]
+    fn test_compat_device_chip_type() {
+        let ftdi = FtdiDevice::find(&descriptor(0x6010, 0x700)).unwrap();
+        assert_eq!(ftdi.id(), (0x0403, 0x6010));
+        assert_eq!(
+            ftdi.chip_type(&descriptor(0x6010, 0x700)),
+            (ChipType::FT2232H, true)
+        );
+        // Clones with a bogus bcdDevice fall back to the usual chip type.
+        assert_eq!(
+            ftdi.chip_type(&descriptor(0x6010, 0x1234)),
+            (ChipType::FT2232C, false)
+        );
+
+        assert!(FtdiDevice::find(&descriptor(0x6001, 0x600)).is_none());
+
+        let ftdi = FtdiDevice::find(&descriptor(0x6048, 0x3600)).unwrap();
+        assert_eq!(
+            ftdi.chip_type(&descriptor(0x6048, 0x3600)),
+            (ChipType::FT4232HA, true)
+        );
+        assert_eq!(
+            descriptor(0x6045, 0x3300).chip_type(),
+            Some(ChipType::FT232HP)
+        );
+    }
+
+    #[test]
+    fn test_properties() {
+        use ftdaye::Interface;
+
+        let ftdi = FtdiDevice::find(&descriptor(0x6011, 0x800)).unwrap();
+        let properties =
+            |chip_type, interface| FtdiProperties::try_from((ftdi, Some(chip_type), interface));
+
+        assert_eq!(
+            properties(ChipType::FT4232H, Interface::B)
+                .unwrap()
+                .buffer_size,
+            2048
+        );
+        assert_eq!(
+            properties(ChipType::FT2232HP, Interface::B)
+                .unwrap()
+                .buffer_size,
+            4096
+        );
+        assert!(matches!(
+            properties(ChipType::FT4232H, Interface::C),
+            Err(FtdiError::NoMpsse(ChipType::FT4232H, Interface::C))
+        ));
+        assert!(matches!(
+            properties(ChipType::FT4232HA, Interface::D),
+            Err(FtdiError::NoMpsse(..))
+        ));
+        assert!(matches!(
+            properties(ChipType::FT2232C, Interface::B),
+            Err(FtdiError::NoMpsse(..))
+        ));
+        for uart_only in [ChipType::R, ChipType::FT230X] {
+            assert!(matches!(
+                properties(uart_only, Interface::A),
+                Err(FtdiError::UnsupportedChipType(_))
+            ));
+        }
+        // Unknown chips are assumed to be the fallback.
+        assert!(FtdiProperties::try_from((ftdi, None, Interface::B)).is_ok());
+    }
+
+    #[test]
+    fn test_jtag_adapter_reconnect() {
+        let transport = MemoryTransport::default();
+        let device = Builder::new()
+            .with_reconnect(Duration::from_secs(1))
+            .transport_open(transport.clone())
+            .unwrap();
+        let mut adapter = JtagAdapter::new(FTDI_COMPAT_DEVICES[0], device).unwrap();
+        adapter.attach().unwrap();
+        let attach = transport.take_written();
+
+        adapter.shift_bit(false, true, true).unwrap();
+        transport.unplug();
+        assert!(matches!(
+            adapter.read_captured_bits(),
+            Err(JtagProbeError::Reconnected)
+        ));
+        // The pin and clock setup is replayed, the lost command is not.
+        assert_eq!(transport.take_written(), attach);
+
+        for _ in 0..8 {
+            adapter.shift_bit(false, true, true).unwrap();
+        }
+        transport.push_response(&[0xa5]);
+        assert_eq!(adapter.read_captured_bits().unwrap().load_le::<u8>(), 0xa5);
+    }
+
+    #[test]
+    fn test_jtag_adapter_recovery() {
+        let transport = MemoryTransport::default();
+        let device = Builder::new()
+            .with_recovery(1)
+            .transport_open(transport.clone())
+            .unwrap();
+        let mut adapter = JtagAdapter::new(FTDI_COMPAT_DEVICES[0], device).unwrap();
+        adapter.attach().unwrap();
+        let attach = transport.take_written();
+
+        adapter.shift_bit(false, true, true).unwrap();
+        transport.stall(1);
+        assert!(matches!(
+            adapter.read_captured_bits(),
+            Err(JtagProbeError::Recovered { state_lost: true })
+        ));
+        // After the resync the pin and clock setup is replayed.
+        assert_eq!(
+            transport.take_written(),
+            [&[0xaa, 0xab][..], &attach].concat()
+        );
+
+        for _ in 0..8 {
+            adapter.shift_bit(false, true, true).unwrap();
+        }
+        transport.push_response(&[0xa5]);
+        assert_eq!(adapter.read_captured_bits().unwrap().load_le::<u8>(), 0xa5);
+    }
+}
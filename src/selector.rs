@@ -0,0 +1,303 @@
+//! Selecting one probe among several connected ones.
+
+use std::fmt;
+use std::str::FromStr;
+
+use nusb::DeviceInfo;
+
+use crate::ftdaye::transport::DeviceDescriptor;
+use crate::ftdaye::{Interface, UsbTarget};
+use crate::{usb_util, JtagProbeError, ProbeInfo};
+
+/// Identifies a single probe, optionally along with the interface to use.
+///
+/// Parsed from one of:
+/// - `VID:PID[:SERIAL]`, with VID and PID in hex, e.g. `0403:6010:210319B0C0DE`
+/// - `usb-path=PATH`, e.g. `usb-path=1-4.2`, see [`ProbeInfo::usb_path`]
+/// - `product="PRODUCT"`, matching the USB product string exactly
+///
+/// Each form accepts an interface suffix such as `:B`. A single letter from A to D at the end
+/// is always taken as the interface, never as a serial number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProbeSelector {
+    pub filter: ProbeFilter,
+    /// The interface to open, overriding the one configured on the
+    /// [`Builder`](crate::ftdaye::Builder).
+    pub interface: Option<Interface>,
+}
+
+/// The part of a [`ProbeSelector`] that picks the USB device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProbeFilter {
+    Id {
+        vendor_id: u16,
+        product_id: u16,
+        serial_number: Option<String>,
+    },
+    UsbPath(String),
+    Product(String),
+}
+
+impl ProbeSelector {
+    /// Returns whether the device with the given descriptor, connected at `usb_path`, matches.
+    pub fn matches(&self, descriptor: &DeviceDescriptor, usb_path: &str) -> bool {
+        match &self.filter {
+            ProbeFilter::Id {
+                vendor_id,
+                product_id,
+                serial_number,
+            } => {
+                (descriptor.vendor_id, descriptor.product_id) == (*vendor_id, *product_id)
+                    && (serial_number.is_none() || descriptor.serial_number == *serial_number)
+            }
+            ProbeFilter::UsbPath(path) => path == usb_path,
+            ProbeFilter::Product(product) => {
+                descriptor.product_string.as_deref() == Some(product.as_str())
+            }
+        }
+    }
+
+    /// Finds the one connected USB device matching this selector.
+    ///
+    /// Fails if no device or more than one device matches.
+    pub fn resolve(&self) -> Result<DeviceInfo, JtagProbeError> {
+        let mut matching = nusb::list_devices()
+            .map_err(JtagProbeError::Usb)?
+            .filter(|info| self.matches(&DeviceDescriptor::from(info), &usb_util::usb_path(info)))
+            .collect::<Vec<_>>();
+
+        match matching.len() {
+            0 => Err(JtagProbeError::ProbeNotFound(self.to_string())),
+            1 => Ok(matching.remove(0)),
+            count => Err(JtagProbeError::AmbiguousProbe {
+                selector: self.to_string(),
+                count,
+            }),
+        }
+    }
+
+    /// Returns the [`ProbeInfo`] of the probe this selector resolves to.
+    pub fn probe_info(&self) -> Result<ProbeInfo, JtagProbeError> {
+        let info = self.resolve()?;
+        ProbeInfo::new(info)
+            .ok_or_else(|| JtagProbeError::Other(format!("{self} is not a known FTDI based probe")))
+    }
+}
+
+impl UsbTarget for &ProbeSelector {
+    fn resolve(self) -> Result<(DeviceInfo, Option<Interface>), JtagProbeError> {
+        Ok((ProbeSelector::resolve(self)?, self.interface))
+    }
+}
+
+impl UsbTarget for ProbeSelector {
+    fn resolve(self) -> Result<(DeviceInfo, Option<Interface>), JtagProbeError> {
+        UsbTarget::resolve(&self)
+    }
+}
+
+fn parse_interface(s: &str) -> Option<Interface> {
+    match s {
+        "A" | "a" => Some(Interface::A),
+        "B" | "b" => Some(Interface::B),
+        "C" | "c" => Some(Interface::C),
+        "D" | "d" => Some(Interface::D),
+        _ => None,
+    }
+}
+
+/// Splits a trailing `:A`..`:D` off `s`.
+fn split_interface(s: &str) -> (&str, Option<Interface>) {
+    match s.rsplit_once(':') {
+        Some((rest, suffix)) => match parse_interface(suffix) {
+            Some(interface) => (rest, Some(interface)),
+            None => (s, None),
+        },
+        None => (s, None),
+    }
+}
+
+impl FromStr for ProbeSelector {
+    type Err = JtagProbeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| JtagProbeError::InvalidSelector(format!("{s:?}: {reason}"));
+
+        let (filter, interface) = if let Some(path) = s.strip_prefix("usb-path=") {
+            let (path, interface) = split_interface(path);
+            if path.is_empty() {
+                return Err(invalid("empty USB path"));
+            }
+            (ProbeFilter::UsbPath(path.to_string()), interface)
+        } else if let Some(product) = s.strip_prefix("product=") {
+            let (product, interface) = match product.strip_prefix('"') {
+                Some(quoted) => {
+                    let (product, suffix) = quoted
+                        .split_once('"')
+                        .ok_or_else(|| invalid("unterminated quote"))?;
+                    let interface = match suffix {
+                        "" => None,
+                        _ => Some(
+                            suffix
+                                .strip_prefix(':')
+                                .and_then(parse_interface)
+                                .ok_or_else(|| {
+                                    invalid("expected an interface after the product")
+                                })?,
+                        ),
+                    };
+                    (product, interface)
+                }
+                None => split_interface(product),
+            };
+            (ProbeFilter::Product(product.to_string()), interface)
+        } else {
+            let (id, interface) = split_interface(s);
+            let mut parts = id.splitn(3, ':');
+            let mut hex = |what| {
+                let part = parts.next().unwrap_or_default();
+                u16::from_str_radix(part.trim_start_matches("0x"), 16)
+                    .map_err(|_| invalid(&format!("invalid {what} {part:?}")))
+            };
+            let vendor_id = hex("VID")?;
+            let product_id = hex("PID")?;
+            let serial_number = parts.next().map(str::to_string);
+            if serial_number.as_deref() == Some("") {
+                return Err(invalid("empty serial number"));
+            }
+
+            (
+                ProbeFilter::Id {
+                    vendor_id,
+                    product_id,
+                    serial_number,
+                },
+                interface,
+            )
+        };
+
+        Ok(Self { filter, interface })
+    }
+}
+
+impl fmt::Display for ProbeSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.filter {
+            ProbeFilter::Id {
+                vendor_id,
+                product_id,
+                serial_number,
+            } => {
+                write!(f, "{vendor_id:04x}:{product_id:04x}")?;
+                if let Some(serial_number) = serial_number {
+                    write!(f, ":{serial_number}")?;
+                }
+            }
+            ProbeFilter::UsbPath(path) => write!(f, "usb-path={path}")?,
+            ProbeFilter::Product(product) => write!(f, "product=\"{product}\"")?,
+        }
+
+        if let Some(interface) = self.interface {
+            write!(f, ":{interface:?}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> ProbeSelector {
+        let selector: ProbeSelector = s.parse().unwrap();
+        // Display round-trips through FromStr.
+        assert_eq!(
+            selector.to_string().parse::<ProbeSelector>().unwrap(),
+            selector
+        );
+        selector
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("0403:6010"),
+            ProbeSelector {
+                filter: ProbeFilter::Id {
+                    vendor_id: 0x0403,
+                    product_id: 0x6010,
+                    serial_number: None
+                },
+                interface: None,
+            }
+        );
+        assert_eq!(
+            parse("0x0403:0x6010:210319B0C0DE:B"),
+            ProbeSelector {
+                filter: ProbeFilter::Id {
+                    vendor_id: 0x0403,
+                    product_id: 0x6010,
+                    serial_number: Some("210319B0C0DE".to_string())
+                },
+                interface: Some(Interface::B),
+            }
+        );
+        assert_eq!(
+            parse("usb-path=1-4.2:a"),
+            ProbeSelector {
+                filter: ProbeFilter::UsbPath("1-4.2".to_string()),
+                interface: Some(Interface::A),
+            }
+        );
+        assert_eq!(
+            parse("product=\"Digilent USB Device\":B"),
+            ProbeSelector {
+                filter: ProbeFilter::Product("Digilent USB Device".to_string()),
+                interface: Some(Interface::B),
+            }
+        );
+        assert_eq!(
+            parse("product=\"A:B\"").filter,
+            ProbeFilter::Product("A:B".to_string())
+        );
+
+        for invalid in [
+            "",
+            "0403",
+            "0403:xyz",
+            "0403:6010:",
+            "usb-path=",
+            "product=\"Digilent",
+            "product=\"Digilent\":E",
+        ] {
+            assert!(
+                matches!(
+                    invalid.parse::<ProbeSelector>(),
+                    Err(JtagProbeError::InvalidSelector(_))
+                ),
+                "{invalid:?} should not parse"
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let descriptor = DeviceDescriptor {
+            vendor_id: 0x0403,
+            product_id: 0x6010,
+            device_version: 0x700,
+            serial_number: Some("210319B0C0DE".to_string()),
+            product_string: Some("Digilent USB Device".to_string()),
+        };
+
+        assert!(parse("0403:6010").matches(&descriptor, "1-4.2"));
+        assert!(parse("0403:6010:210319B0C0DE").matches(&descriptor, "1-4.2"));
+        assert!(!parse("0403:6010:210319B0C0DF").matches(&descriptor, "1-4.2"));
+        assert!(!parse("0403:6014").matches(&descriptor, "1-4.2"));
+        assert!(parse("usb-path=1-4.2").matches(&descriptor, "1-4.2"));
+        assert!(!parse("usb-path=1-4").matches(&descriptor, "1-4.2"));
+        assert!(parse("product=\"Digilent USB Device\"").matches(&descriptor, "1-4.2"));
+        assert!(!parse("product=Digilent").matches(&descriptor, "1-4.2"));
+    }
+}
@@ -1,16 +1,112 @@
-use async_io::{block_on, Timer};
+use async_io::Timer;
 use futures_lite::FutureExt;
-use nusb::{transfer::RequestBuffer, Interface};
-use std::{io, time::Duration};
+use nusb::{
+    transfer::{ControlIn, ControlOut, ControlType, Recipient, RequestBuffer, TransferError},
+    Interface,
+};
+use std::{future::Future, io, time::Duration};
+
+/// Returns the bus number and port chain of `info`, e.g. `1-4.2`, like Linux names devices.
+///
+/// Windows only exposes the port on the parent hub, so the chain has a single port there.
+pub fn usb_path(info: &nusb::DeviceInfo) -> String {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Some(name) = info.sysfs_path().file_name() {
+        return name.to_string_lossy().into_owned();
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        // The location ID holds the bus number in its top byte, then one nibble per port.
+        let location = info.location_id();
+        let ports: Vec<String> = (0..6)
+            .map(|i| (location >> (20 - 4 * i)) & 0xf)
+            .take_while(|&port| port != 0)
+            .map(|port| port.to_string())
+            .collect();
+        return format!("{}-{}", location >> 24, ports.join("."));
+    }
+
+    #[cfg(target_os = "windows")]
+    return format!("{}-{}", info.bus_number(), info.port_number());
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    format!("{}-{}", info.bus_number(), info.device_address())
+}
+
+/// Returns whether `err` means the device is gone, e.g. because it was unplugged.
+pub fn is_disconnect(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::NotConnected
+        || matches!(
+            err.get_ref()
+                .and_then(|e| e.downcast_ref::<TransferError>()),
+            Some(TransferError::Disconnected)
+        )
+}
+
+/// Returns whether `err` may go away by retrying or recovering: a stall, a timeout or a bus
+/// fault.
+pub fn is_transient(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::TimedOut
+        || matches!(
+            err.get_ref()
+                .and_then(|e| e.downcast_ref::<TransferError>()),
+            Some(TransferError::Stall | TransferError::Fault | TransferError::Unknown)
+        )
+}
 
 pub trait InterfaceExt {
-    fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> io::Result<usize>;
-    fn write_bulk(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> io::Result<usize>;
+    fn read_bulk<'a>(
+        &'a self,
+        endpoint: u8,
+        buf: &'a mut [u8],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<usize>> + Send + 'a;
+    fn write_bulk<'a>(
+        &'a self,
+        endpoint: u8,
+        buf: &'a [u8],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<usize>> + Send + 'a;
+    fn control_out_vendor(
+        &self,
+        request: u8,
+        value: u16,
+        index: u16,
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<()>> + Send + '_;
+    fn control_in_vendor<'a>(
+        &'a self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &'a mut [u8],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<usize>> + Send + 'a;
+}
+
+/// Fails `fut` with [`io::ErrorKind::TimedOut`] if it doesn't complete within `timeout`.
+///
+/// Dropping a pending nusb transfer cancels it, so nothing is left in flight on timeout.
+pub async fn with_timeout<T>(
+    fut: impl Future<Output = io::Result<T>>,
+    timeout: Duration,
+) -> io::Result<T> {
+    fut.or(async {
+        Timer::after(timeout).await;
+        Err(std::io::ErrorKind::TimedOut.into())
+    })
+    .await
 }
 
 impl InterfaceExt for Interface {
-    fn write_bulk(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> io::Result<usize> {
-        let fut = async {
+    fn write_bulk<'a>(
+        &'a self,
+        endpoint: u8,
+        buf: &'a [u8],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<usize>> + Send + 'a {
+        let fut = async move {
             let comp = self.bulk_out(endpoint, buf.to_vec()).await;
             comp.status.map_err(io::Error::other)?;
 
@@ -18,14 +114,16 @@ impl InterfaceExt for Interface {
             Ok(n)
         };
 
-        block_on(fut.or(async {
-            Timer::after(timeout).await;
-            Err(std::io::ErrorKind::TimedOut.into())
-        }))
+        with_timeout(fut, timeout)
     }
 
-    fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
-        let fut = async {
+    fn read_bulk<'a>(
+        &'a self,
+        endpoint: u8,
+        buf: &'a mut [u8],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<usize>> + Send + 'a {
+        let fut = async move {
             let comp = self.bulk_in(endpoint, RequestBuffer::new(buf.len())).await;
             comp.status.map_err(io::Error::other)?;
 
@@ -34,9 +132,61 @@ impl InterfaceExt for Interface {
             Ok(n)
         };
 
-        block_on(fut.or(async {
-            Timer::after(timeout).await;
-            Err(std::io::ErrorKind::TimedOut.into())
-        }))
+        with_timeout(fut, timeout)
+    }
+
+    fn control_out_vendor(
+        &self,
+        request: u8,
+        value: u16,
+        index: u16,
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<()>> + Send + '_ {
+        let fut = async move {
+            let comp = self
+                .control_out(ControlOut {
+                    control_type: ControlType::Vendor,
+                    recipient: Recipient::Device,
+                    request,
+                    value,
+                    index,
+                    data: &[],
+                })
+                .await;
+            comp.status.map_err(io::Error::other)?;
+
+            Ok(())
+        };
+
+        with_timeout(fut, timeout)
+    }
+
+    fn control_in_vendor<'a>(
+        &'a self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &'a mut [u8],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<usize>> + Send + 'a {
+        let fut = async move {
+            let comp = self
+                .control_in(ControlIn {
+                    control_type: ControlType::Vendor,
+                    recipient: Recipient::Device,
+                    request,
+                    value,
+                    index,
+                    length: buf.len() as u16,
+                })
+                .await;
+            comp.status.map_err(io::Error::other)?;
+
+            let n = comp.data.len().min(buf.len());
+            buf[..n].copy_from_slice(&comp.data[..n]);
+            Ok(n)
+        };
+
+        with_timeout(fut, timeout)
     }
 }